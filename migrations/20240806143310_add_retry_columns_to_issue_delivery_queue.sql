@@ -0,0 +1,6 @@
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_retries SMALLINT NOT NULL DEFAULT 0,
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN last_error TEXT NULL,
+    -- Set once a delivery has exhausted its attempts; the worker skips these rows
+    ADD COLUMN dead_lettered_at timestamptz NULL;
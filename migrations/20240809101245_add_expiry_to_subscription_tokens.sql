@@ -0,0 +1,10 @@
+BEGIN;
+    ALTER TABLE subscription_tokens
+        ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+        ADD COLUMN expires_at timestamptz NULL;
+    -- Backfill with the default time-to-live
+    UPDATE subscription_tokens
+        SET expires_at = created_at + interval '24 hours'
+        WHERE expires_at IS NULL;
+    ALTER TABLE subscription_tokens ALTER COLUMN expires_at SET NOT NULL;
+COMMIT;
@@ -0,0 +1,7 @@
+-- One row per throttled key (`address:<email>` or `ip:<addr>`), holding
+-- the last time a confirmation email was resent on its behalf
+CREATE TABLE confirmation_resend_throttle(
+    throttle_key TEXT NOT NULL,
+    last_sent_at timestamptz NOT NULL,
+    PRIMARY KEY(throttle_key)
+);
@@ -0,0 +1,28 @@
+-- A single editable template, sent to every newly confirmed subscriber
+CREATE TABLE welcome_email(
+    id BOOLEAN NOT NULL DEFAULT TRUE,
+    enabled BOOLEAN NOT NULL,
+    subject TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    updated_at timestamptz NOT NULL,
+    PRIMARY KEY(id),
+    CHECK (id)
+);
+INSERT INTO welcome_email (enabled, subject, text_content, html_content, updated_at)
+VALUES (
+    TRUE,
+    'Welcome to our newsletter!',
+    E'Thanks for confirming your subscription!\nYou will receive our next issue as soon as it is out.',
+    '<p>Thanks for confirming your subscription!</p><p>You will receive our next issue as soon as it is out.</p>',
+    now()
+);
+
+CREATE TABLE welcome_email_queue(
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    last_error TEXT NULL,
+    PRIMARY KEY(subscriber_id)
+);
@@ -0,0 +1,6 @@
+-- Existing accounts keep full access
+ALTER TABLE users
+    ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+        CHECK (role IN ('owner', 'editor', 'viewer')),
+    ADD COLUMN is_active BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
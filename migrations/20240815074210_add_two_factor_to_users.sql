@@ -0,0 +1,13 @@
+-- Base32 TOTP secret, set once the user has confirmed enrollment with a valid code
+ALTER TABLE users
+    ADD COLUMN totp_secret TEXT NULL,
+    -- Last accepted 30 seconds time step, so a code can't be replayed
+    ADD COLUMN totp_last_used_step BIGINT NULL;
+
+CREATE TABLE user_recovery_codes(
+    user_id uuid NOT NULL
+        REFERENCES users (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at timestamptz NULL,
+    PRIMARY KEY(user_id, code_hash)
+);
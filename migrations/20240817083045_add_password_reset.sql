@@ -0,0 +1,16 @@
+-- Optional, so that existing users keep working until they set one
+ALTER TABLE users
+    ADD COLUMN email TEXT NULL,
+    -- Bumped to invalidate every session of the user, e.g. after a password reset
+    ADD COLUMN session_generation INTEGER NOT NULL DEFAULT 0;
+
+CREATE UNIQUE INDEX users_email_key ON users (lower(email));
+
+CREATE TABLE password_reset_tokens(
+    -- SHA-256 of the token sent by email, the token itself is never stored
+    token_hash TEXT NOT NULL PRIMARY KEY,
+    user_id uuid NOT NULL
+        REFERENCES users (user_id) ON DELETE CASCADE,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz NULL
+);
@@ -0,0 +1,10 @@
+-- Failed logins per `username:<username>` or `ip:<address>` key
+CREATE TABLE login_failures(
+    throttle_key TEXT NOT NULL PRIMARY KEY,
+    n_failures INTEGER NOT NULL,
+    last_failure_at timestamptz NOT NULL,
+    -- No login is attempted for this key before then
+    blocked_until timestamptz NOT NULL,
+    -- Set when the key hit the maximum number of failures
+    locked_out BOOLEAN NOT NULL DEFAULT FALSE
+);
@@ -0,0 +1,17 @@
+CREATE TABLE api_tokens(
+    api_token_id uuid NOT NULL PRIMARY KEY,
+    user_id uuid NOT NULL
+        REFERENCES users (user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- SHA-256 of the token, which is only shown once when created
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL,
+    last_used_at timestamptz NULL,
+    revoked_at timestamptz NULL
+);
+
+-- NULL for issues published from the admin form
+ALTER TABLE newsletter_issues
+    ADD COLUMN api_token_id uuid NULL
+        REFERENCES api_tokens (api_token_id) ON DELETE SET NULL;
@@ -0,0 +1,13 @@
+-- One row per login, referenced from the session state by `session_id`
+CREATE TABLE user_sessions(
+    session_id uuid NOT NULL PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    last_seen_at timestamptz NOT NULL,
+    user_agent TEXT NULL,
+    ip_address TEXT NOT NULL,
+    -- The session is logged out on its next request once this is set
+    revoked_at timestamptz NULL
+);
+
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id);
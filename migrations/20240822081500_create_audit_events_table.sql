@@ -0,0 +1,24 @@
+-- Who did what, from where. Rows are never updated nor deleted.
+CREATE TABLE audit_events(
+    audit_event_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    occurred_at timestamptz NOT NULL,
+    -- No foreign key: events outlive the users who triggered them
+    actor_user_id uuid NULL,
+    actor_username TEXT NULL,
+    action TEXT NOT NULL,
+    target TEXT NULL,
+    ip_address TEXT NULL,
+    details TEXT NULL
+);
+
+CREATE INDEX audit_events_occurred_at_idx ON audit_events (occurred_at);
+
+CREATE FUNCTION reject_audit_event_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION reject_audit_event_changes();
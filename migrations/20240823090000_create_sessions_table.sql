@@ -0,0 +1,8 @@
+-- Session state for the `postgres` session backend
+CREATE TABLE sessions(
+    session_key TEXT NOT NULL PRIMARY KEY,
+    -- JSON object mapping keys to their serialized values
+    state TEXT NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
@@ -0,0 +1,24 @@
+-- One row per CSV upload on /admin/subscribers/import
+CREATE TABLE subscriber_imports(
+    import_id uuid NOT NULL PRIMARY KEY,
+    imported_by uuid NULL REFERENCES users (user_id) ON DELETE SET NULL,
+    started_at timestamptz NOT NULL,
+    -- Status given to rows without one: `confirmed` or `pending_confirmation`
+    default_status TEXT NOT NULL,
+    n_accepted INTEGER NOT NULL DEFAULT 0,
+    n_duplicates INTEGER NOT NULL DEFAULT 0,
+    n_invalid INTEGER NOT NULL DEFAULT 0,
+    -- NULL while the upload is still being processed, or if it was interrupted
+    finished_at timestamptz NULL
+);
+-- The downloadable report: what happened to each line of the file
+CREATE TABLE subscriber_import_rows(
+    import_id uuid NOT NULL
+        REFERENCES subscriber_imports (import_id) ON DELETE CASCADE,
+    line_number BIGINT NOT NULL,
+    email TEXT NULL,
+    -- `accepted`, `duplicate` or `invalid`
+    outcome TEXT NOT NULL,
+    message TEXT NULL,
+    PRIMARY KEY (import_id, line_number)
+);
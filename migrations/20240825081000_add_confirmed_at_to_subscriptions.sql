@@ -0,0 +1,3 @@
+-- NULL for subscribers who haven't confirmed, and for those confirmed before
+-- the column existed or imported as already confirmed: we don't know when.
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
@@ -0,0 +1,6 @@
+-- Addresses erased at their owner's request, kept as a SHA-256 of the
+-- lowercased address: enough to refuse re-importing them, not to recover them
+CREATE TABLE suppressed_emails(
+    email_hash TEXT NOT NULL PRIMARY KEY,
+    suppressed_at timestamptz NOT NULL
+);
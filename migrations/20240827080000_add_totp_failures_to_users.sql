@@ -0,0 +1,6 @@
+-- Wrong second factor codes since the last successful one. Kept per user
+-- rather than per session, so that starting the login over doesn't reset it.
+ALTER TABLE users
+    ADD COLUMN n_failed_totp_attempts INTEGER NOT NULL DEFAULT 0,
+    -- No second factor is checked for this user before then
+    ADD COLUMN totp_locked_until timestamptz NULL;
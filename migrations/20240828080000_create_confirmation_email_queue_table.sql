@@ -0,0 +1,14 @@
+-- Confirmation emails of imported subscribers, sent in the background
+CREATE TABLE confirmation_email_queue(
+    subscription_token TEXT NOT NULL
+        REFERENCES subscription_tokens (subscription_token) ON DELETE CASCADE,
+    -- The import row to flag if the email can't be sent
+    import_id uuid NOT NULL,
+    line_number BIGINT NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    last_error TEXT NULL,
+    PRIMARY KEY(subscription_token),
+    FOREIGN KEY (import_id, line_number)
+        REFERENCES subscriber_import_rows (import_id, line_number) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+-- Personal data links waiting to be emailed. Requests for addresses we
+-- hold nothing on are dropped by the worker, without an email.
+CREATE TABLE personal_data_email_queue(
+    id uuid NOT NULL PRIMARY KEY,
+    email TEXT NOT NULL,
+    -- `access` or `erasure`
+    request TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    last_error TEXT NULL
+);
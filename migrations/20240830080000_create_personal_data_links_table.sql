@@ -0,0 +1,9 @@
+-- Links emailed for personal data requests, so that they don't have to
+-- carry the address. Only the hash of their token is stored.
+CREATE TABLE personal_data_links(
+    token_hash TEXT NOT NULL PRIMARY KEY,
+    email TEXT NOT NULL,
+    -- `access` or `erasure`
+    request TEXT NOT NULL,
+    expires_at timestamptz NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Confirmation emails for the public subscription form are queued as well:
+-- only those of imported subscribers point at a row of an import report.
+ALTER TABLE confirmation_email_queue ALTER COLUMN import_id DROP NOT NULL;
+ALTER TABLE confirmation_email_queue ALTER COLUMN line_number DROP NOT NULL;
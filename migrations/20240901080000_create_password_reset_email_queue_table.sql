@@ -0,0 +1,9 @@
+-- Password reset links waiting to be emailed. Requests for addresses no
+-- active user has are dropped by the worker, without an email.
+CREATE TABLE password_reset_email_queue(
+    id uuid NOT NULL PRIMARY KEY,
+    email TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    last_error TEXT NULL
+);
@@ -0,0 +1,236 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::Role;
+
+// Makes leaked tokens easy to spot, e.g. by secret scanners
+const TOKEN_PREFIX: &str = "z2p_";
+
+/// What an API token is allowed to do on behalf of its owner.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    PublishNewsletters,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 1] = [ApiScope::PublishNewsletters];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::PublishNewsletters => "newsletters:publish",
+        }
+    }
+
+    /// The role the token owner needs for the scope to be usable.
+    pub fn required_role(&self) -> Role {
+        match self {
+            ApiScope::PublishNewsletters => Role::Editor,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for ApiScope {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        ApiScope::ALL
+            .into_iter()
+            .find(|s| s.as_str() == value)
+            .ok_or_else(|| format!("{} is not a valid scope.", value))
+    }
+}
+
+fn generate_api_token() -> String {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect();
+    format!("{}{}", TOKEN_PREFIX, random)
+}
+
+fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Create a token for `user_id`, returning its id and the token itself.
+/// Only a hash is stored: the token can't be shown again.
+#[tracing::instrument(name = "Create API token", skip(pool))]
+pub async fn create_api_token(
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+    pool: &PgPool,
+) -> Result<(Uuid, String), anyhow::Error> {
+    let api_token_id = Uuid::new_v4();
+    let token = generate_api_token();
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_owned()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (api_token_id, user_id, name, token_hash, scopes, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        api_token_id,
+        user_id,
+        name,
+        hash_api_token(&token),
+        &scopes
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store an API token.")?;
+    Ok((api_token_id, token))
+}
+
+pub struct ApiTokenSummary {
+    pub api_token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn list_api_tokens(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<ApiTokenSummary>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiTokenSummary,
+        r#"
+        SELECT api_token_id, name, scopes, created_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list API tokens.")?;
+    Ok(tokens)
+}
+
+/// Returns `false` if `user_id` has no such active token.
+#[tracing::instrument(name = "Revoke API token", skip(pool))]
+pub async fn revoke_api_token(
+    user_id: Uuid,
+    api_token_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE api_tokens SET revoked_at = now()
+        WHERE api_token_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        api_token_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an API token.")?
+    .rows_affected();
+    Ok(n_updated == 1)
+}
+
+/// An API token that checked out, with the current role of its owner.
+#[derive(Clone, Debug)]
+pub struct ApiToken {
+    pub api_token_id: Uuid,
+    pub user_id: Uuid,
+    pub role: Role,
+    pub scopes: Vec<ApiScope>,
+}
+
+impl ApiToken {
+    /// Scopes stop working when the owner loses the role they require.
+    pub fn allows(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope) && self.role >= scope.required_role()
+    }
+}
+
+/// Look up an unrevoked token of an active user, recording that it was used.
+#[tracing::instrument(name = "Authenticate API token", skip(token, pool))]
+pub async fn authenticate_api_token(
+    token: &str,
+    pool: &PgPool,
+) -> Result<Option<ApiToken>, anyhow::Error> {
+    let Some(row) = sqlx::query!(
+        r#"
+        UPDATE api_tokens t SET last_used_at = now()
+        FROM users u
+        WHERE
+            u.user_id = t.user_id AND
+            t.token_hash = $1 AND
+            t.revoked_at IS NULL AND
+            u.is_active
+        RETURNING t.api_token_id, t.user_id, t.scopes, u.role
+        "#,
+        hash_api_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to authenticate an API token.")?
+    else {
+        return Ok(None);
+    };
+    let role = Role::try_from(row.role).map_err(anyhow::Error::msg)?;
+    // Scopes this version doesn't know about are ignored
+    let scopes = row
+        .scopes
+        .into_iter()
+        .filter_map(|s| ApiScope::try_from(s).ok())
+        .collect();
+    Ok(Some(ApiToken {
+        api_token_id: row.api_token_id,
+        user_id: row.user_id,
+        role,
+        scopes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_api_token, ApiScope, ApiToken};
+    use crate::authentication::Role;
+    use claims::assert_err;
+    use uuid::Uuid;
+
+    #[test]
+    fn tokens_are_prefixed_and_unique() {
+        let token = generate_api_token();
+        assert!(token.starts_with("z2p_"));
+        assert_ne!(token, generate_api_token());
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_string_form() {
+        for scope in ApiScope::ALL {
+            assert_eq!(ApiScope::try_from(scope.as_str().to_string()), Ok(scope));
+        }
+        assert_err!(ApiScope::try_from("newsletters:delete".to_string()));
+    }
+
+    #[test]
+    fn scopes_need_the_matching_role() {
+        let token = |role| ApiToken {
+            api_token_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            role,
+            scopes: vec![ApiScope::PublishNewsletters],
+        };
+        assert!(token(Role::Editor).allows(ApiScope::PublishNewsletters));
+        assert!(!token(Role::Viewer).allows(ApiScope::PublishNewsletters));
+    }
+}
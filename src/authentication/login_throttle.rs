@@ -0,0 +1,227 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+
+use crate::configuration::LoginThrottlingSettings;
+
+pub fn username_throttle_key(username: &str) -> String {
+    format!("username:{}", username)
+}
+
+pub fn ip_throttle_key(client_ip: &str) -> String {
+    format!("ip:{}", client_ip)
+}
+
+/// Count a login attempt for `throttle_key` as a failure before verifying it,
+/// delaying the next attempt and locking the key out once it reaches
+/// `max_failures`. Returns `false`, without counting anything, if the key is
+/// still delayed or locked out.
+///
+/// The row stays locked until `transaction` is over: concurrent attempts
+/// wait for it, then find the key delayed, so that guessing in parallel
+/// does not get around the throttling.
+#[tracing::instrument(name = "Reserve login attempt", skip(transaction, settings))]
+pub async fn reserve_login_attempt(
+    transaction: &mut Transaction<'_, Postgres>,
+    throttle_key: &str,
+    max_failures: i32,
+    settings: &LoginThrottlingSettings,
+) -> Result<bool, anyhow::Error> {
+    let now = Utc::now();
+    let lockout = chrono::Duration::from_std(Duration::from_secs(settings.lockout_seconds))?;
+    // Failures older than a lockout period are forgotten
+    let Some(row) = sqlx::query!(
+        r#"
+        INSERT INTO login_failures AS f
+            (throttle_key, n_failures, last_failure_at, blocked_until, locked_out)
+        VALUES ($1, 1, $2, $2, false)
+        ON CONFLICT (throttle_key) DO UPDATE
+        SET
+            n_failures = CASE WHEN f.last_failure_at > $3 THEN f.n_failures + 1 ELSE 1 END,
+            last_failure_at = EXCLUDED.last_failure_at
+        WHERE f.blocked_until <= $2
+        RETURNING n_failures
+        "#,
+        throttle_key,
+        now,
+        now - lockout
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to reserve a login attempt.")?
+    else {
+        return Ok(false);
+    };
+    let n_failures = row.n_failures;
+    let locked_out = n_failures >= max_failures;
+    let delay = if locked_out {
+        lockout
+    } else {
+        let delay = settings
+            .initial_delay_seconds
+            .saturating_mul(1 << (n_failures - 1).min(16));
+        chrono::Duration::from_std(Duration::from_secs(delay))?.min(lockout)
+    };
+    sqlx::query!(
+        r#"
+        UPDATE login_failures
+        SET blocked_until = $2, locked_out = $3
+        WHERE throttle_key = $1
+        "#,
+        throttle_key,
+        now + delay,
+        locked_out
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delay the next login attempt.")?;
+    if locked_out {
+        tracing::warn!(
+            throttle_key,
+            n_failures,
+            lockout_seconds = settings.lockout_seconds,
+            "Logins are locked out after too many failures."
+        );
+    }
+    Ok(true)
+}
+
+/// Take back the failure counted by `reserve_login_attempt` for an attempt
+/// that succeeded, lifting the delay it put on `throttle_key`. The failures
+/// of other attempts are kept, unlike with `clear_login_failures`.
+#[tracing::instrument(name = "Release login attempt", skip(pool))]
+pub async fn release_login_attempt(throttle_key: &str, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE login_failures
+        SET n_failures = n_failures - 1, blocked_until = now(), locked_out = false
+        WHERE throttle_key = $1
+        "#,
+        throttle_key
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to release a login attempt.")?;
+    sqlx::query!(
+        r#"DELETE FROM login_failures WHERE throttle_key = $1 AND n_failures <= 0"#,
+        throttle_key
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to release a login attempt.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to release a login attempt.")?;
+    Ok(())
+}
+
+/// Reserve an attempt at checking the password of `username` from
+/// `client_ip`, on both of their keys. Returns `false` if either of them is
+/// throttled, without counting anything.
+#[tracing::instrument(name = "Reserve password check", skip(settings, pool))]
+pub async fn reserve_password_check(
+    username: &str,
+    client_ip: &str,
+    settings: &LoginThrottlingSettings,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    for (key, max_failures) in [
+        (
+            username_throttle_key(username),
+            settings.max_failures_per_username,
+        ),
+        (ip_throttle_key(client_ip), settings.max_failures_per_ip),
+    ] {
+        if !reserve_login_attempt(&mut transaction, &key, max_failures, settings).await? {
+            return Ok(false);
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reserve a password check.")?;
+    Ok(true)
+}
+
+/// The password of `username` was right: forget its failures, and take back
+/// the one counted for `client_ip`. Other failures from the same IP may be
+/// guesses at other accounts, they are kept.
+#[tracing::instrument(name = "Settle password check", skip(pool))]
+pub async fn settle_password_check(
+    username: &str,
+    client_ip: &str,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    clear_login_failures(&username_throttle_key(username), pool).await?;
+    release_login_attempt(&ip_throttle_key(client_ip), pool).await
+}
+
+/// Forget the failures of `throttle_key`, lifting any delay or lockout.
+/// Returns whether there was anything to clear.
+#[tracing::instrument(name = "Clear login failures", skip(pool))]
+pub async fn clear_login_failures(
+    throttle_key: &str,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let n_deleted = sqlx::query!(
+        r#"DELETE FROM login_failures WHERE throttle_key = $1"#,
+        throttle_key
+    )
+    .execute(pool)
+    .await
+    .context("Failed to clear login failures.")?
+    .rows_affected();
+    Ok(n_deleted > 0)
+}
+
+/// Forget the failures of keys that have not been delayed or locked out
+/// for `retention`.
+#[tracing::instrument(skip(pool), err)]
+pub async fn purge_login_failures(
+    pool: &PgPool,
+    retention: Duration,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(retention)?;
+    let n_deleted = sqlx::query!(
+        r#"DELETE FROM login_failures WHERE blocked_until < $1"#,
+        cutoff
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_deleted)
+}
+
+pub struct LoginFailures {
+    pub throttle_key: String,
+    pub n_failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub blocked_until: DateTime<Utc>,
+    pub locked_out: bool,
+}
+
+#[tracing::instrument(name = "List login failures", skip(pool))]
+pub async fn list_login_failures(pool: &PgPool) -> Result<Vec<LoginFailures>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        LoginFailures,
+        r#"
+        SELECT throttle_key, n_failures, last_failure_at, blocked_until, locked_out
+        FROM login_failures
+        ORDER BY locked_out DESC, last_failure_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list login failures.")?;
+    Ok(rows)
+}
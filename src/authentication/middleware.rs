@@ -1,12 +1,21 @@
 use std::ops::Deref;
 
+use actix_multipart::Multipart;
 use actix_web::body::MessageBody;
-use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::error::InternalError;
-use actix_web::{FromRequest, HttpMessage};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::{InternalError, PayloadError};
+use actix_web::http::header::{
+    ContentType, HeaderMap, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE,
+};
+use actix_web::http::Method;
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
 use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use futures_util::{StreamExt, TryStreamExt};
+use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::{authenticate_api_token, start_session, touch_session, Role};
 use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
 
@@ -35,15 +44,273 @@ pub async fn reject_anonymous_users(
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => {
+    let Some(user_id) = session.get_user_id().map_err(e500)? else {
+        let response = see_other("/login");
+        let e = anyhow::anyhow!("The user has not logged in");
+        return Err(InternalError::from_response(e, response).into());
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or_else(|| e500("The database pool is not registered"))?;
+    // Roles can change and accounts can be deactivated while a session is
+    // alive: look them up on every request rather than caching them in the session.
+    // Sessions from before this was tracked count as the first generation.
+    let session_generation = session.get_session_generation().map_err(e500)?.unwrap_or(0);
+    match get_active_user(user_id, pool).await.map_err(e500)? {
+        Some((role, current_generation)) if session_generation >= current_generation => {
+            let session_is_live = match session.get_session_id().map_err(e500)? {
+                Some(session_id) => touch_session(session_id, user_id, pool)
+                    .await
+                    .map_err(e500)?,
+                // Sessions from before they were tracked get a record on their next request
+                None => {
+                    let session_id = start_session(user_id, req.request(), pool)
+                        .await
+                        .map_err(e500)?;
+                    session.insert_session_id(session_id).map_err(e500)?;
+                    true
+                }
+            };
+            if !session_is_live {
+                return Err(logged_out(session));
+            }
             req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(role);
             next.call(req).await
         }
-        None => {
-            let response = see_other("/login");
-            let e = anyhow::anyhow!("The user has not logged in");
+        _ => Err(logged_out(session)),
+    }
+}
+
+/// How much of an upload is read to find its `csrf_token` field.
+const MAX_CSRF_FIELD_PREFIX: usize = 8 * 1024;
+
+/// The value of the upload's first field if it is `csrf_token`. The bytes
+/// read to find it are handed back to the handler along with the rest.
+async fn read_csrf_field(req: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    let mut payload = req.take_payload();
+    let mut prefix = web::BytesMut::new();
+    while prefix.len() < MAX_CSRF_FIELD_PREFIX {
+        match payload.next().await {
+            Some(chunk) => prefix.extend_from_slice(&chunk?),
+            None => break,
+        }
+    }
+    let prefix = prefix.freeze();
+    let token = first_csrf_field(req.headers(), prefix.clone()).await;
+    let body = futures_util::stream::once(async move { Ok(prefix) }).chain(payload);
+    req.set_payload(Payload::Stream {
+        payload: Box::pin(body),
+    });
+    Ok(token)
+}
+
+async fn first_csrf_field(headers: &HeaderMap, prefix: web::Bytes) -> Option<String> {
+    let prefix = futures_util::stream::once(async move { Ok::<_, PayloadError>(prefix) });
+    let mut multipart = Multipart::new(headers, prefix);
+    let mut field = multipart.try_next().await.ok()??;
+    if field.name() != "csrf_token" {
+        return None;
+    }
+    let mut value = vec![];
+    // Fails if the field doesn't end within the prefix
+    while let Some(chunk) = field.try_next().await.ok()? {
+        value.extend_from_slice(&chunk);
+    }
+    String::from_utf8(value).ok()
+}
+
+fn logged_out(session: TypedSession) -> actix_web::Error {
+    session.log_out();
+    let response = see_other("/login");
+    let e = anyhow::anyhow!("The user has been deactivated, deleted or logged out");
+    InternalError::from_response(e, response).into()
+}
+
+/// Reject `POST`s that don't carry the session's CSRF token, either in the
+/// `csrf_token` form field or in an `X-CSRF-Token` header.
+///
+/// File uploads must send `csrf_token` as their first field: only the start
+/// of their body is buffered here, the rest is streamed to the handler.
+pub async fn reject_invalid_csrf_tokens(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if req.method() != Method::POST {
+        return next.call(req).await;
+    }
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let expected_token = session.get_csrf_token().map_err(e500)?;
+
+    let mut token = req
+        .headers()
+        .get("X-CSRF-Token")
+        .and_then(|h| h.to_str().ok())
+        .map(|t| t.to_owned());
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    let is_form = content_type.starts_with("application/x-www-form-urlencoded");
+    let is_upload = content_type.starts_with("multipart/form-data");
+    if token.is_none() && is_upload {
+        token = read_csrf_field(&mut req).await?;
+    }
+    if token.is_none() && is_form {
+        // Read the form to find the token, then hand the body back to the handler
+        let body = req.extract::<web::Bytes>().await?;
+        token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|(key, value)| (key == "csrf_token").then_some(value));
+        let (_, mut payload) = actix_http::h1::Payload::create(true);
+        payload.unread_data(body);
+        req.set_payload(payload.into());
+    }
+
+    match (token, expected_token) {
+        (Some(token), Some(expected_token)) if tokens_match(&token, &expected_token) => {
+            next.call(req).await
+        }
+        _ => {
+            let response = HttpResponse::Forbidden()
+                .content_type(ContentType::html())
+                .body(
+                    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forbidden</title>
+</head>
+<body>
+    <p>The form has expired. Please go back, reload the page and try again.</p>
+</body>
+</html>"#,
+                );
+            let e = anyhow::anyhow!("The CSRF token is missing or invalid");
             Err(InternalError::from_response(e, response).into())
         }
     }
 }
+
+// Compare in constant time, so that response times don't leak the token
+pub(super) fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Authenticate API requests with an `Authorization: Bearer <token>` header.
+/// The token, its owner's `UserId` and `Role` are added to the request extensions.
+pub async fn reject_invalid_api_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_owned());
+    let Some(token) = token else {
+        return Err(api_unauthorized(
+            "The 'Authorization' header is missing or is not a bearer token.",
+        ));
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or_else(|| e500("The database pool is not registered"))?;
+    match authenticate_api_token(&token, pool).await.map_err(e500)? {
+        Some(api_token) => {
+            req.extensions_mut().insert(UserId(api_token.user_id));
+            req.extensions_mut().insert(api_token.role);
+            req.extensions_mut().insert(api_token);
+            next.call(req).await
+        }
+        None => Err(api_unauthorized(
+            "The API token is invalid or has been revoked.",
+        )),
+    }
+}
+
+fn api_unauthorized(message: &str) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized()
+        .insert_header((WWW_AUTHENTICATE, r#"Bearer realm="api""#))
+        .json(serde_json::json!({
+            "error": "unauthorized",
+            "message": message,
+        }));
+    InternalError::from_response(anyhow::anyhow!(message.to_owned()), response).into()
+}
+
+/// Only let editors and owners through. Must run after `reject_anonymous_users`.
+pub async fn require_editor(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Editor, req, next).await
+}
+
+/// Only let owners through. Must run after `reject_anonymous_users`.
+pub async fn require_owner(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Owner, req, next).await
+}
+
+async fn require_role(
+    minimum_role: Role,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let role = req.extensions().get::<Role>().copied();
+    match role {
+        Some(role) if role >= minimum_role => next.call(req).await,
+        _ => {
+            let response = HttpResponse::Forbidden()
+                .content_type(ContentType::html())
+                .body(
+                    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forbidden</title>
+</head>
+<body>
+    <p>You are not allowed to access this page.</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+                );
+            let e = anyhow::anyhow!("The user needs the {} role", minimum_role);
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+/// The role and the current session generation of an active user.
+#[tracing::instrument(name = "Get an active user", skip(pool))]
+async fn get_active_user(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<(Role, i32)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT role, session_generation FROM users WHERE user_id = $1 AND is_active"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the role of a user.")?;
+    row.map(|r| {
+        let role = Role::try_from(r.role).map_err(anyhow::Error::msg)?;
+        Ok((role, r.session_generation))
+    })
+    .transpose()
+}
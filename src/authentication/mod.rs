@@ -1,5 +1,43 @@
+mod api_tokens;
+mod login_throttle;
 mod middleware;
 mod password;
+mod password_policy;
+mod password_reset;
+mod role;
+mod sessions;
+mod two_factor;
 
-pub use middleware::{reject_anonymous_users, UserId};
-pub use password::{change_password, validate_credentials, AuthError, Credentials};
+pub use api_tokens::{
+    authenticate_api_token, create_api_token, list_api_tokens, revoke_api_token, ApiScope,
+    ApiToken, ApiTokenSummary,
+};
+pub use login_throttle::{
+    clear_login_failures, ip_throttle_key, list_login_failures, purge_login_failures,
+    reserve_password_check, settle_password_check, username_throttle_key, LoginFailures,
+};
+pub use middleware::{
+    reject_anonymous_users, reject_invalid_api_tokens, reject_invalid_csrf_tokens, require_editor,
+    require_owner, UserId,
+};
+pub use password::{
+    change_password, create_user, get_username, validate_credentials, AuthError, Credentials,
+    FallbackPasswordHash,
+};
+pub use password_policy::{check_password_policy, PasswordPolicyViolation};
+pub use password_reset::{
+    check_password_reset_token, create_password_reset_token, enqueue_password_reset_email,
+    get_active_user_by_email, password_reset_throttle_keys, purge_password_reset_tokens,
+    reset_password, PasswordResetUser,
+};
+pub use role::Role;
+pub use sessions::{
+    get_session_generation, invalidate_sessions, list_sessions, purge_user_sessions,
+    revoke_other_sessions, revoke_session, start_session, touch_session, UserSession,
+};
+pub use two_factor::{
+    clear_failed_second_factors, count_unused_recovery_codes, disable_two_factor,
+    enable_two_factor, generate_recovery_codes, generate_totp_secret, get_totp_secret,
+    is_second_factor_locked, matching_totp_step, otpauth_uri, record_failed_second_factor,
+    validate_second_factor,
+};
@@ -1,9 +1,13 @@
+use super::Role;
+use crate::configuration::PasswordHashingSettings;
 use crate::telemetry::spawn_blocking_with_tracing;
 use anyhow::Context;
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use rand::Rng;
 use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
+use tracing::Instrument;
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
@@ -18,19 +22,41 @@ pub struct Credentials {
     pub password: Secret<String>,
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+/// The hash unknown usernames are checked against, so that they take as long
+/// to reject as wrong passwords. It is computed once at startup with the
+/// configured parameters: a hardcoded one would get faster to verify than
+/// real hashes as soon as the parameters are raised.
+#[derive(Clone)]
+pub struct FallbackPasswordHash(Secret<String>);
+
+impl FallbackPasswordHash {
+    pub fn new(hashing: &PasswordHashingSettings) -> Result<Self, anyhow::Error> {
+        let params = hashing
+            .params()
+            .context("Invalid password hashing parameters.")?;
+        let password: String = rand::thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        Ok(Self(compute_password_hash(Secret::new(password), params)?))
+    }
+}
+
+/// Check a username and password. Hashes computed with weaker parameters
+/// than `hashing` are upgraded in the background once the password matched.
+#[tracing::instrument(
+    name = "Validate credentials",
+    skip(credentials, hashing, fallback_hash, pool)
+)]
 pub async fn validate_credentials(
     credentials: Credentials,
+    hashing: &PasswordHashingSettings,
+    fallback_hash: &FallbackPasswordHash,
     pool: &PgPool,
 ) -> Result<uuid::Uuid, AuthError> {
-    // Set to some random password to mitigate timing attacks for non existent users;
     let mut user_id = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-        gZiV/M1gPc22ElAH/Jh1Hw$\
-        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .to_string(),
-    );
+    let mut expected_password_hash = fallback_hash.0.clone();
 
     if let Some((stored_user_id, stored_password_hash)) =
         get_stored_credentials(&credentials.username, pool).await?
@@ -39,15 +65,92 @@ pub async fn validate_credentials(
         expected_password_hash = stored_password_hash;
     }
 
+    let stored_password_hash = expected_password_hash.clone();
+    let password = credentials.password.clone();
     spawn_blocking_with_tracing(move || {
         verify_password_hash(expected_password_hash, credentials.password)
     })
     .await
     .context("Failed to spawn blocking task.")??;
 
-    user_id
+    let user_id = user_id
         .ok_or_else(|| anyhow::anyhow!("Unknown username."))
-        .map_err(AuthError::InvalidCredentials)
+        .map_err(AuthError::InvalidCredentials)?;
+
+    let params = hashing
+        .params()
+        .context("Invalid password hashing parameters.")?;
+    if needs_rehash(stored_password_hash.expose_secret(), &params) {
+        let pool = pool.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) =
+                    rehash_password(user_id, stored_password_hash, password, params, &pool).await
+                {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to rehash a password with the current parameters."
+                    );
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+    Ok(user_id)
+}
+
+/// Whether a stored hash was computed with another algorithm, or with
+/// parameters below the current policy.
+fn needs_rehash(password_hash: &str, policy: &Params) -> bool {
+    let Ok(password_hash) = PasswordHash::new(password_hash) else {
+        return true;
+    };
+    if password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(&password_hash) {
+        Ok(params) => {
+            params.m_cost() < policy.m_cost()
+                || params.t_cost() < policy.t_cost()
+                || params.p_cost() < policy.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+#[tracing::instrument(
+    name = "Rehash password",
+    skip(old_password_hash, password, params, pool)
+)]
+async fn rehash_password(
+    user_id: uuid::Uuid,
+    old_password_hash: Secret<String>,
+    password: Secret<String>,
+    params: Params,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, params))
+            .await?
+            .context("Failed to hash password")?;
+    // Leave the hash alone if the password changed in the meantime
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2 AND password_hash = $3
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+        old_password_hash.expose_secret()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the rehashed password in the database.")?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -79,7 +182,7 @@ async fn get_stored_credentials(
         r#"
         SELECT user_id, password_hash
         FROM users
-        WHERE username = $1
+        WHERE username = $1 AND is_active
         "#,
         username,
     )
@@ -90,15 +193,36 @@ async fn get_stored_credentials(
     Ok(row)
 }
 
-#[tracing::instrument(name = "Change password", skip(password, pool))]
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: uuid::Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT username
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve a username.")?;
+    Ok(row.username)
+}
+
+#[tracing::instrument(name = "Change password", skip(password, hashing, executor))]
 pub async fn change_password(
     user_id: uuid::Uuid,
     password: Secret<String>,
-    pool: &PgPool,
+    hashing: &PasswordHashingSettings,
+    executor: impl PgExecutor<'_>,
 ) -> Result<(), anyhow::Error> {
-    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
-        .await?
-        .context("Failed to hash password")?;
+    let params = hashing
+        .params()
+        .context("Invalid password hashing parameters.")?;
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, params))
+            .await?
+            .context("Failed to hash password")?;
     sqlx::query!(
         r#"
         UPDATE users
@@ -108,20 +232,116 @@ pub async fn change_password(
         password_hash.expose_secret(),
         user_id
     )
-    .execute(pool)
+    .execute(executor)
     .await
     .context("Failed to change user's password in the database.")?;
     Ok(())
 }
 
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
-    let salt = SaltString::generate(&mut rand::thread_rng());
-    let password_hash = Argon2::new(
-        Algorithm::Argon2id,
-        Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
+/// Store a new user, returning `None` if the username is already taken.
+#[tracing::instrument(name = "Create user", skip(password, hashing, executor))]
+pub async fn create_user(
+    username: &str,
+    password: Secret<String>,
+    role: Role,
+    hashing: &PasswordHashingSettings,
+    executor: impl PgExecutor<'_>,
+) -> Result<Option<uuid::Uuid>, anyhow::Error> {
+    let params = hashing
+        .params()
+        .context("Invalid password hashing parameters.")?;
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, params))
+            .await?
+            .context("Failed to hash password")?;
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (username) DO NOTHING
+        RETURNING user_id
+        "#,
+        uuid::Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        role.as_str()
     )
-    .hash_password(password.expose_secret().as_bytes(), &salt)?
-    .to_string();
+    .fetch_optional(executor)
+    .await
+    .context("Failed to store a new user in the database.")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+fn compute_password_hash(
+    password: Secret<String>,
+    params: Params,
+) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
     Ok(Secret::new(password_hash))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_password_hash, needs_rehash, FallbackPasswordHash};
+    use crate::configuration::PasswordHashingSettings;
+    use argon2::Params;
+    use secrecy::{ExposeSecret, Secret};
+
+    fn hash_with(params: Params) -> String {
+        compute_password_hash(Secret::new("password".to_string()), params)
+            .unwrap()
+            .expose_secret()
+            .clone()
+    }
+
+    #[test]
+    fn hashes_matching_the_policy_are_kept() {
+        let policy = Params::new(15000, 2, 1, None).unwrap();
+        assert!(!needs_rehash(&hash_with(policy.clone()), &policy));
+    }
+
+    #[test]
+    fn hashes_above_the_policy_are_kept() {
+        let policy = Params::new(8192, 1, 1, None).unwrap();
+        let stronger = Params::new(15000, 2, 1, None).unwrap();
+        assert!(!needs_rehash(&hash_with(stronger), &policy));
+    }
+
+    #[test]
+    fn hashes_below_the_policy_are_rehashed() {
+        let weaker = Params::new(8192, 1, 1, None).unwrap();
+        for policy in [
+            Params::new(15000, 1, 1, None).unwrap(),
+            Params::new(8192, 2, 1, None).unwrap(),
+            Params::new(8192, 1, 2, None).unwrap(),
+        ] {
+            assert!(needs_rehash(&hash_with(weaker.clone()), &policy));
+        }
+    }
+
+    #[test]
+    fn other_algorithms_are_rehashed() {
+        let policy = Params::new(15000, 2, 1, None).unwrap();
+        let argon2i_hash = "$argon2i$v=19$m=15000,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$\
+            CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+        assert!(needs_rehash(argon2i_hash, &policy));
+    }
+
+    #[test]
+    fn the_fallback_hash_uses_the_configured_parameters() {
+        let hashing = PasswordHashingSettings {
+            memory_kib: 19456,
+            iterations: 3,
+            parallelism: 1,
+        };
+        let fallback_hash = FallbackPasswordHash::new(&hashing).unwrap();
+        assert!(!needs_rehash(
+            fallback_hash.0.expose_secret(),
+            &hashing.params().unwrap()
+        ));
+        assert!(fallback_hash.0.expose_secret().contains("m=19456,t=3,p=1"));
+    }
+}
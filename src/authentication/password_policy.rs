@@ -0,0 +1,153 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::configuration::PasswordPolicySettings;
+
+#[derive(Debug, PartialEq)]
+pub enum PasswordPolicyViolation {
+    TooShort { min_length: usize },
+    TooLong { max_length: usize },
+    SameAsUsername,
+    SameAsCurrentPassword,
+    TooWeak { warning: Option<String> },
+}
+
+impl std::fmt::Display for PasswordPolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordPolicyViolation::TooShort { min_length } => write!(
+                f,
+                "The new password must be at least {} characters long.",
+                min_length
+            ),
+            PasswordPolicyViolation::TooLong { max_length } => write!(
+                f,
+                "The new password must be at most {} characters long.",
+                max_length
+            ),
+            PasswordPolicyViolation::SameAsUsername => {
+                write!(f, "The new password can't be your username.")
+            }
+            PasswordPolicyViolation::SameAsCurrentPassword => {
+                write!(
+                    f,
+                    "The new password must be different from the current one."
+                )
+            }
+            PasswordPolicyViolation::TooWeak { warning } => {
+                write!(f, "The new password is too easy to guess.")?;
+                if let Some(warning) = warning {
+                    write!(f, " {}", warning)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Check a new password against the policy. `current_password` is `None`
+/// when it is not known, e.g. when resetting a forgotten password.
+pub fn check_password_policy(
+    password: &str,
+    username: &str,
+    current_password: Option<&str>,
+    policy: &PasswordPolicySettings,
+) -> Result<(), PasswordPolicyViolation> {
+    // Lengths are checked first, the strength estimate is costly on long inputs
+    let length = password.graphemes(true).count();
+    if length < policy.min_length {
+        return Err(PasswordPolicyViolation::TooShort {
+            min_length: policy.min_length,
+        });
+    }
+    if length > policy.max_length {
+        return Err(PasswordPolicyViolation::TooLong {
+            max_length: policy.max_length,
+        });
+    }
+    if password.to_lowercase() == username.to_lowercase() {
+        return Err(PasswordPolicyViolation::SameAsUsername);
+    }
+    if current_password == Some(password) {
+        return Err(PasswordPolicyViolation::SameAsCurrentPassword);
+    }
+    let estimate = zxcvbn::zxcvbn(password, &[username]);
+    if u8::from(estimate.score()) < policy.min_strength {
+        let warning = estimate
+            .feedback()
+            .and_then(|f| f.warning())
+            .map(|w| w.to_string());
+        return Err(PasswordPolicyViolation::TooWeak { warning });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_password_policy, PasswordPolicyViolation};
+    use crate::configuration::PasswordPolicySettings;
+    use claims::{assert_err, assert_ok};
+
+    fn policy() -> PasswordPolicySettings {
+        PasswordPolicySettings {
+            min_length: 12,
+            max_length: 64,
+            min_strength: 3,
+        }
+    }
+
+    #[test]
+    fn a_long_random_password_is_accepted() {
+        assert_ok!(check_password_policy(
+            "correct horse battery staple",
+            "ursula",
+            None,
+            &policy()
+        ));
+    }
+
+    #[test]
+    fn length_is_counted_in_graphemes() {
+        // 11 graphemes, but far more bytes
+        let password = "ё".repeat(11);
+        assert_eq!(
+            check_password_policy(&password, "ursula", None, &policy()),
+            Err(PasswordPolicyViolation::TooShort { min_length: 12 })
+        );
+    }
+
+    #[test]
+    fn overly_long_passwords_are_rejected() {
+        let password = "a".repeat(65);
+        assert_eq!(
+            check_password_policy(&password, "ursula", None, &policy()),
+            Err(PasswordPolicyViolation::TooLong { max_length: 64 })
+        );
+    }
+
+    #[test]
+    fn the_username_is_rejected() {
+        assert_eq!(
+            check_password_policy("Ursula-Le-Guin", "ursula-le-guin", None, &policy()),
+            Err(PasswordPolicyViolation::SameAsUsername)
+        );
+    }
+
+    #[test]
+    fn the_current_password_is_rejected() {
+        let password = "correct horse battery staple";
+        assert_eq!(
+            check_password_policy(password, "ursula", Some(password), &policy()),
+            Err(PasswordPolicyViolation::SameAsCurrentPassword)
+        );
+    }
+
+    #[test]
+    fn easy_to_guess_passwords_are_rejected() {
+        let result = check_password_policy("password1234", "ursula", None, &policy());
+        assert_err!(&result);
+        assert!(matches!(
+            result,
+            Err(PasswordPolicyViolation::TooWeak { .. })
+        ));
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::Context;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::Secret;
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::{change_password, invalidate_sessions};
+use crate::configuration::PasswordHashingSettings;
+use crate::personal_data::suppression_hash;
+
+fn generate_reset_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+// Only the hash is stored, a leaked table can't be used to reset passwords.
+fn hash_reset_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub struct PasswordResetUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+}
+
+#[tracing::instrument(name = "Get active user by email", skip(email, pool))]
+pub async fn get_active_user_by_email(
+    email: &str,
+    pool: &PgPool,
+) -> Result<Option<PasswordResetUser>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, username, email as "email!"
+        FROM users
+        WHERE lower(email) = lower($1) AND is_active
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a user by email.")?
+    .map(|r| PasswordResetUser {
+        user_id: r.user_id,
+        username: r.username,
+        email: r.email,
+    });
+    Ok(row)
+}
+
+/// The `confirmation_resend_throttle` keys of password reset requests, from
+/// this address and this client. The address is hashed, not to keep a trace
+/// of addresses no user has.
+pub fn password_reset_throttle_keys(email: &str, client_ip: &str) -> [String; 2] {
+    [
+        format!("password-reset:ip:{}", client_ip),
+        format!("password-reset:address:{}", suppression_hash(email)),
+    ]
+}
+
+/// Queue a reset link for the user with this address, for the password reset
+/// email worker.
+#[tracing::instrument(skip(transaction, email))]
+pub async fn enqueue_password_reset_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO password_reset_email_queue (id, email) VALUES ($1, $2)"#,
+        Uuid::new_v4(),
+        email
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Issue a single-use token to reset the password of `user_id`, valid for `ttl`.
+#[tracing::instrument(name = "Create password reset token", skip(executor))]
+pub async fn create_password_reset_token(
+    user_id: Uuid,
+    ttl: Duration,
+    executor: impl PgExecutor<'_>,
+) -> Result<String, anyhow::Error> {
+    let token = generate_reset_token();
+    let expires_at = Utc::now() + chrono::Duration::from_std(ttl)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        hash_reset_token(&token),
+        user_id,
+        expires_at
+    )
+    .execute(executor)
+    .await
+    .context("Failed to store a password reset token.")?;
+    Ok(token)
+}
+
+/// Returns the user a token resets the password of, if it is still usable.
+#[tracing::instrument(name = "Check password reset token", skip(token, pool))]
+pub async fn check_password_reset_token(
+    token: &str,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT t.user_id
+        FROM password_reset_tokens t
+        JOIN users u ON u.user_id = t.user_id
+        WHERE
+            t.token_hash = $1 AND
+            t.used_at IS NULL AND
+            t.expires_at > now() AND
+            u.is_active
+        "#,
+        hash_reset_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a password reset token.")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+/// Consume the token and set the new password, logging the user out of
+/// every session. Returns `None` if the token is not usable.
+///
+/// Everything happens in a single transaction: the token is only used up
+/// if the password is actually changed.
+#[tracing::instrument(name = "Reset password", skip(token, password, hashing, pool))]
+pub async fn reset_password(
+    token: &str,
+    password: Secret<String>,
+    hashing: &PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let Some(row) = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens t
+        SET used_at = now()
+        FROM users u
+        WHERE
+            u.user_id = t.user_id AND
+            t.token_hash = $1 AND
+            t.used_at IS NULL AND
+            t.expires_at > now() AND
+            u.is_active
+        RETURNING t.user_id
+        "#,
+        hash_reset_token(token)
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to use a password reset token.")?
+    else {
+        return Ok(None);
+    };
+    change_password(row.user_id, password, hashing, &mut *transaction).await?;
+    invalidate_sessions(row.user_id, &mut transaction).await?;
+    // Older links sent to the same user must not work anymore either
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL"#,
+        row.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the remaining password reset tokens.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")?;
+    Ok(Some(row.user_id))
+}
+
+/// Delete the tokens that expired more than `retention` ago, used or not.
+#[tracing::instrument(skip(pool), err)]
+pub async fn purge_password_reset_tokens(
+    pool: &PgPool,
+    retention: Duration,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(retention)?;
+    let n_deleted = sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE expires_at < $1"#,
+        cutoff
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_deleted)
+}
@@ -0,0 +1,65 @@
+/// What an admin user is allowed to do, from least to most privileged.
+///
+/// Viewers can browse the admin area, editors can also publish and manage
+/// deliveries, owners can also manage the other users.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Owner, Role::Editor, Role::Viewer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "owner" => Ok(Role::Owner),
+            other => Err(format!("{} is not a valid role.", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Role;
+    use claims::assert_err;
+
+    #[test]
+    fn roles_round_trip_through_their_string_form() {
+        for role in Role::ALL {
+            assert_eq!(Role::try_from(role.as_str().to_string()), Ok(role));
+        }
+    }
+
+    #[test]
+    fn unknown_roles_are_rejected() {
+        assert_err!(Role::try_from("admin".to_string()));
+    }
+
+    #[test]
+    fn owners_outrank_editors_who_outrank_viewers() {
+        assert!(Role::Owner > Role::Editor);
+        assert!(Role::Editor > Role::Viewer);
+    }
+}
@@ -0,0 +1,190 @@
+use actix_web::http::header::USER_AGENT;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::utils::client_ip;
+
+/// Sessions remember the generation they were created in; bumping it logs
+/// the user out everywhere on their next request.
+#[tracing::instrument(name = "Get session generation", skip(pool))]
+pub async fn get_session_generation(user_id: Uuid, pool: &PgPool) -> Result<i32, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT session_generation FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the session generation of a user.")?;
+    Ok(row.session_generation)
+}
+
+#[tracing::instrument(name = "Invalidate all sessions of a user", skip(transaction))]
+pub async fn invalidate_sessions(
+    user_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE users SET session_generation = session_generation + 1 WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to invalidate the sessions of a user.")?;
+    sqlx::query!(
+        r#"UPDATE user_sessions SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL"#,
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to revoke the sessions of a user.")?;
+    Ok(())
+}
+
+/// Record a new session for `user_id`, with the user agent and IP address
+/// of the request that started it.
+#[tracing::instrument(name = "Start a user session", skip(request, pool))]
+pub async fn start_session(
+    user_id: Uuid,
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let session_id = Uuid::new_v4();
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|h| h.to_str().ok());
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions (
+            session_id, user_id, created_at, last_seen_at, user_agent, ip_address
+        )
+        VALUES ($1, $2, now(), now(), $3, $4)
+        "#,
+        session_id,
+        user_id,
+        user_agent,
+        client_ip(request)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new user session.")?;
+    Ok(session_id)
+}
+
+/// Bump the last seen time of a session. Returns `false` if it has been
+/// revoked or does not belong to `user_id`.
+#[tracing::instrument(name = "Touch a user session", skip(pool))]
+pub async fn touch_session(
+    session_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE user_sessions SET last_seen_at = now()
+        WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the last seen time of a session.")?
+    .rows_affected();
+    Ok(n_updated == 1)
+}
+
+pub struct UserSession {
+    pub session_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: String,
+}
+
+#[tracing::instrument(name = "List user sessions", skip(pool))]
+pub async fn list_sessions(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<UserSession>, anyhow::Error> {
+    let sessions = sqlx::query_as!(
+        UserSession,
+        r#"
+        SELECT session_id, created_at, last_seen_at, user_agent, ip_address
+        FROM user_sessions
+        WHERE user_id = $1 AND revoked_at IS NULL
+        ORDER BY last_seen_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list the sessions of a user.")?;
+    Ok(sessions)
+}
+
+/// Returns `false` if `user_id` has no such active session.
+#[tracing::instrument(name = "Revoke a user session", skip(pool))]
+pub async fn revoke_session(
+    user_id: Uuid,
+    session_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE user_sessions SET revoked_at = now()
+        WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke a user session.")?
+    .rows_affected();
+    Ok(n_updated == 1)
+}
+
+/// Revoke every session of `user_id` except `current_session_id`,
+/// returning how many were revoked.
+#[tracing::instrument(name = "Revoke the other user sessions", skip(executor))]
+pub async fn revoke_other_sessions(
+    user_id: Uuid,
+    current_session_id: Uuid,
+    executor: impl PgExecutor<'_>,
+) -> Result<u64, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE user_sessions SET revoked_at = now()
+        WHERE user_id = $1 AND session_id != $2 AND revoked_at IS NULL
+        "#,
+        user_id,
+        current_session_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to revoke the other sessions of a user.")?
+    .rows_affected();
+    Ok(n_updated)
+}
+
+/// Delete the sessions idle for longer than `session_ttl`: the session store
+/// has forgotten them already.
+#[tracing::instrument(skip(pool), err)]
+pub async fn purge_user_sessions(
+    pool: &PgPool,
+    session_ttl: std::time::Duration,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(session_ttl)?;
+    let n_deleted = sqlx::query!(
+        r#"DELETE FROM user_sessions WHERE last_seen_at < $1"#,
+        cutoff
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_deleted)
+}
@@ -0,0 +1,352 @@
+use anyhow::Context;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use super::middleware::tokens_match;
+
+const ISSUER: &str = "zero2prod";
+const STEP_SECONDS: u64 = 30;
+const N_RECOVERY_CODES: usize = 10;
+
+/// A fresh base32-encoded secret for RFC 6238 TOTP enrollment.
+pub fn generate_totp_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn totp(secret: &str, username: &str) -> Result<TOTP, anyhow::Error> {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|e| anyhow::anyhow!("Invalid TOTP secret: {:?}", e))?;
+    // Colons are reserved as the issuer separator in otpauth labels
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        STEP_SECONDS,
+        secret,
+        Some(ISSUER.into()),
+        username.replace(':', "_"),
+    )
+    .context("Failed to build the TOTP generator.")
+}
+
+/// The `otpauth://` URI authenticator apps scan to enroll.
+pub fn otpauth_uri(secret: &str, username: &str) -> Result<String, anyhow::Error> {
+    Ok(totp(secret, username)?.get_url())
+}
+
+/// Returns the time step `code` was generated for, if it matches the
+/// current one or its immediate neighbours (to tolerate clock drift).
+pub fn matching_totp_step(secret: &str, code: &str) -> Result<Option<u64>, anyhow::Error> {
+    // The account name is not part of the code computation
+    let totp = totp(secret, "")?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let current_step = now / STEP_SECONDS;
+    for step in [current_step - 1, current_step, current_step + 1] {
+        if tokens_match(&totp.generate(step * STEP_SECONDS), code) {
+            return Ok(Some(step));
+        }
+    }
+    Ok(None)
+}
+
+/// One-time codes to log in with when the authenticator is lost.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = thread_rng();
+    (0..N_RECOVERY_CODES)
+        .map(|_| {
+            let code: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .take(10)
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+// Recovery codes are random enough that a fast hash is sufficient.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+#[tracing::instrument(name = "Get TOTP secret", skip(pool))]
+pub async fn get_totp_secret(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_secret FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the TOTP secret of a user.")?;
+    Ok(row.totp_secret)
+}
+
+/// Turn on two-factor authentication, replacing any previous recovery codes.
+///
+/// `confirmed_step` is the time step of the code used to confirm the
+/// enrollment, so that it can't be replayed to log in.
+#[tracing::instrument(
+    name = "Enable two-factor authentication",
+    skip(secret, recovery_codes, pool)
+)]
+pub async fn enable_two_factor(
+    user_id: Uuid,
+    secret: &str,
+    confirmed_step: u64,
+    recovery_codes: &[String],
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"UPDATE users SET totp_secret = $1, totp_last_used_step = $2 WHERE user_id = $3"#,
+        secret,
+        confirmed_step as i64,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the TOTP secret.")?;
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the previous recovery codes.")?;
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_recovery_codes (user_id, code_hash)
+        SELECT $1, code_hash FROM UNNEST($2::text[]) AS code_hash
+        "#,
+        user_id,
+        &code_hashes
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable two-factor authentication.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"UPDATE users SET totp_secret = NULL, totp_last_used_step = NULL WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the TOTP secret.")?;
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable two-factor authentication.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Count unused recovery codes", skip(pool))]
+pub async fn count_unused_recovery_codes(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<i64, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT count(*) as "count!" FROM user_recovery_codes WHERE user_id = $1 AND used_at IS NULL"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the unused recovery codes.")?;
+    Ok(row.count)
+}
+
+/// Check the second factor of a login: either a TOTP code from the
+/// authenticator app or one of the recovery codes, each usable only once.
+#[tracing::instrument(name = "Validate second factor", skip(code, pool))]
+pub async fn validate_second_factor(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let code = code.trim();
+    let Some(secret) = get_totp_secret(user_id, pool).await? else {
+        return Ok(false);
+    };
+    if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+        let Some(step) = matching_totp_step(&secret, code)? else {
+            return Ok(false);
+        };
+        let n_updated = sqlx::query!(
+            r#"
+            UPDATE users
+            SET totp_last_used_step = $2
+            WHERE
+                user_id = $1 AND
+                (totp_last_used_step IS NULL OR totp_last_used_step < $2)
+            "#,
+            user_id,
+            step as i64
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record the last used TOTP step.")?
+        .rows_affected();
+        return Ok(n_updated == 1);
+    }
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE user_recovery_codes
+        SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to use a recovery code.")?
+    .rows_affected();
+    Ok(n_updated == 1)
+}
+
+/// Whether the second factor of `user_id` is locked out after too many
+/// wrong codes.
+#[tracing::instrument(name = "Check the second factor lockout", skip(pool))]
+pub async fn is_second_factor_locked(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_locked_until > now() AS locked FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to check the second factor lockout.")?;
+    Ok(row.locked.unwrap_or(false))
+}
+
+/// Count one more wrong code for `user_id`. Once `max_attempts` is reached,
+/// the second factor is locked out for `lockout` and the count starts over.
+/// Returns whether the user is now locked out.
+#[tracing::instrument(name = "Record a failed second factor", skip(pool))]
+pub async fn record_failed_second_factor(
+    user_id: Uuid,
+    max_attempts: i32,
+    lockout: std::time::Duration,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE users
+        SET
+            n_failed_totp_attempts = CASE
+                WHEN n_failed_totp_attempts + 1 >= $2 THEN 0
+                ELSE n_failed_totp_attempts + 1
+            END,
+            totp_locked_until = CASE
+                WHEN n_failed_totp_attempts + 1 >= $2 THEN now() + $3 * interval '1 second'
+                ELSE totp_locked_until
+            END
+        WHERE user_id = $1
+        RETURNING totp_locked_until > now() AS locked
+        "#,
+        user_id,
+        max_attempts,
+        lockout.as_secs_f64()
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to record a failed second factor.")?;
+    Ok(row.locked.unwrap_or(false))
+}
+
+#[tracing::instrument(name = "Clear failed second factors", skip(pool))]
+pub async fn clear_failed_second_factors(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET n_failed_totp_attempts = 0, totp_locked_until = NULL
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to clear the failed second factors.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_recovery_codes, generate_totp_secret, hash_recovery_code};
+    use super::{matching_totp_step, otpauth_uri, totp};
+
+    #[test]
+    fn the_current_code_matches() {
+        let secret = generate_totp_secret();
+        let code = totp(&secret, "").unwrap().generate_current().unwrap();
+        assert!(matching_totp_step(&secret, &code).unwrap().is_some());
+    }
+
+    #[test]
+    fn codes_from_a_long_time_ago_do_not_match() {
+        let secret = generate_totp_secret();
+        let code = totp(&secret, "").unwrap().generate(0);
+        assert!(matching_totp_step(&secret, &code).unwrap().is_none());
+    }
+
+    #[test]
+    fn the_otpauth_uri_names_the_issuer_and_the_user() {
+        let uri = otpauth_uri(&generate_totp_secret(), "ursula").unwrap();
+        assert!(uri.starts_with("otpauth://totp/zero2prod:ursula?"));
+    }
+
+    #[test]
+    fn recovery_codes_are_unique() {
+        let mut codes = generate_recovery_codes();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), 10);
+    }
+
+    #[test]
+    fn recovery_code_hashes_ignore_case_and_separators() {
+        assert_eq!(
+            hash_recovery_code("abcde-12345"),
+            hash_recovery_code("ABCDE12345")
+        );
+    }
+}
@@ -1,20 +1,28 @@
 use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailSender, PostmarkEmailClient, SmtpEmailClient};
 use config::{Config, File};
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
+use std::sync::Arc;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub issue_delivery: IssueDeliverySettings,
+    pub login_throttling: LoginThrottlingSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub password_policy: PasswordPolicySettings,
+    pub session: SessionSettings,
+    /// Only used by the `redis` session backend
     pub redis_uri: Secret<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct DatabaseSettings {
     pub username: String,
     pub password: Secret<String>,
@@ -25,22 +33,154 @@ pub struct DatabaseSettings {
     pub require_ssl: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_ttl_hours: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub unconfirmed_subscriber_retention_days: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub resend_confirmation_address_cooldown_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub resend_confirmation_ip_cooldown_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub password_reset_token_ttl_minutes: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub personal_data_link_ttl_minutes: u64,
+    /// Reverse proxies whose `X-Forwarded-For` header is believed
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct EmailClientSettings {
+    pub provider: EmailProvider,
+    /// The API base URL for HTTP providers, the connection URL for SMTP
+    /// (e.g. `smtp://127.0.0.1:1025` or `smtps://smtp.example.com`).
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub timeout_milliseconds: u64,
+    /// Only used by the SMTP provider, which logs in with this username
+    /// and `authorization_token` as password when it is set.
+    #[serde(default)]
+    pub smtp_username: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    Mailgun,
+    Postmark,
+    Smtp,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct IssueDeliverySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: i16,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub initial_backoff_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_backoff_seconds: u64,
+}
+
+/// Failed logins are counted per username and per client IP. Each failure
+/// delays the next attempt a bit more, until the key gets locked out.
+#[derive(Deserialize, Clone)]
+pub struct LoginThrottlingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_username: i32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_ip: i32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub initial_delay_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_seconds: u64,
+}
+
+/// Argon2id cost parameters for new password hashes. Stored hashes computed
+/// with weaker parameters are upgraded when their owner logs in.
+#[derive(Deserialize, Clone)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+    }
+}
+
+/// What new passwords must look like.
+#[derive(Deserialize, Clone)]
+pub struct PasswordPolicySettings {
+    /// Lengths are counted in graphemes
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_length: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_length: usize,
+    /// Minimum zxcvbn score, from 0 (too guessable) to 4 (very unguessable)
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_strength: u8,
+}
+
+/// Where session state is kept and what the session cookie looks like.
+#[derive(Deserialize, Clone)]
+pub struct SessionSettings {
+    pub backend: SessionBackend,
+    /// How long session state is kept without any activity
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub ttl_minutes: i64,
+    pub cookie_name: String,
+    pub cookie_same_site: CookieSameSite,
+    pub cookie_secure: bool,
+    #[serde(default)]
+    pub cookie_domain: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionBackend {
+    Redis,
+    Postgres,
+    /// Lost on restart, for tests and local development
+    Memory,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<CookieSameSite> for actix_web::cookie::SameSite {
+    fn from(value: CookieSameSite) -> Self {
+        match value {
+            CookieSameSite::Strict => Self::Strict,
+            CookieSameSite::Lax => Self::Lax,
+            CookieSameSite::None => Self::None,
+        }
+    }
+}
+
+impl SessionSettings {
+    pub fn ttl(&self) -> actix_web::cookie::time::Duration {
+        actix_web::cookie::time::Duration::minutes(self.ttl_minutes)
+    }
 }
 
 impl Settings {
@@ -66,6 +206,32 @@ impl Settings {
     }
 }
 
+impl ApplicationSettings {
+    pub fn subscription_token_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.subscription_token_ttl_hours * 60 * 60)
+    }
+
+    pub fn unconfirmed_subscriber_retention(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.unconfirmed_subscriber_retention_days * 24 * 60 * 60)
+    }
+
+    pub fn resend_confirmation_address_cooldown(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.resend_confirmation_address_cooldown_seconds)
+    }
+
+    pub fn resend_confirmation_ip_cooldown(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.resend_confirmation_ip_cooldown_seconds)
+    }
+
+    pub fn password_reset_token_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.password_reset_token_ttl_minutes * 60)
+    }
+
+    pub fn personal_data_link_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.personal_data_link_ttl_minutes * 60)
+    }
+}
+
 impl DatabaseSettings {
     pub fn without_db(&self) -> PgConnectOptions {
         let ssl_mode = if self.require_ssl {
@@ -90,6 +256,34 @@ impl DatabaseSettings {
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> Arc<dyn EmailSender> {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        match self.provider {
+            EmailProvider::Mailgun => Arc::new(EmailClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailProvider::Postmark => Arc::new(PostmarkEmailClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailProvider::Smtp => {
+                let credentials = self
+                    .smtp_username
+                    .map(|username| (username, self.authorization_token));
+                Arc::new(
+                    SmtpEmailClient::new(&self.base_url, sender_email, credentials, timeout)
+                        .expect("Invalid SMTP connection URL."),
+                )
+            }
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
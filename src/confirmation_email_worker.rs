@@ -0,0 +1,220 @@
+use crate::{
+    configuration::{IssueDeliverySettings, Settings},
+    domain::SubscriberEmail,
+    email_client::EmailSender,
+    issue_delivery_worker::{backoff, ExecutionOutcome},
+    routes::send_confirmation_email,
+};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+pub async fn run_confirmation_worker_until_stopped(
+    connection_pool: PgPool,
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let email_client = configuration.email_client.client();
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.issue_delivery,
+        configuration.application.base_url,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    settings: IssueDeliverySettings,
+    base_url: String,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_confirmation_task(&pool, email_client.as_ref(), &settings, &base_url)
+            .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Send one queued confirmation email, if any.
+///
+/// Emails that still fail once attempts run out are dropped, and noted in the
+/// import report for imported subscribers: the subscriber can ask for another
+/// one from the public form.
+#[tracing::instrument(
+    skip_all,
+    fields(import_id = tracing::field::Empty, line_number = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_confirmation_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    settings: &IssueDeliverySettings,
+    base_url: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    if let (Some(import_id), Some(line_number)) = (task.import_id, task.line_number) {
+        Span::current()
+            .record("import_id", display(import_id))
+            .record("line_number", line_number);
+    }
+    if task.status != "pending_confirmation" {
+        tracing::info!(
+            "Skipping a confirmation email. The subscriber is no longer pending confirmation."
+        );
+        delete_task(transaction, &task).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    let email = match SubscriberEmail::parse(task.email.clone()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Dropping a confirmation email. \
+                 The subscriber's stored contact details are invalid",
+            );
+            give_up_on_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    if let Err(e) =
+        send_confirmation_email(email_client, &email, base_url, &task.subscription_token).await
+    {
+        if task.n_retries + 1 >= settings.max_attempts {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a confirmation email. No attempts left, dropping it.",
+            );
+            give_up_on_task(transaction, &task).await?;
+        } else {
+            let backoff = backoff(settings, task.n_retries);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a confirmation email. Retrying in {} seconds.",
+                backoff.as_secs(),
+            );
+            retry_task_later(transaction, &task, &format!("{:?}", e), backoff).await?;
+        }
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    delete_task(transaction, &task).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    subscription_token: String,
+    import_id: Option<Uuid>,
+    line_number: Option<i64>,
+    email: String,
+    status: String,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT q.subscription_token, q.import_id, q.line_number, s.email, s.status, q.n_retries
+        FROM confirmation_email_queue q
+        JOIN subscription_tokens t ON t.subscription_token = q.subscription_token
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE q.execute_after <= now()
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    Ok(r.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(mut transaction: PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM confirmation_email_queue WHERE subscription_token = $1"#,
+        task.subscription_token
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Drop the task and flag its row in the import report, if it has one.
+#[tracing::instrument(skip_all)]
+async fn give_up_on_task(mut transaction: PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    // A no-op outside of imports
+    sqlx::query!(
+        r#"
+        UPDATE subscriber_import_rows
+        SET message = 'The confirmation email could not be sent.'
+        WHERE import_id = $1 AND line_number = $2
+        "#,
+        task.import_id as Option<Uuid>,
+        task.line_number as Option<i64>
+    )
+    .execute(&mut *transaction)
+    .await?;
+    delete_task(transaction, task).await
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_task_later(
+    mut transaction: PgTransaction,
+    task: &Task,
+    error: &str,
+    backoff: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = chrono::Utc::now() + chrono::Duration::from_std(backoff)?;
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET
+            n_retries = n_retries + 1,
+            last_error = $2,
+            execute_after = $3
+        WHERE subscription_token = $1
+        "#,
+        task.subscription_token,
+        error,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Queue the confirmation email carrying `subscription_token`.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO confirmation_email_queue (subscription_token) VALUES ($1)"#,
+        subscription_token
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use super::EmailSender;
 use crate::domain::SubscriberEmail;
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
@@ -48,14 +49,18 @@ impl EmailClient {
             authorization_token,
         }
     }
+}
 
-    pub async fn send_email(
+#[async_trait::async_trait]
+impl EmailSender for EmailClient {
+    async fn send_email_with_headers(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+        headers: &[(&str, &str)],
+    ) -> Result<(), anyhow::Error> {
         let url = format!("{}/email", self.base_url);
 
         let request_body = SendEmailRequest {
@@ -65,12 +70,17 @@ impl EmailClient {
             html_body: html_content,
             text_body: text_content,
         };
+        let mut params = request_body.params();
+        // Mailgun picks custom MIME headers up from `h:`-prefixed fields
+        for (name, value) in headers {
+            params.insert(format!("h:{}", name), value);
+        }
 
         let _builder = self
             .http_client
             .post(url)
             .basic_auth("api", Some(self.authorization_token.expose_secret()))
-            .form(&request_body.params())
+            .form(&params)
             .send()
             .await?
             .error_for_status()?;
@@ -82,7 +92,7 @@ impl EmailClient {
 mod tests {
     const TIMEOUT_MILLISECONDS: u64 = 200;
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, EmailSender};
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
@@ -0,0 +1,38 @@
+mod mailgun;
+mod postmark;
+mod smtp;
+
+use crate::domain::SubscriberEmail;
+
+pub use mailgun::EmailClient;
+pub use postmark::PostmarkEmailClient;
+pub use smtp::SmtpEmailClient;
+
+/// A backend able to deliver a single email on our behalf.
+///
+/// Handlers and the delivery worker only ever see a `dyn EmailSender`:
+/// which provider sits behind it is picked from `EmailClientSettings::provider`.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// Same as `send_email`, attaching extra `(name, value)` headers
+    /// (e.g. `List-Unsubscribe`) to the outgoing message.
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), anyhow::Error>;
+}
@@ -0,0 +1,206 @@
+use super::EmailSender;
+use crate::domain::SubscriberEmail;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+
+/// Delivers emails over SMTP.
+///
+/// The relay is described by a connection URL, e.g. `smtp://127.0.0.1:1025`
+/// for a plaintext relay, `smtp://host:587?tls=required` for STARTTLS or
+/// `smtps://host:465` for implicit TLS.
+pub struct SmtpEmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpEmailClient {
+    pub fn new(
+        connection_url: &str,
+        sender: SubscriberEmail,
+        credentials: Option<(String, Secret<String>)>,
+        timeout: std::time::Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let mut builder =
+            AsyncSmtpTransport::<Tokio1Executor>::from_url(connection_url)?.timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpEmailClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), anyhow::Error> {
+        let mut builder = Message::builder()
+            .from(self.sender.as_ref().parse::<Mailbox>()?)
+            .to(recipient.as_ref().parse::<Mailbox>()?)
+            .subject(subject);
+        for (name, value) in headers {
+            let name = HeaderName::new_from_ascii(name.to_string())?;
+            builder = builder.raw_header(HeaderValue::new(name, value.to_string()));
+        }
+        let message = builder.multipart(MultiPart::alternative_plain_html(
+            text_content.to_owned(),
+            html_content.to_owned(),
+        ))?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, SmtpEmailClient};
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A minimal SMTP stand-in: it accepts a single session, records the
+    /// transcript and answers every `RCPT TO` with `rcpt_reply`.
+    async fn spawn_smtp_server(rcpt_reply: &'static str) -> (u16, Arc<Mutex<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let transcript = Arc::new(Mutex::new(String::new()));
+        let recorded = transcript.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut in_data = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                recorded.lock().unwrap().push_str(&format!("{}\n", line));
+                let reply = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    "250 Queued\r\n"
+                } else {
+                    match line.split_whitespace().next().unwrap_or_default() {
+                        "EHLO" | "HELO" => "250 localhost\r\n",
+                        "MAIL" => "250 OK\r\n",
+                        "RCPT" => rcpt_reply,
+                        "DATA" => {
+                            in_data = true;
+                            "354 End data with <CR><LF>.<CR><LF>\r\n"
+                        }
+                        "QUIT" => {
+                            writer.write_all(b"221 Bye\r\n").await.unwrap();
+                            break;
+                        }
+                        _ => "250 OK\r\n",
+                    }
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        (port, transcript)
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(port: u16) -> SmtpEmailClient {
+        SmtpEmailClient::new(
+            &format!("smtp://127.0.0.1:{}", port),
+            email(),
+            None,
+            std::time::Duration::from_millis(500),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_the_message_to_the_relay() {
+        // Arrange
+        let (port, transcript) = spawn_smtp_server("250 OK\r\n").await;
+        let email_client = email_client(port);
+        let recipient = email();
+        // Act
+        let outcome = email_client
+            .send_email(&recipient, "A subject", "<p>Html</p>", "Plain text")
+            .await;
+        // Assert
+        assert_ok!(outcome);
+        let transcript = transcript.lock().unwrap();
+        assert!(transcript.contains(&format!("RCPT TO:<{}>", recipient)));
+        assert!(transcript.contains("Subject: A subject"));
+        assert!(transcript.contains("Plain text"));
+        assert!(transcript.contains("<p>Html</p>"));
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_adds_the_headers_to_the_message() {
+        // Arrange
+        let (port, transcript) = spawn_smtp_server("250 OK\r\n").await;
+        let email_client = email_client(port);
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                "A subject",
+                "<p>Html</p>",
+                "Plain text",
+                &[("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")],
+            )
+            .await;
+        // Assert
+        assert_ok!(outcome);
+        assert!(transcript
+            .lock()
+            .unwrap()
+            .contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_relay_rejects_the_recipient() {
+        // Arrange
+        let (port, _) = spawn_smtp_server("550 No such user\r\n").await;
+        let email_client = email_client(port);
+        // Act
+        let outcome = email_client
+            .send_email(&email(), "A subject", "<p>Html</p>", "Plain text")
+            .await;
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_relay_is_unreachable() {
+        // Arrange
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let email_client = email_client(port);
+        // Act
+        let outcome = email_client
+            .send_email(&email(), "A subject", "<p>Html</p>", "Plain text")
+            .await;
+        // Assert
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,366 @@
+use crate::{
+    configuration::{IssueDeliverySettings, Settings},
+    domain::SubscriberEmail,
+    email_client::EmailSender,
+    routes::unsubscribe_link,
+    startup::HmacSecret,
+};
+use rand::Rng;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(
+    connection_pool: PgPool,
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let email_client = configuration.email_client.client();
+    let hmac_secret = HmacSecret(configuration.application.hmac_secret);
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.issue_delivery,
+        configuration.application.base_url,
+        hmac_secret,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    settings: IssueDeliverySettings,
+    base_url: String,
+    hmac_secret: HmacSecret,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(
+            &pool,
+            email_client.as_ref(),
+            &settings,
+            &base_url,
+            &hmac_secret,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=tracing::field::Empty,
+        subscriber_email=tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    settings: &IssueDeliverySettings,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(pool).await?;
+    if task.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (transaction, task) = task.unwrap();
+    Span::current()
+        .record("newsletter_issue_id", display(task.newsletter_issue_id))
+        .record("subscriber_email", display(&task.subscriber_email));
+    let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Dead-lettering a delivery to a confirmed subscriber. \
+                 Their stored contact details are invalid",
+            );
+            dead_letter_task(transaction, &task, &e).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let Some(subscriber_id) = get_confirmed_subscriber_id(pool, &task.subscriber_email).await?
+    else {
+        tracing::info!("Skipping a delivery. The subscriber is no longer confirmed.");
+        delete_task(transaction, &task).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    };
+    let issue = get_issue(pool, task.newsletter_issue_id).await?;
+    if let Err(e) = send_with_unsubscribe_link(
+        email_client,
+        &email,
+        &issue.title,
+        &issue.html_content,
+        &issue.text_content,
+        &unsubscribe_link(base_url, subscriber_id, hmac_secret),
+    )
+    .await
+    {
+        let error = format!("{:?}", e);
+        if task.n_retries + 1 >= settings.max_attempts {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. \
+                 No attempts left, moving it to the dead letters.",
+            );
+            dead_letter_task(transaction, &task, &error).await?;
+        } else {
+            let backoff = backoff(settings, task.n_retries);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. \
+                 Retrying in {} seconds.",
+                backoff.as_secs(),
+            );
+            retry_task_later(transaction, &task, &error, backoff).await?;
+        }
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    delete_task(transaction, &task).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Send `html_content` and `text_content` with a footer pointing to
+/// `unsubscribe_link`, advertised to mail clients through the RFC 8058 headers.
+pub(crate) async fn send_with_unsubscribe_link(
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    unsubscribe_link: &str,
+) -> Result<(), anyhow::Error> {
+    let html_content = format!(
+        "{}<p><a href=\"{}\">Unsubscribe</a></p>",
+        html_content,
+        htmlescape::encode_attribute(unsubscribe_link)
+    );
+    let text_content = format!(
+        "{}\n\n--\nTo unsubscribe, visit {}",
+        text_content, unsubscribe_link
+    );
+    // RFC 8058: mail clients POST `List-Unsubscribe=One-Click` to the link
+    let list_unsubscribe = format!("<{}>", unsubscribe_link);
+    let headers = [
+        ("List-Unsubscribe", list_unsubscribe.as_str()),
+        ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+    ];
+    email_client
+        .send_email_with_headers(recipient, subject, &html_content, &text_content, &headers)
+        .await
+}
+
+/// Exponential backoff with jitter: the delay doubles with every failed
+/// attempt (up to `max_backoff_seconds`) and is then drawn at random from
+/// the upper half of that window, so retries of the same issue spread out.
+pub(crate) fn backoff(settings: &IssueDeliverySettings, n_retries: i16) -> Duration {
+    let exponent = u32::try_from(n_retries).unwrap_or(0).min(32);
+    let delay = settings
+        .initial_backoff_seconds
+        .saturating_mul(2u64.saturating_pow(exponent))
+        .min(settings.max_backoff_seconds);
+    let jittered = rand::thread_rng().gen_range(delay / 2..=delay);
+    Duration::from_secs(jittered)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    // `SKIP LOCKED` lets concurrent workers pick different rows
+    // instead of waiting on the one locked by another worker.
+    let r = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE
+            dead_lettered_at IS NULL AND
+            execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    Ok(r.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(mut transaction: PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_task_later(
+    mut transaction: PgTransaction,
+    task: &Task,
+    error: &str,
+    backoff: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = chrono::Utc::now() + chrono::Duration::from_std(backoff)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            last_error = $3,
+            execute_after = $4
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        error,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    mut transaction: PgTransaction,
+    task: &Task,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            last_error = $3,
+            dead_lettered_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        error
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber_id(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE email = $1 AND status = 'confirmed'
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(r.map(|r| r.id))
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::backoff;
+    use crate::configuration::IssueDeliverySettings;
+    use std::time::Duration;
+
+    fn settings() -> IssueDeliverySettings {
+        IssueDeliverySettings {
+            max_attempts: 5,
+            initial_backoff_seconds: 30,
+            max_backoff_seconds: 3600,
+        }
+    }
+
+    #[test]
+    fn the_first_retry_waits_at_most_the_initial_backoff() {
+        let delay = backoff(&settings(), 0);
+        assert!(delay >= Duration::from_secs(15));
+        assert!(delay <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn the_backoff_grows_exponentially() {
+        let delay = backoff(&settings(), 3);
+        assert!(delay >= Duration::from_secs(120));
+        assert!(delay <= Duration::from_secs(240));
+    }
+
+    #[test]
+    fn the_backoff_is_capped() {
+        let delay = backoff(&settings(), i16::MAX);
+        assert!(delay >= Duration::from_secs(1800));
+        assert!(delay <= Duration::from_secs(3600));
+    }
+}
@@ -1,9 +1,19 @@
+pub mod audit;
 pub mod authentication;
 pub mod configuration;
+pub mod confirmation_email_worker;
 pub mod domain;
 pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod password_reset_email_worker;
+pub mod personal_data;
+pub mod personal_data_email_worker;
 pub mod routes;
 pub mod session_state;
+pub mod session_store;
 pub mod startup;
+pub mod subscription_cleanup_worker;
 pub mod telemetry;
 pub mod utils;
+pub mod welcome_email_worker;
@@ -1,3 +1,5 @@
+use std::fmt::{Debug, Display};
+use tokio::task::JoinError;
 use zero2prod::configuration::Settings;
 use zero2prod::startup::Application;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
@@ -8,6 +10,38 @@ async fn main() -> anyhow::Result<()> {
     init_subscriber(subscriber);
     let configuration = Settings::new().expect("Failed to read configuration.");
     let application = Application::build(&configuration, None).await?;
-    application.run_until_stopped().await?;
+    let (worker_names, worker_tasks): (Vec<_>, Vec<_>) =
+        application.spawn_workers().into_iter().unzip();
+    let application_task = tokio::spawn(application.run_until_stopped());
+
+    // Whichever task exits first takes the whole process down with it.
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        (o, i, _) = futures_util::future::select_all(worker_tasks) => report_exit(worker_names[i], o),
+    };
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "'{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
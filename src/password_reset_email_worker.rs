@@ -0,0 +1,190 @@
+use crate::{
+    authentication::{create_password_reset_token, get_active_user_by_email},
+    configuration::{IssueDeliverySettings, Settings},
+    domain::SubscriberEmail,
+    email_client::EmailSender,
+    issue_delivery_worker::{backoff, ExecutionOutcome},
+    routes::send_password_reset_email,
+};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+pub async fn run_password_reset_worker_until_stopped(
+    connection_pool: PgPool,
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let email_client = configuration.email_client.client();
+    let token_ttl = configuration.application.password_reset_token_ttl();
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.issue_delivery,
+        configuration.application.base_url,
+        token_ttl,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    settings: IssueDeliverySettings,
+    base_url: String,
+    token_ttl: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_password_reset_task(
+            &pool,
+            email_client.as_ref(),
+            &settings,
+            &base_url,
+            token_ttl,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Email one queued password reset link, if an active user has its address.
+///
+/// Requests are dropped once attempts run out: the user can make another one
+/// from the public form.
+#[tracing::instrument(skip_all, fields(task_id = tracing::field::Empty), err)]
+pub async fn try_execute_password_reset_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    settings: &IssueDeliverySettings,
+    base_url: &str,
+    token_ttl: Duration,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("task_id", display(task.id));
+    let Some(user) = get_active_user_by_email(&task.email, pool).await? else {
+        tracing::info!("Dropping a password reset request. No active user has its address.");
+        delete_task(transaction, &task).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    };
+    let recipient = match SubscriberEmail::parse(user.email) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Dropping a password reset request. The user's stored email is invalid",
+            );
+            delete_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let token = create_password_reset_token(user.user_id, token_ttl, &mut *transaction).await?;
+    if let Err(e) = send_password_reset_email(
+        email_client,
+        &recipient,
+        &user.username,
+        base_url,
+        &token,
+        token_ttl.as_secs() / 60,
+    )
+    .await
+    {
+        if task.n_retries + 1 >= settings.max_attempts {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a password reset email. No attempts left, dropping it.",
+            );
+            delete_task(transaction, &task).await?;
+        } else {
+            let backoff = backoff(settings, task.n_retries);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a password reset email. Retrying in {} seconds.",
+                backoff.as_secs(),
+            );
+            retry_task_later(transaction, &task, &format!("{:?}", e), backoff).await?;
+        }
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    delete_task(transaction, &task).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    id: Uuid,
+    email: String,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT id, email, n_retries
+        FROM password_reset_email_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    Ok(r.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(mut transaction: PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM password_reset_email_queue WHERE id = $1"#,
+        task.id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_task_later(
+    mut transaction: PgTransaction,
+    task: &Task,
+    error: &str,
+    backoff: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(backoff)?;
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_email_queue
+        SET
+            n_retries = n_retries + 1,
+            last_error = $2,
+            execute_after = $3
+        WHERE id = $1
+        "#,
+        task.id,
+        error,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,488 @@
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgExecutor, PgPool, Postgres, Transaction};
+use std::collections::HashSet;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::routes::PersonalDataRequest;
+
+/// What an erased address is remembered by.
+///
+/// Addresses are compared case-insensitively throughout, so the hash is taken
+/// on the lowercased address.
+pub fn suppression_hash(email: &str) -> String {
+    hex::encode(Sha256::digest(email.trim().to_lowercase().as_bytes()))
+}
+
+/// The `confirmation_resend_throttle` key of personal data requests for an
+/// address. It is hashed, like erased addresses, not to keep a trace of
+/// addresses we hold nothing on.
+pub fn personal_data_throttle_key(email: &str) -> String {
+    format!("personal-data:address:{}", suppression_hash(email))
+}
+
+/// The `confirmation_resend_throttle` keys derived from an address.
+pub fn address_throttle_keys(email: &str) -> Vec<String> {
+    vec![
+        format!("address:{}", email.trim().to_lowercase()),
+        personal_data_throttle_key(email),
+    ]
+}
+
+/// Queue the link to carry out `request`, for the personal data email worker.
+#[tracing::instrument(skip(transaction, email))]
+pub async fn enqueue_personal_data_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    request: PersonalDataRequest,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO personal_data_email_queue (id, email, request)
+        VALUES ($1, $2, $3)
+        "#,
+        Uuid::new_v4(),
+        email,
+        request.as_str()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+fn generate_link_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+// Only the hash is stored, a leaked table can't be used to follow the links.
+fn hash_link_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Issue the token of a link to carry out `request` on `email`, valid for `ttl`.
+#[tracing::instrument(skip(executor, email))]
+pub async fn create_personal_data_link_token(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    request: PersonalDataRequest,
+    ttl: Duration,
+) -> Result<String, anyhow::Error> {
+    let token = generate_link_token();
+    let expires_at = Utc::now() + chrono::Duration::from_std(ttl)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO personal_data_links (token_hash, email, request, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_link_token(&token),
+        email,
+        request.as_str(),
+        expires_at
+    )
+    .execute(executor)
+    .await
+    .context("Failed to store a personal data link.")?;
+    Ok(token)
+}
+
+/// Delete the links that expired, right away: they name an address.
+#[tracing::instrument(skip(pool), err)]
+pub async fn purge_personal_data_links(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_deleted = sqlx::query!(r#"DELETE FROM personal_data_links WHERE expires_at < now()"#)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    Ok(n_deleted)
+}
+
+/// The address a link token was issued for, if it carries out `request` and
+/// hasn't expired.
+#[tracing::instrument(skip(pool, token))]
+pub async fn check_personal_data_link_token(
+    pool: &PgPool,
+    token: &str,
+    request: PersonalDataRequest,
+) -> Result<Option<String>, anyhow::Error> {
+    let email = sqlx::query!(
+        r#"
+        SELECT email FROM personal_data_links
+        WHERE token_hash = $1 AND request = $2 AND expires_at > now()
+        "#,
+        hash_link_token(token),
+        request.as_str()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a personal data link.")?
+    .map(|r| r.email);
+    Ok(email)
+}
+
+/// Whether we hold anything at all on `email`.
+#[tracing::instrument(name = "Check for personal data", skip(pool, email))]
+pub async fn holds_personal_data(pool: &PgPool, email: &str) -> Result<bool, anyhow::Error> {
+    let found = sqlx::query!(
+        r#"
+        SELECT
+            EXISTS(SELECT 1 FROM subscriptions WHERE lower(email) = lower($1)) OR
+            EXISTS(SELECT 1 FROM issue_delivery_queue WHERE lower(subscriber_email) = lower($1)) OR
+            EXISTS(SELECT 1 FROM subscriber_import_rows WHERE lower(email) = lower($1))
+            AS "found!"
+        "#,
+        email
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look for personal data.")?
+    .found;
+    Ok(found)
+}
+
+pub struct PersonalData {
+    /// The subscriptions found for the address
+    pub subscriber_ids: Vec<Uuid>,
+    /// What we hold on it, for its owner
+    pub document: serde_json::Value,
+}
+
+/// Every row tied to `email`.
+///
+/// Along with the subscriptions themselves, this covers their confirmation
+/// tokens, queued emails, resend throttling, import reports and the audit
+/// events that name them.
+#[tracing::instrument(name = "Export personal data", skip(pool, email))]
+pub async fn export_personal_data(
+    pool: &PgPool,
+    email: &str,
+) -> Result<PersonalData, anyhow::Error> {
+    let subscriptions = sqlx::query!(
+        r#"
+        SELECT id, email, name, status, subscribed_at, confirmed_at
+        FROM subscriptions
+        WHERE lower(email) = lower($1)
+        "#,
+        email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriptions of an address.")?;
+    let subscriber_ids: Vec<Uuid> = subscriptions.iter().map(|s| s.id).collect();
+
+    let tokens = sqlx::query!(
+        r#"
+        SELECT subscriber_id, subscription_token, created_at, expires_at
+        FROM subscription_tokens
+        WHERE subscriber_id = ANY($1)
+        ORDER BY created_at
+        "#,
+        &subscriber_ids
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the confirmation tokens of an address.")?;
+
+    let welcome_emails = sqlx::query!(
+        r#"
+        SELECT subscriber_id, n_retries, execute_after, last_error
+        FROM welcome_email_queue
+        WHERE subscriber_id = ANY($1)
+        "#,
+        &subscriber_ids
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the queued welcome emails of an address.")?;
+
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT
+            q.newsletter_issue_id, i.title, q.subscriber_email, q.n_retries,
+            q.execute_after, q.last_error, q.dead_lettered_at
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE lower(q.subscriber_email) = lower($1)
+        ORDER BY i.published_at
+        "#,
+        email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the queued deliveries of an address.")?;
+
+    let throttling = sqlx::query!(
+        r#"
+        SELECT throttle_key, last_sent_at
+        FROM confirmation_resend_throttle
+        WHERE throttle_key = ANY($1)
+        "#,
+        &address_throttle_keys(email)
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the resend throttling of an address.")?;
+
+    let import_rows = sqlx::query!(
+        r#"
+        SELECT r.import_id, i.started_at, r.line_number, r.email, r.outcome, r.message
+        FROM subscriber_import_rows r
+        JOIN subscriber_imports i USING (import_id)
+        WHERE lower(r.email) = lower($1)
+        ORDER BY i.started_at, r.line_number
+        "#,
+        email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the import reports of an address.")?;
+
+    let subscriber_targets: Vec<String> = subscriber_ids.iter().map(Uuid::to_string).collect();
+    let audit_events = sqlx::query!(
+        r#"
+        SELECT occurred_at, action, target
+        FROM audit_events
+        WHERE target = ANY($1)
+        ORDER BY occurred_at
+        "#,
+        &subscriber_targets
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the audit events of an address.")?;
+
+    let document = json!({
+        "email": email,
+        "exported_at": chrono::Utc::now().to_rfc3339(),
+        "subscriptions": subscriptions.iter().map(|s| json!({
+            "id": s.id,
+            "email": s.email,
+            "name": s.name,
+            "status": s.status,
+            "subscribed_at": s.subscribed_at.to_rfc3339(),
+            "confirmed_at": s.confirmed_at.map(|t| t.to_rfc3339()),
+        })).collect::<Vec<_>>(),
+        "subscription_tokens": tokens.iter().map(|t| json!({
+            "subscriber_id": t.subscriber_id,
+            "subscription_token": t.subscription_token,
+            "created_at": t.created_at.to_rfc3339(),
+            "expires_at": t.expires_at.to_rfc3339(),
+        })).collect::<Vec<_>>(),
+        "welcome_email_queue": welcome_emails.iter().map(|w| json!({
+            "subscriber_id": w.subscriber_id,
+            "n_retries": w.n_retries,
+            "execute_after": w.execute_after.to_rfc3339(),
+            "last_error": w.last_error,
+        })).collect::<Vec<_>>(),
+        "issue_deliveries": deliveries.iter().map(|d| json!({
+            "newsletter_issue_id": d.newsletter_issue_id,
+            "title": d.title,
+            "subscriber_email": d.subscriber_email,
+            "n_retries": d.n_retries,
+            "execute_after": d.execute_after.to_rfc3339(),
+            "last_error": d.last_error,
+            "dead_lettered_at": d.dead_lettered_at.map(|t| t.to_rfc3339()),
+        })).collect::<Vec<_>>(),
+        "email_throttling": throttling.iter().map(|t| json!({
+            "throttle_key": t.throttle_key,
+            "last_sent_at": t.last_sent_at.to_rfc3339(),
+        })).collect::<Vec<_>>(),
+        "imports": import_rows.iter().map(|r| json!({
+            "import_id": r.import_id,
+            "imported_at": r.started_at.to_rfc3339(),
+            "line_number": r.line_number,
+            "email": r.email,
+            "outcome": r.outcome,
+            "message": r.message,
+        })).collect::<Vec<_>>(),
+        "audit_events": audit_events.iter().map(|e| json!({
+            "occurred_at": e.occurred_at.to_rfc3339(),
+            "action": e.action,
+            "target": e.target,
+        })).collect::<Vec<_>>(),
+    });
+    Ok(PersonalData {
+        subscriber_ids,
+        document,
+    })
+}
+
+/// Remove everything tied to `email`, then remember its hash so that it
+/// isn't imported again. Returns the ids of the subscriptions removed.
+///
+/// Import reports keep their rows, for their counts to add up, but lose the
+/// address and the message that went with it. Audit events only name
+/// subscribers by id and are left alone: the log is append-only.
+#[tracing::instrument(name = "Erase personal data", skip(transaction, email))]
+pub async fn erase_personal_data(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<Vec<Uuid>, anyhow::Error> {
+    let subscriber_ids: Vec<Uuid> = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE lower(email) = lower($1) FOR UPDATE"#,
+        email
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to retrieve the subscriptions of an address.")?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)"#,
+        &subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the confirmation tokens of an address.")?;
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE lower(subscriber_email) = lower($1)"#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the queued deliveries of an address.")?;
+    sqlx::query!(
+        r#"DELETE FROM personal_data_links WHERE lower(email) = lower($1)"#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the personal data links of an address.")?;
+    sqlx::query!(
+        r#"DELETE FROM personal_data_email_queue WHERE lower(email) = lower($1)"#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the queued personal data links of an address.")?;
+    sqlx::query!(
+        r#"DELETE FROM confirmation_resend_throttle WHERE throttle_key = ANY($1)"#,
+        &address_throttle_keys(email)
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the resend throttling of an address.")?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriber_import_rows SET email = NULL, message = NULL
+        WHERE lower(email) = lower($1)
+        "#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to anonymize the import reports of an address.")?;
+    // Queued welcome emails go along, `ON DELETE CASCADE`
+    sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = ANY($1)"#,
+        &subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the subscriptions of an address.")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressed_emails (email_hash, suppressed_at)
+        VALUES ($1, now())
+        ON CONFLICT (email_hash) DO NOTHING
+        "#,
+        suppression_hash(email)
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to suppress an erased address.")?;
+    Ok(subscriber_ids)
+}
+
+/// The addresses among `emails` that were erased.
+#[tracing::instrument(name = "Find suppressed addresses", skip_all)]
+pub async fn find_suppressed<'a>(
+    executor: impl PgExecutor<'_>,
+    emails: impl IntoIterator<Item = &'a str>,
+) -> Result<HashSet<String>, anyhow::Error> {
+    let emails: Vec<&str> = emails.into_iter().collect();
+    let hashes: Vec<String> = emails.iter().map(|e| suppression_hash(e)).collect();
+    let suppressed: HashSet<String> = sqlx::query!(
+        r#"SELECT email_hash FROM suppressed_emails WHERE email_hash = ANY($1)"#,
+        &hashes
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to look up suppressed addresses.")?
+    .into_iter()
+    .map(|r| r.email_hash)
+    .collect();
+    Ok(emails
+        .into_iter()
+        .zip(hashes)
+        .filter(|(_, hash)| suppressed.contains(hash))
+        .map(|(email, _)| email.to_owned())
+        .collect())
+}
+
+/// Record `action` against each of `subscriber_ids`, or once without a
+/// target if the address had no subscription left.
+pub async fn record_personal_data_event(
+    connection: &mut PgConnection,
+    request: &HttpRequest,
+    actor_user_id: Option<Uuid>,
+    action: AuditAction,
+    subscriber_ids: &[Uuid],
+    details: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let targets: Vec<Option<String>> = if subscriber_ids.is_empty() {
+        vec![None]
+    } else {
+        subscriber_ids
+            .iter()
+            .map(|id| Some(id.to_string()))
+            .collect()
+    };
+    for target in targets {
+        record_audit_event(
+            &mut *connection,
+            request,
+            actor_user_id,
+            action,
+            target.as_deref(),
+            details,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::suppression_hash;
+
+    #[test]
+    fn the_suppression_hash_ignores_case_and_surrounding_spaces() {
+        assert_eq!(
+            suppression_hash(" Ursula@Example.com"),
+            suppression_hash("ursula@example.com")
+        );
+        assert_ne!(
+            suppression_hash("ursula@example.com"),
+            suppression_hash("ursula@example.org")
+        );
+    }
+
+    #[test]
+    fn the_suppression_hash_does_not_contain_the_address() {
+        let hash = suppression_hash("ursula@example.com");
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("ursula"));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    configuration::{IssueDeliverySettings, Settings},
+    domain::SubscriberEmail,
+    email_client::EmailSender,
+    issue_delivery_worker::{backoff, ExecutionOutcome},
+    personal_data::{create_personal_data_link_token, holds_personal_data},
+    routes::{personal_data_link, send_personal_data_link, PersonalDataRequest},
+};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+pub async fn run_personal_data_worker_until_stopped(
+    connection_pool: PgPool,
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let email_client = configuration.email_client.client();
+    let link_ttl = configuration.application.personal_data_link_ttl();
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.issue_delivery,
+        configuration.application.base_url,
+        link_ttl,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    settings: IssueDeliverySettings,
+    base_url: String,
+    link_ttl: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_personal_data_task(
+            &pool,
+            email_client.as_ref(),
+            &settings,
+            &base_url,
+            link_ttl,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Email one queued personal data link, if we hold anything on its address.
+///
+/// Requests are dropped once attempts run out: the subscriber can make
+/// another one from the public form.
+#[tracing::instrument(skip_all, fields(task_id = tracing::field::Empty), err)]
+pub async fn try_execute_personal_data_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    settings: &IssueDeliverySettings,
+    base_url: &str,
+    link_ttl: Duration,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("task_id", display(task.id));
+    if !holds_personal_data(pool, &task.email).await? {
+        tracing::info!("Dropping a personal data request. We hold nothing on its address.");
+        delete_task(transaction, &task).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    let (email, kind) = match (
+        SubscriberEmail::parse(task.email.clone()),
+        PersonalDataRequest::try_from(task.request.clone()),
+    ) {
+        (Ok(email), Ok(kind)) => (email, kind),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(
+                error.message = %e,
+                "Dropping a personal data request. It is invalid",
+            );
+            delete_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let token =
+        create_personal_data_link_token(&mut *transaction, email.as_ref(), kind, link_ttl).await?;
+    let link = personal_data_link(base_url, kind, &token);
+    if let Err(e) =
+        send_personal_data_link(email_client, &email, kind, &link, link_ttl.as_secs() / 60).await
+    {
+        if task.n_retries + 1 >= settings.max_attempts {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a personal data link. No attempts left, dropping it.",
+            );
+            delete_task(transaction, &task).await?;
+        } else {
+            let backoff = backoff(settings, task.n_retries);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a personal data link. Retrying in {} seconds.",
+                backoff.as_secs(),
+            );
+            retry_task_later(transaction, &task, &format!("{:?}", e), backoff).await?;
+        }
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    delete_task(transaction, &task).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    id: Uuid,
+    email: String,
+    request: String,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT id, email, request, n_retries
+        FROM personal_data_email_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    Ok(r.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(mut transaction: PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM personal_data_email_queue WHERE id = $1"#,
+        task.id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_task_later(
+    mut transaction: PgTransaction,
+    task: &Task,
+    error: &str,
+    backoff: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(backoff)?;
+    sqlx::query!(
+        r#"
+        UPDATE personal_data_email_queue
+        SET
+            n_retries = n_retries + 1,
+            last_error = $2,
+            execute_after = $3
+        WHERE id = $1
+        "#,
+        task.id,
+        error,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::authentication::{self, ApiScope, UserId};
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+/// The API tokens of the current user, with a form to create a new one.
+pub async fn list_api_tokens(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        // Messages echo back token names
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for t in authentication::list_api_tokens(**user_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        let last_used_at = t
+            .last_used_at
+            .map(|d| d.to_rfc3339())
+            .unwrap_or_else(|| "never".into());
+        let status = match t.revoked_at {
+            Some(revoked_at) => format!("revoked at {}", revoked_at.to_rfc3339()),
+            None => format!(
+                r#"<form action="/admin/api_tokens/{}/revoke" method="post">
+                        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                        <button type="submit">Revoke</button>
+                    </form>"#,
+                t.api_token_id
+            ),
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{name}</td>
+                <td>{scopes}</td>
+                <td>{created_at}</td>
+                <td>{last_used_at}</td>
+                <td>{status}</td>
+            </tr>"#,
+            name = encode_minimal(&t.name),
+            scopes = encode_minimal(&t.scopes.join(", ")),
+            created_at = t.created_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    let mut scopes_html = String::new();
+    for scope in ApiScope::ALL {
+        writeln!(
+            scopes_html,
+            r#"<label><input type="checkbox" name="scope" value="{scope}"> {scope}</label><br>"#,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API tokens</title>
+</head>
+<body>
+    {msg_html}
+    <p>API tokens let scripts act on your behalf, e.g. to publish newsletter issues
+    with <code>POST /api/v1/newsletters</code> and an <code>Authorization: Bearer</code> header.</p>
+    <table>
+        <tr>
+            <th>Name</th>
+            <th>Scopes</th>
+            <th>Created</th>
+            <th>Last used</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <form action="/admin/api_tokens" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <label>Name
+            <input type="text" placeholder="e.g. release-notes CI" name="name">
+        </label>
+        <br>
+        {scopes_html}
+        <button type="submit">Create token</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,84 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{self, ApiScope, UserId};
+use crate::utils::{e500, see_other};
+
+/// Form fields as key/value pairs: checkboxes repeat the `scope` key.
+type FormData = Vec<(String, String)>;
+
+#[tracing::instrument(name = "Create an API token", skip(form, pool), fields(user_id=%**user_id))]
+pub async fn create_api_token(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut name = String::new();
+    let mut scopes = Vec::new();
+    for (key, value) in form.0 {
+        match key.as_str() {
+            "name" => name = value.trim().to_owned(),
+            "scope" => match ApiScope::try_from(value) {
+                Ok(scope) if !scopes.contains(&scope) => scopes.push(scope),
+                Ok(_) => {}
+                Err(e) => {
+                    FlashMessage::error(e).send();
+                    return Ok(see_other("/admin/api_tokens"));
+                }
+            },
+            _ => {}
+        }
+    }
+    if name.is_empty() {
+        FlashMessage::error("The token name can't be empty.").send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    if scopes.is_empty() {
+        FlashMessage::error("Please pick at least one scope.").send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+
+    let (_, token) = authentication::create_api_token(**user_id, &name, &scopes, &pool)
+        .await
+        .map_err(e500)?;
+    // Rendered instead of redirecting: only a hash is stored, so this is
+    // the one chance to copy the token.
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API token created</title>
+</head>
+<body>
+    <p>The token {name} has been created. Copy it now, it won't be shown again:</p>
+    <p><code id="api-token">{token}</code></p>
+    <p><a href="/admin/api_tokens">&lt;- Back</a></p>
+</body>
+</html>"#,
+            name = encode_minimal(&name),
+        )))
+}
+
+#[tracing::instrument(name = "Revoke an API token", skip(pool), fields(user_id=%**user_id))]
+pub async fn revoke_api_token(
+    api_token_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if authentication::revoke_api_token(**user_id, *api_token_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The API token has been revoked.").send();
+    } else {
+        FlashMessage::error("There is no such active API token.").send();
+    }
+    Ok(see_other("/admin/api_tokens"))
+}
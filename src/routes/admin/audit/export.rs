@@ -0,0 +1,79 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+
+use super::FilterQuery;
+use crate::audit::{self, AuditFilter};
+use crate::routes::admin::download::{stream_download, take_csv, Chunk, CHUNK_SIZE};
+use crate::utils::{csv_cell, e400};
+
+/// Every event matching the filters as a CSV file, newest first.
+///
+/// Like the subscriber export, rows are read from the database as the
+/// response is written.
+#[tracing::instrument(name = "Export audit events", skip(filter_query, pool))]
+pub async fn export_audit_events(
+    filter_query: web::Query<FilterQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = filter_query.parse().map_err(e400)?;
+
+    let pool = pool.get_ref().clone();
+    let body =
+        stream_download(|sender| async move { try_write_events(&filter, &pool, &sender).await });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("audit_log.csv".into())],
+        })
+        .streaming(body))
+}
+
+async fn try_write_events(
+    filter: &AuditFilter,
+    pool: &PgPool,
+    sender: &mpsc::Sender<Chunk>,
+) -> Result<(), anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record([
+        "occurred_at",
+        "actor_user_id",
+        "actor_username",
+        "action",
+        "target",
+        "ip_address",
+        "details",
+    ])?;
+    let mut events = audit::fetch_audit_events(filter, None, None, pool);
+    while let Some(e) = events
+        .try_next()
+        .await
+        .context("Failed to read the audit events to export.")?
+    {
+        // Usernames, targets and details may come from user input: they must
+        // not be evaluated when the file is opened in a spreadsheet
+        writer.write_record([
+            e.occurred_at.to_rfc3339().as_str(),
+            &e.actor_user_id.map(|id| id.to_string()).unwrap_or_default(),
+            &csv_cell(e.actor_username.as_deref().unwrap_or_default()),
+            &csv_cell(&e.action),
+            &csv_cell(e.target.as_deref().unwrap_or_default()),
+            &csv_cell(e.ip_address.as_deref().unwrap_or_default()),
+            &csv_cell(e.details.as_deref().unwrap_or_default()),
+        ])?;
+        writer.flush()?;
+        if writer.get_ref().len() >= CHUNK_SIZE
+            && sender.send(Ok(take_csv(&mut writer)?)).await.is_err()
+        {
+            // The client went away
+            return Ok(());
+        }
+    }
+    let _ = sender.send(Ok(take_csv(&mut writer)?)).await;
+    Ok(())
+}
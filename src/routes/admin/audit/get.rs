@@ -0,0 +1,118 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use super::FilterQuery;
+use crate::audit::{self, AuditAction};
+use crate::utils::{e400, e500};
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct PageQuery {
+    before: Option<i64>,
+}
+
+/// The audit log, newest first, a page at a time.
+pub async fn list_audit_events(
+    filter_query: web::Query<FilterQuery>,
+    page: web::Query<PageQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = filter_query.parse().map_err(e400)?;
+    let events = audit::list_audit_events(&filter, page.before, Some(PAGE_SIZE), &pool)
+        .await
+        .map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for e in &events {
+        let actor = match (&e.actor_username, e.actor_user_id) {
+            (Some(username), _) => encode_minimal(username),
+            (None, Some(user_id)) => user_id.to_string(),
+            (None, None) => String::new(),
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{occurred_at}</td>
+                <td>{actor}</td>
+                <td>{action}</td>
+                <td>{target}</td>
+                <td>{ip_address}</td>
+                <td>{details}</td>
+            </tr>"#,
+            occurred_at = e.occurred_at.to_rfc3339(),
+            action = encode_minimal(&e.action),
+            target = encode_minimal(e.target.as_deref().unwrap_or_default()),
+            ip_address = encode_minimal(e.ip_address.as_deref().unwrap_or_default()),
+            details = encode_minimal(e.details.as_deref().unwrap_or_default()),
+        )
+        .unwrap();
+    }
+
+    let mut action_options = String::from(r#"<option value="">Any</option>"#);
+    for action in AuditAction::ALL {
+        let selected = if filter.action == Some(action) {
+            " selected"
+        } else {
+            ""
+        };
+        write!(
+            action_options,
+            r#"<option value="{action}"{selected}>{action}</option>"#
+        )
+        .unwrap();
+    }
+    let value_of = |v: &Option<String>| encode_minimal(v.as_deref().unwrap_or_default());
+    let query_string = filter_query.to_query_string();
+    // A full page means there may be older events
+    let older_link = match events.last() {
+        Some(last) if events.len() as i64 == PAGE_SIZE => format!(
+            r#"<a href="/admin/audit?{}&amp;before={}">Older events -&gt;</a>"#,
+            encode_minimal(&query_string),
+            last.audit_event_id
+        ),
+        _ => String::new(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Audit log</title>
+</head>
+<body>
+    <form action="/admin/audit" method="get">
+        <label>Action <select name="action">{action_options}</select></label>
+        <label>User <input type="text" name="actor" value="{actor}"></label>
+        <label>From <input type="date" name="since" value="{since}"></label>
+        <label>To <input type="date" name="until" value="{until}"></label>
+        <button type="submit">Filter</button>
+    </form>
+    <p><a href="/admin/audit/export?{export_query}">Export as CSV</a></p>
+    <table>
+        <tr>
+            <th>When</th>
+            <th>User</th>
+            <th>Action</th>
+            <th>Target</th>
+            <th>IP address</th>
+            <th>Details</th>
+        </tr>
+        {rows_html}
+    </table>
+    <p>{older_link}</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            actor = value_of(&filter_query.actor),
+            since = value_of(&filter_query.since),
+            until = value_of(&filter_query.until),
+            export_query = encode_minimal(&query_string),
+        )))
+}
@@ -0,0 +1,97 @@
+mod export;
+mod get;
+
+pub use export::export_audit_events;
+pub use get::list_audit_events;
+
+use crate::audit::{AuditAction, AuditFilter};
+use crate::utils::start_of_day;
+
+/// Filters as submitted by the form on `/admin/audit`: empty fields are ignored.
+#[derive(serde::Deserialize)]
+pub struct FilterQuery {
+    action: Option<String>,
+    actor: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+impl FilterQuery {
+    fn parse(&self) -> Result<AuditFilter, String> {
+        let non_empty = |v: &Option<String>| v.clone().filter(|v| !v.trim().is_empty());
+        Ok(AuditFilter {
+            action: non_empty(&self.action)
+                .map(AuditAction::try_from)
+                .transpose()?,
+            actor_username: non_empty(&self.actor).map(|a| a.trim().to_owned()),
+            since: non_empty(&self.since)
+                .map(|d| start_of_day(&d))
+                .transpose()?,
+            // `until` is inclusive: stop at the start of the next day
+            until: non_empty(&self.until)
+                .map(|d| start_of_day(&d).map(|t| t + chrono::Duration::days(1)))
+                .transpose()?,
+        })
+    }
+
+    /// The filters as a query string, to carry them over to other links.
+    fn to_query_string(&self) -> String {
+        [
+            ("action", &self.action),
+            ("actor", &self.actor),
+            ("since", &self.since),
+            ("until", &self.until),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            value
+                .as_deref()
+                .map(|v| format!("{}={}", key, urlencoding::encode(v)))
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FilterQuery;
+    use crate::audit::AuditAction;
+    use claims::assert_err;
+
+    fn query(action: &str, since: &str, until: &str) -> FilterQuery {
+        FilterQuery {
+            action: Some(action.into()),
+            actor: Some("".into()),
+            since: Some(since.into()),
+            until: Some(until.into()),
+        }
+    }
+
+    #[test]
+    fn empty_fields_are_ignored() {
+        let filter = query("", "", "").parse().unwrap();
+        assert!(filter.action.is_none());
+        assert!(filter.actor_username.is_none());
+        assert!(filter.since.is_none());
+        assert!(filter.until.is_none());
+    }
+
+    #[test]
+    fn the_until_date_is_inclusive() {
+        let filter = query("login_failed", "2024-08-01", "2024-08-01")
+            .parse()
+            .unwrap();
+        assert_eq!(filter.action, Some(AuditAction::LoginFailed));
+        assert_eq!(
+            filter.until.unwrap() - filter.since.unwrap(),
+            chrono::Duration::days(1)
+        );
+    }
+
+    #[test]
+    fn invalid_actions_and_dates_are_rejected() {
+        assert_err!(query("nope", "", "").parse());
+        assert_err!(query("", "01/08/2024", "").parse());
+    }
+}
@@ -2,23 +2,42 @@ use actix_web::{
     http::header::{ContentType, LOCATION},
     web, HttpResponse,
 };
-use anyhow::Context;
+use htmlescape::encode_minimal;
 use sqlx::PgPool;
-use uuid::Uuid;
 
+use crate::authentication::{get_username, Role};
 use crate::{session_state::TypedSession, utils::e500};
 
 pub async fn admin_dashboard(
     session: TypedSession,
+    role: web::ReqData<Role>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
     let username = if let Some(user_id) = session.get_user_id().map_err(e500)? {
-        get_username(user_id, &pool).await.map_err(e500)?
+        encode_minimal(&get_username(user_id, &pool).await.map_err(e500)?)
     } else {
         return Ok(HttpResponse::SeeOther()
             .insert_header((LOCATION, "/login"))
             .finish());
     };
+    let role = role.into_inner();
+    let mut actions_html = String::new();
+    if role >= Role::Editor {
+        actions_html.push_str(
+            r#"<li><a href="/admin/newsletters">Send a newsletter issue</a></li>
+                    <li><a href="/admin/dead_letters">Inspect failed deliveries</a></li>
+                    <li><a href="/admin/welcome_email">Edit the welcome email</a></li>
+                    <li><a href="/admin/api_tokens">Manage API tokens</a></li>"#,
+        );
+    }
+    if role >= Role::Owner {
+        actions_html.push_str(
+            r#"<li><a href="/admin/users">Manage users</a></li>
+                    <li><a href="/admin/lockouts">Review login lockouts</a></li>
+                    <li><a href="/admin/audit">Browse the audit log</a></li>"#,
+        );
+    }
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
@@ -32,11 +51,18 @@ pub async fn admin_dashboard(
 
             <body>
                 <p>Welcome {username}!</p>
+                <p>You are signed in as {role}.</p>
                 <p>Available actions:</p>
                 <ol>
+                    <li><a href="/admin/subscribers">Browse subscribers</a></li>
+                    {actions_html}
                     <li><a href="/admin/password">Change password</a></li>
+                    <li><a href="/admin/email">Change email</a></li>
+                    <li><a href="/admin/2fa">Two-factor authentication</a></li>
+                    <li><a href="/admin/sessions">Manage your sessions</a></li>
                     <li>
                         <form name="logoutForm" action="/admin/logout" method="post">
+                            <input hidden type="text" name="csrf_token" value="{csrf_token}">
                             <input type="submit" value="Logout">
                         </form>
                     </li>
@@ -47,19 +73,3 @@ pub async fn admin_dashboard(
             </html>"#
         )))
 }
-
-#[tracing::instrument(name = "Get username", skip(pool))]
-pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
-    let row = sqlx::query!(
-        r#"
-        SELECT username
-        FROM users
-        WHERE user_id = $1
-        "#,
-        user_id,
-    )
-    .fetch_one(pool)
-    .await
-    .context("Failed to perform a query to retrieve a username.")?;
-    Ok(row.username)
-}
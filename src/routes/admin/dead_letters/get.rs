@@ -0,0 +1,125 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+pub async fn dead_letters(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let dead_letters = get_dead_letters(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for d in &dead_letters {
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{title}</td>
+                <td>{email}</td>
+                <td>{n_retries}</td>
+                <td>{dead_lettered_at}</td>
+                <td><pre>{last_error}</pre></td>
+                <td>
+                    <form action="/admin/dead_letters" method="post">
+                        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                        <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                        <input hidden type="text" name="subscriber_email" value="{email}">
+                        <button type="submit">Re-queue</button>
+                    </form>
+                </td>
+            </tr>"#,
+            title = encode_minimal(&d.title),
+            email = encode_minimal(&d.subscriber_email),
+            n_retries = d.n_retries,
+            dead_lettered_at = d.dead_lettered_at.to_rfc3339(),
+            last_error = encode_minimal(d.last_error.as_deref().unwrap_or_default()),
+            issue_id = d.newsletter_issue_id,
+        )
+        .unwrap();
+    }
+    let body = if dead_letters.is_empty() {
+        "<p>There are no dead letters.</p>".to_string()
+    } else {
+        format!(
+            r#"<table>
+        <tr>
+            <th>Issue</th>
+            <th>Subscriber</th>
+            <th>Attempts</th>
+            <th>Dead-lettered at</th>
+            <th>Last error</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <form action="/admin/dead_letters" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Re-queue all</button>
+    </form>"#
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Dead letters</title>
+</head>
+<body>
+    {msg_html}
+    {body}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+struct DeadLetter {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_retries: i16,
+    last_error: Option<String>,
+    dead_lettered_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Get dead letters", skip(pool))]
+async fn get_dead_letters(pool: &PgPool) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT
+            q.newsletter_issue_id,
+            i.title,
+            q.subscriber_email,
+            q.n_retries,
+            q.last_error,
+            q.dead_lettered_at as "dead_lettered_at!"
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE q.dead_lettered_at IS NOT NULL
+        ORDER BY q.dead_lettered_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve dead letters.")?;
+    Ok(dead_letters)
+}
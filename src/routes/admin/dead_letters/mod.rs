@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::dead_letters;
+pub use post::requeue_dead_letters;
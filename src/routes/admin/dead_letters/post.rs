@@ -0,0 +1,63 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    newsletter_issue_id: Option<Uuid>,
+    subscriber_email: Option<String>,
+}
+
+/// Put dead-lettered deliveries back in the queue with a fresh attempt budget.
+/// Without a specific delivery in the form, every dead letter is re-queued.
+#[tracing::instrument(name = "Re-queue dead letters", skip(form, pool))]
+pub async fn requeue_dead_letters(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let n_requeued = match (form.0.newsletter_issue_id, form.0.subscriber_email) {
+        (Some(issue_id), Some(email)) => {
+            sqlx::query!(
+                r#"
+            UPDATE issue_delivery_queue
+            SET
+                n_retries = 0,
+                execute_after = now(),
+                dead_lettered_at = NULL
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email = $2 AND
+                dead_lettered_at IS NOT NULL
+            "#,
+                issue_id,
+                email
+            )
+            .execute(&**pool)
+            .await
+        }
+        _ => {
+            sqlx::query!(
+                r#"
+            UPDATE issue_delivery_queue
+            SET
+                n_retries = 0,
+                execute_after = now(),
+                dead_lettered_at = NULL
+            WHERE dead_lettered_at IS NOT NULL
+            "#,
+            )
+            .execute(&**pool)
+            .await
+        }
+    }
+    .context("Failed to re-queue dead letters.")
+    .map_err(e500)?
+    .rows_affected();
+
+    FlashMessage::info(format!("Re-queued {} failed deliveries.", n_requeued)).send();
+    Ok(see_other("/admin/dead_letters"))
+}
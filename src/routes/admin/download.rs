@@ -0,0 +1,46 @@
+use actix_web::web;
+use futures_util::Stream;
+use std::future::Future;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+/// Rows are sent to the client in chunks of about this many bytes.
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+pub(crate) type Chunk = Result<web::Bytes, anyhow::Error>;
+
+/// The body of a download, written by `write` as the client reads it.
+///
+/// `write` runs in its own task and hands chunks over to the response: the
+/// channel being bounded, it never gets far ahead of a slow client. If it
+/// fails, the response is cut short rather than let look complete.
+pub(crate) fn stream_download<W, F>(write: W) -> impl Stream<Item = Chunk>
+where
+    W: FnOnce(mpsc::Sender<Chunk>) -> F,
+    F: Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(4);
+    let writer = write(sender.clone());
+    tokio::spawn(
+        async move {
+            if let Err(e) = writer.await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to write a download",
+                );
+                let _ = sender.send(Err(e)).await;
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+    futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })
+}
+
+/// Everything written to `writer` since the last call.
+pub(crate) fn take_csv(writer: &mut csv::Writer<Vec<u8>>) -> Result<web::Bytes, anyhow::Error> {
+    let buffer = std::mem::replace(writer, csv::Writer::from_writer(vec![])).into_inner()?;
+    Ok(buffer.into())
+}
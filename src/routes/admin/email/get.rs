@@ -0,0 +1,65 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::authentication::UserId;
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+pub async fn change_email_form(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let email = sqlx::query!(r#"SELECT email FROM users WHERE user_id = $1"#, **user_id)
+        .fetch_one(pool.as_ref())
+        .await
+        .context("Failed to retrieve the email of a user.")
+        .map_err(e500)?
+        .email;
+    let current_html = match &email {
+        Some(email) => format!("<p>Your email is {}.</p>", encode_minimal(email)),
+        None => {
+            "<p>You have not set an email yet: you won't be able to reset a forgotten password.</p>"
+                .to_string()
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+        <html lang="en">
+
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8">
+          <title>Change email</title>
+        </head>
+
+        <body>
+          {msg_html}
+          {current_html}
+          <form action="/admin/email" method="post">
+            <input hidden type="text" name="csrf_token" value="{csrf_token}">
+            <label>New email
+              <input type="email" placeholder="Enter your email" name="email">
+            </label>
+            <button type="submit">Change email</button>
+          </form>
+          <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+
+        </html>"#
+        )))
+}
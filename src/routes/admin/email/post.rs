@@ -0,0 +1,51 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+/// Set the address password reset links are sent to.
+#[tracing::instrument(name = "Change email", skip(form, pool))]
+pub async fn change_email(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Ok(email) = SubscriberEmail::parse(form.0.email.trim().to_string()) else {
+        FlashMessage::error("Please enter a valid email.").send();
+        return Ok(see_other("/admin/email"));
+    };
+
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE users SET email = $1
+        WHERE
+            user_id = $2 AND
+            NOT EXISTS (
+                SELECT 1 FROM users WHERE lower(email) = lower($1) AND user_id <> $2
+            )
+        "#,
+        email.as_ref(),
+        **user_id
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to change the email of a user.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_updated == 0 {
+        FlashMessage::error("This email is already used by another user.").send();
+    } else {
+        FlashMessage::info("Your email has been changed.").send();
+    }
+    Ok(see_other("/admin/email"))
+}
@@ -0,0 +1,87 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use chrono::Utc;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::authentication::list_login_failures;
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+/// Usernames and IPs with recent failed logins, locked out ones first.
+pub async fn list_lockouts(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        // Messages echo back the cleared key, which can contain a username
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let now = Utc::now();
+    let mut rows_html = String::new();
+    for f in list_login_failures(&pool).await.map_err(e500)? {
+        let status = if f.blocked_until <= now {
+            "expired"
+        } else if f.locked_out {
+            "locked out"
+        } else {
+            "delayed"
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{throttle_key}</td>
+                <td>{n_failures}</td>
+                <td>{last_failure_at}</td>
+                <td>{status}</td>
+                <td>{blocked_until}</td>
+                <td>
+                    <form action="/admin/lockouts" method="post">
+                        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                        <input hidden type="text" name="throttle_key" value="{throttle_key}">
+                        <button type="submit">Clear</button>
+                    </form>
+                </td>
+            </tr>"#,
+            throttle_key = encode_minimal(&f.throttle_key),
+            n_failures = f.n_failures,
+            last_failure_at = f.last_failure_at.to_rfc3339(),
+            blocked_until = f.blocked_until.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login lockouts</title>
+</head>
+<body>
+    {msg_html}
+    <p>Failed logins are counted per username and per IP address.</p>
+    <table>
+        <tr>
+            <th>Key</th>
+            <th>Failures</th>
+            <th>Last failure</th>
+            <th>Status</th>
+            <th>Blocked until</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::list_lockouts;
+pub use post::clear_lockout;
@@ -0,0 +1,33 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::authentication::{clear_login_failures, UserId};
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    throttle_key: String,
+}
+
+#[tracing::instrument(name = "Clear a login lockout", skip(form, pool), fields(throttle_key = %form.throttle_key))]
+pub async fn clear_lockout(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if clear_login_failures(&form.throttle_key, &pool)
+        .await
+        .map_err(e500)?
+    {
+        tracing::info!(admin_user_id = %**user_id, "Login failures have been cleared by an admin.");
+        FlashMessage::info(format!(
+            "The failed logins of {} have been cleared.",
+            form.throttle_key
+        ))
+        .send();
+    } else {
+        FlashMessage::error("There are no failed logins to clear.").send();
+    }
+    Ok(see_other("/admin/lockouts"))
+}
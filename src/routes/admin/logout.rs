@@ -1,14 +1,33 @@
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{revoke_session, UserId};
 use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
-use actix_web::HttpResponse;
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
 
-pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
-    if session.get_user_id().map_err(e500)?.is_none() {
-        Ok(see_other("/login"))
-    } else {
-        session.log_out();
-        FlashMessage::info("You have successfully logged out.").send();
-        Ok(see_other("/login"))
+pub async fn log_out(
+    session: TypedSession,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        revoke_session(**user_id, session_id, &pool)
+            .await
+            .map_err(e500)?;
     }
+    record_audit_event(
+        &**pool,
+        &request,
+        Some(**user_id),
+        AuditAction::LoggedOut,
+        None,
+        None,
+    )
+    .await
+    .map_err(e500)?;
+    session.log_out();
+    FlashMessage::info("You have successfully logged out.").send();
+    Ok(see_other("/login"))
 }
@@ -1,9 +1,30 @@
+mod api_tokens;
+mod audit;
 mod dashboard;
+mod dead_letters;
+mod download;
+mod email;
+mod lockouts;
 mod logout;
 mod newsletter;
 mod password;
+mod sessions;
+mod subscribers;
+mod two_factor;
+mod users;
+mod welcome_email;
 
+pub use api_tokens::*;
+pub use audit::*;
 pub use dashboard::admin_dashboard;
+pub use dead_letters::*;
+pub use email::*;
+pub use lockouts::*;
 pub use logout::*;
 pub use newsletter::*;
 pub use password::*;
+pub use sessions::*;
+pub use subscribers::*;
+pub use two_factor::*;
+pub use users::*;
+pub use welcome_email::*;
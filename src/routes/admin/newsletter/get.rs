@@ -1,15 +1,22 @@
 use actix_web::http::header::ContentType;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
 use std::fmt::Write;
 
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
 pub async fn publish_newsletter_form(
+    session: TypedSession,
     flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
     }
+    let idempotency_key = uuid::Uuid::new_v4();
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -23,6 +30,7 @@ pub async fn publish_newsletter_form(
 <body>
     {msg_html}
     <form action="/admin/newsletters" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
         <label>Title:<br>
             <input
                 type="text"
@@ -49,6 +57,7 @@ pub async fn publish_newsletter_form(
             ></textarea>
         </label>
         <br>
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
         <button type="submit">Publish</button>
     </form>
     <p><a href="/admin/dashboard">&lt;- Back</a></p>
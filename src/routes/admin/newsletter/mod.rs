@@ -2,4 +2,5 @@ mod get;
 mod post;
 
 pub use get::publish_newsletter_form;
+pub(crate) use post::publish_issue;
 pub use post::publish_newsletter;
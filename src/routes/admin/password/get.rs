@@ -1,6 +1,7 @@
 use actix_web::http::header::ContentType;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
 
 use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
@@ -14,10 +15,11 @@ pub async fn change_password_form(
     if session.get_user_id().map_err(e500)?.is_none() {
         return Ok(see_other("/login"));
     };
+    let csrf_token = session.csrf_token().map_err(e500)?;
 
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
     }
 
     Ok(HttpResponse::Ok().content_type(ContentType::html())
@@ -33,6 +35,7 @@ pub async fn change_password_form(
         <body>
           {msg_html}
           <form action="/admin/password" method="post">
+            <input hidden type="text" name="csrf_token" value="{csrf_token}">
             <label>Current password
               <input type="password" placeholder="Enter current password" name="current_password">
             </label>
@@ -1,10 +1,17 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 
-use crate::authentication::{validate_credentials, AuthError, Credentials, UserId};
-use crate::routes::admin::dashboard::get_username;
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::get_username;
+use crate::authentication::{
+    check_password_policy, revoke_other_sessions, validate_credentials, AuthError, Credentials,
+    FallbackPasswordHash, UserId,
+};
+use crate::configuration::{PasswordHashingSettings, PasswordPolicySettings};
+use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
 
 #[derive(serde::Deserialize)]
@@ -14,10 +21,16 @@ pub struct FormData {
     new_password_check: Secret<String>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn change_password(
     form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    hashing: web::Data<PasswordHashingSettings>,
+    fallback_hash: web::Data<FallbackPasswordHash>,
+    policy: web::Data<PasswordPolicySettings>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
 
@@ -34,10 +47,10 @@ pub async fn change_password(
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
 
     let credentials = Credentials {
-        username,
-        password: form.0.current_password,
+        username: username.clone(),
+        password: form.0.current_password.clone(),
     };
-    if let Err(e) = validate_credentials(credentials, &pool).await {
+    if let Err(e) = validate_credentials(credentials, &hashing, &fallback_hash, &pool).await {
         return match e {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect.").send();
@@ -47,8 +60,51 @@ pub async fn change_password(
         };
     }
 
-    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+    if let Err(violation) = check_password_policy(
+        form.new_password.expose_secret(),
+        &username,
+        Some(form.current_password.expose_secret()),
+        &policy,
+    ) {
+        FlashMessage::error(violation.to_string()).send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    // The new password, its audit row and the revocations go together: a
+    // failure halfway must not leave the old password's sessions alive
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    crate::authentication::change_password(
+        *user_id,
+        form.0.new_password,
+        &hashing,
+        &mut *transaction,
+    )
+    .await
+    .map_err(e500)?;
+    record_audit_event(
+        &mut *transaction,
+        &request,
+        Some(*user_id),
+        AuditAction::PasswordChanged,
+        None,
+        None,
+    )
+    .await
+    .map_err(e500)?;
+    // Whoever knew the old password may still be logged in elsewhere
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        revoke_other_sessions(*user_id, session_id, &mut *transaction)
+            .await
+            .map_err(e500)?;
+    }
+    transaction
+        .commit()
         .await
+        .context("Failed to commit SQL transaction to change a password.")
         .map_err(e500)?;
     FlashMessage::error("Your password has been changed.").send();
     Ok(see_other("/admin/password"))
@@ -0,0 +1,89 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::authentication::{self, UserId};
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+/// Where the current user is logged in, most recently active first.
+pub async fn list_sessions(
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let mut rows_html = String::new();
+    for s in authentication::list_sessions(**user_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        let action = if Some(s.session_id) == current_session_id {
+            "This session".to_owned()
+        } else {
+            format!(
+                r#"<form action="/admin/sessions/{}/revoke" method="post">
+                        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                        <button type="submit">Revoke</button>
+                    </form>"#,
+                s.session_id
+            )
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{user_agent}</td>
+                <td>{ip_address}</td>
+                <td>{created_at}</td>
+                <td>{last_seen_at}</td>
+                <td>{action}</td>
+            </tr>"#,
+            user_agent = encode_minimal(s.user_agent.as_deref().unwrap_or("unknown")),
+            ip_address = encode_minimal(&s.ip_address),
+            created_at = s.created_at.to_rfc3339(),
+            last_seen_at = s.last_seen_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Sessions</title>
+</head>
+<body>
+    {msg_html}
+    <p>You are logged in on:</p>
+    <table>
+        <tr>
+            <th>Browser</th>
+            <th>IP address</th>
+            <th>Logged in</th>
+            <th>Last seen</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <form action="/admin/sessions/revoke_others" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Log out all other sessions</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,47 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{self, UserId};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+
+#[tracing::instrument(name = "Revoke a session", skip(pool), fields(user_id=%**user_id))]
+pub async fn revoke_session(
+    session_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if authentication::revoke_session(**user_id, *session_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The session has been revoked.").send();
+    } else {
+        FlashMessage::error("There is no such active session.").send();
+    }
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(name = "Revoke the other sessions", skip(session, pool), fields(user_id=%**user_id))]
+pub async fn revoke_other_sessions(
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Set by `reject_anonymous_users` for every logged in session
+    let session_id = session
+        .get_session_id()
+        .map_err(e500)?
+        .ok_or_else(|| e500("The session is not tracked"))?;
+    let n_revoked = authentication::revoke_other_sessions(**user_id, session_id, &**pool)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info(format!(
+        "{} other session(s) have been logged out.",
+        n_revoked
+    ))
+    .send();
+    Ok(see_other("/admin/sessions"))
+}
@@ -0,0 +1,315 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::STATUSES;
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::UserId;
+use crate::routes::admin::download::{stream_download, take_csv, Chunk, CHUNK_SIZE};
+use crate::utils::{csv_cell, e400, e500, start_of_day};
+
+/// Filters and format as given in the query string: empty fields are ignored.
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    format: Option<String>,
+    status: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+/// A validated `ExportQuery`.
+#[derive(Debug)]
+struct ExportFilter {
+    format: ExportFormat,
+    status: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl ExportQuery {
+    fn parse(&self) -> Result<ExportFilter, String> {
+        let non_empty = |v: &Option<String>| {
+            v.as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_owned)
+        };
+        let format = match non_empty(&self.format).as_deref() {
+            None | Some("csv") => ExportFormat::Csv,
+            Some("ndjson") => ExportFormat::Ndjson,
+            Some(other) => {
+                return Err(format!(
+                    "{} is not a supported format. Use either `csv` or `ndjson`.",
+                    other
+                ))
+            }
+        };
+        let status = non_empty(&self.status);
+        if let Some(status) = &status {
+            if !STATUSES.contains(&status.as_str()) {
+                return Err(format!("{} is not a valid status.", status));
+            }
+        }
+        Ok(ExportFilter {
+            format,
+            status,
+            since: non_empty(&self.since)
+                .map(|d| start_of_day(&d))
+                .transpose()?,
+            // `until` is inclusive: stop at the start of the next day
+            until: non_empty(&self.until)
+                .map(|d| start_of_day(&d).map(|t| t + chrono::Duration::days(1)))
+                .transpose()?,
+        })
+    }
+
+    /// The non-empty filters, for the audit log.
+    fn describe(&self) -> String {
+        [
+            ("format", &self.format),
+            ("status", &self.status),
+            ("since", &self.since),
+            ("until", &self.until),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(|v| format!("{}={}", key, v))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    confirmed_at: Option<DateTime<Utc>>,
+}
+
+/// Subscriptions matching the filters, oldest first, as CSV or as
+/// newline-delimited JSON.
+///
+/// Rows are read from the database as the response is written, so the
+/// whole list never has to fit in memory.
+#[tracing::instrument(
+    name = "Export subscribers",
+    skip(export_query, request, user_id, pool)
+)]
+pub async fn export_subscribers(
+    export_query: web::Query<ExportQuery>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = export_query.parse().map_err(e400)?;
+    record_audit_event(
+        &**pool,
+        &request,
+        Some(**user_id),
+        AuditAction::SubscribersExported,
+        None,
+        Some(&export_query.describe()),
+    )
+    .await
+    .map_err(e500)?;
+
+    let (content_type, file_name) = match filter.format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "subscribers.csv"),
+        ExportFormat::Ndjson => ("application/x-ndjson", "subscribers.ndjson"),
+    };
+    let pool = pool.get_ref().clone();
+    let body =
+        stream_download(
+            |sender| async move { try_write_subscribers(&filter, &pool, &sender).await },
+        );
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(file_name.into())],
+        })
+        .streaming(body))
+}
+
+async fn try_write_subscribers(
+    filter: &ExportFilter,
+    pool: &PgPool,
+    sender: &mpsc::Sender<Chunk>,
+) -> Result<(), anyhow::Error> {
+    let mut encoder = Encoder::new(filter.format)?;
+    let mut subscribers = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, confirmed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR status = $1) AND
+            ($2::timestamptz IS NULL OR subscribed_at >= $2) AND
+            ($3::timestamptz IS NULL OR subscribed_at < $3)
+        ORDER BY subscribed_at, id
+        "#,
+        filter.status,
+        filter.since,
+        filter.until,
+    )
+    .fetch(pool);
+    while let Some(subscriber) = subscribers
+        .try_next()
+        .await
+        .context("Failed to read the subscribers to export.")?
+    {
+        encoder.encode(&subscriber)?;
+        if encoder.len() >= CHUNK_SIZE && sender.send(Ok(encoder.take()?)).await.is_err() {
+            // The client went away
+            return Ok(());
+        }
+    }
+    let _ = sender.send(Ok(encoder.take()?)).await;
+    Ok(())
+}
+
+/// Turns subscribers into bytes in the requested format.
+enum Encoder {
+    Csv(Box<csv::Writer<Vec<u8>>>),
+    Ndjson(Vec<u8>),
+}
+
+impl Encoder {
+    fn new(format: ExportFormat) -> Result<Self, anyhow::Error> {
+        Ok(match format {
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(vec![]);
+                writer.write_record([
+                    "id",
+                    "email",
+                    "name",
+                    "status",
+                    "subscribed_at",
+                    "confirmed_at",
+                ])?;
+                writer.flush()?;
+                Encoder::Csv(Box::new(writer))
+            }
+            ExportFormat::Ndjson => Encoder::Ndjson(vec![]),
+        })
+    }
+
+    fn encode(&mut self, s: &ExportedSubscriber) -> Result<(), anyhow::Error> {
+        let confirmed_at = s.confirmed_at.map(|t| t.to_rfc3339());
+        match self {
+            Encoder::Csv(writer) => {
+                // Addresses and names come from the public form: they must not
+                // be evaluated when the file is opened in a spreadsheet
+                writer.write_record([
+                    s.id.to_string().as_str(),
+                    &csv_cell(&s.email),
+                    &csv_cell(&s.name),
+                    &s.status,
+                    &s.subscribed_at.to_rfc3339(),
+                    confirmed_at.as_deref().unwrap_or_default(),
+                ])?;
+                // Keep everything written so far in the underlying buffer,
+                // so that `len` is accurate
+                writer.flush()?;
+            }
+            Encoder::Ndjson(buffer) => {
+                serde_json::to_writer(
+                    &mut *buffer,
+                    &serde_json::json!({
+                        "id": s.id,
+                        "email": s.email,
+                        "name": s.name,
+                        "status": s.status,
+                        "subscribed_at": s.subscribed_at.to_rfc3339(),
+                        "confirmed_at": confirmed_at,
+                    }),
+                )?;
+                buffer.push(b'\n');
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Encoder::Csv(writer) => writer.get_ref().len(),
+            Encoder::Ndjson(buffer) => buffer.len(),
+        }
+    }
+
+    /// Everything encoded since the last call.
+    fn take(&mut self) -> Result<web::Bytes, anyhow::Error> {
+        match self {
+            Encoder::Csv(writer) => take_csv(writer),
+            Encoder::Ndjson(buffer) => Ok(std::mem::take(buffer).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExportFormat, ExportQuery};
+    use claims::assert_err;
+
+    fn query(format: &str, status: &str, since: &str, until: &str) -> ExportQuery {
+        ExportQuery {
+            format: Some(format.into()),
+            status: Some(status.into()),
+            since: Some(since.into()),
+            until: Some(until.into()),
+        }
+    }
+
+    #[test]
+    fn csv_is_the_default_format() {
+        let filter = query("", "", "", "").parse().unwrap();
+        assert_eq!(filter.format, ExportFormat::Csv);
+        assert!(filter.status.is_none());
+        assert!(filter.since.is_none());
+        assert!(filter.until.is_none());
+    }
+
+    #[test]
+    fn the_until_date_is_inclusive() {
+        let filter = query("ndjson", "confirmed", "2024-08-01", "2024-08-01")
+            .parse()
+            .unwrap();
+        assert_eq!(filter.format, ExportFormat::Ndjson);
+        assert_eq!(
+            filter.until.unwrap() - filter.since.unwrap(),
+            chrono::Duration::days(1)
+        );
+    }
+
+    #[test]
+    fn unknown_formats_statuses_and_invalid_dates_are_rejected() {
+        assert_err!(query("xml", "", "", "").parse());
+        assert_err!(query("", "deleted", "", "").parse());
+        assert_err!(query("", "", "01/08/2024", "").parse());
+    }
+
+    #[test]
+    fn the_description_skips_empty_filters() {
+        let description = query("csv", "", "2024-08-01", " ").describe();
+        assert_eq!(description, "format=csv, since=2024-08-01");
+    }
+}
@@ -0,0 +1,392 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use super::{SubscriberFilter, SubscriberQuery, STATUSES};
+use crate::authentication::Role;
+use crate::session_state::TypedSession;
+use crate::utils::{e400, e500, see_other};
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct PageQuery {
+    page: Option<u32>,
+}
+
+pub(super) struct SubscriberRow {
+    pub(super) id: Uuid,
+    pub(super) email: String,
+    pub(super) name: String,
+    pub(super) status: String,
+    pub(super) subscribed_at: DateTime<Utc>,
+}
+
+/// Subscribers, most recent first, a page at a time.
+pub async fn list_subscribers(
+    subscriber_query: web::Query<SubscriberQuery>,
+    page: web::Query<PageQuery>,
+    role: web::ReqData<Role>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = subscriber_query.parse().map_err(e400)?;
+    let page = page.page.unwrap_or(1).max(1);
+    let (subscribers, n_subscribers) = search_subscribers(&filter, page, &pool)
+        .await
+        .map_err(e500)?;
+    let n_pages = ((n_subscribers + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for s in &subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td><a href="/admin/subscribers/{id}">{email}</a></td>
+                <td>{name}</td>
+                <td>{status}</td>
+                <td>{subscribed_at}</td>
+            </tr>"#,
+            id = s.id,
+            email = encode_minimal(&s.email),
+            name = encode_minimal(&s.name),
+            status = s.status,
+            subscribed_at = s.subscribed_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    let mut status_options = String::from(r#"<option value="">Any</option>"#);
+    for status in STATUSES {
+        let selected = if filter.status.as_deref() == Some(status) {
+            " selected"
+        } else {
+            ""
+        };
+        write!(
+            status_options,
+            r#"<option value="{status}"{selected}>{status}</option>"#
+        )
+        .unwrap();
+    }
+    let editor_tools = if *role >= Role::Editor {
+        format!(
+            r#"<form action="/admin/subscribers/export" method="get">
+        <label>Status <select name="status">{status_options}</select></label>
+        <label>Subscribed since <input type="date" name="since"></label>
+        <label>until <input type="date" name="until"></label>
+        <label>Format <select name="format">
+            <option value="csv">CSV</option>
+            <option value="ndjson">NDJSON</option>
+        </select></label>
+        <button type="submit">Export</button>
+    </form>
+    <p><a href="/admin/subscribers/import">Import subscribers from a CSV file</a></p>"#
+        )
+    } else {
+        String::new()
+    };
+    let query_string = encode_minimal(&subscriber_query.to_query_string());
+    let mut pages_html = String::new();
+    if page > 1 {
+        write!(
+            pages_html,
+            r#"<a href="/admin/subscribers?{query_string}&amp;page={}">&lt;- Previous</a> "#,
+            page - 1
+        )
+        .unwrap();
+    }
+    write!(pages_html, "Page {} of {}", page, n_pages).unwrap();
+    if i64::from(page) < n_pages {
+        write!(
+            pages_html,
+            r#" <a href="/admin/subscribers?{query_string}&amp;page={}">Next -&gt;</a>"#,
+            page + 1
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/subscribers" method="get">
+        <label>Email or name <input type="text" name="q" value="{q}"></label>
+        <label>Status <select name="status">{status_options}</select></label>
+        <button type="submit">Search</button>
+    </form>
+    <p>{n_subscribers} subscriber(s) found.</p>
+    <table>
+        <tr>
+            <th>Email</th>
+            <th>Name</th>
+            <th>Status</th>
+            <th>Subscribed at</th>
+        </tr>
+        {rows_html}
+    </table>
+    <p>{pages_html}</p>
+    {editor_tools}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            q = encode_minimal(subscriber_query.q.as_deref().unwrap_or_default()),
+        )))
+}
+
+#[tracing::instrument(name = "Search subscribers", skip(filter, pool))]
+async fn search_subscribers(
+    filter: &SubscriberFilter,
+    page: u32,
+    pool: &PgPool,
+) -> Result<(Vec<SubscriberRow>, i64), anyhow::Error> {
+    let n_subscribers = sqlx::query!(
+        r#"
+        SELECT count(*) AS "count!"
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        "#,
+        filter.pattern,
+        filter.status,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count subscribers.")?
+    .count;
+    let subscribers = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $3 OFFSET $4
+        "#,
+        filter.pattern,
+        filter.status,
+        PAGE_SIZE,
+        i64::from(page - 1) * PAGE_SIZE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to search subscribers.")?;
+    Ok((subscribers, n_subscribers))
+}
+
+/// Everything we know about a subscriber: their confirmation tokens and the
+/// issues still waiting to be delivered to them (or that could not be).
+pub async fn subscriber_details(
+    subscriber_id: web::Path<Uuid>,
+    session: TypedSession,
+    role: web::ReqData<Role>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber(subscriber_id, &pool).await.map_err(e500)? else {
+        FlashMessage::error("The subscriber could not be found.").send();
+        return Ok(see_other("/admin/subscribers"));
+    };
+    let csrf_token = session.csrf_token().map_err(e500)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        // Messages can echo back the submitted name or email
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let now = Utc::now();
+    let tokens = sqlx::query!(
+        r#"
+        SELECT created_at, expires_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at DESC
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve the confirmation tokens of a subscriber.")
+    .map_err(e500)?;
+    let mut tokens_html = String::new();
+    for t in &tokens {
+        writeln!(
+            tokens_html,
+            r#"<tr>
+                <td>{created_at}</td>
+                <td>{expires_at}</td>
+                <td>{state}</td>
+            </tr>"#,
+            created_at = t.created_at.to_rfc3339(),
+            expires_at = t.expires_at.to_rfc3339(),
+            state = if t.expires_at <= now {
+                "expired"
+            } else {
+                "valid"
+            },
+        )
+        .unwrap();
+    }
+
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT i.title, q.n_retries, q.execute_after, q.last_error, q.dead_lettered_at
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE q.subscriber_email = $1
+        ORDER BY i.published_at DESC
+        "#,
+        subscriber.email
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve the deliveries of a subscriber.")
+    .map_err(e500)?;
+    let mut deliveries_html = String::new();
+    for d in &deliveries {
+        let state = match d.dead_lettered_at {
+            Some(at) => format!("failed for good at {}", at.to_rfc3339()),
+            None => format!("next attempt at {}", d.execute_after.to_rfc3339()),
+        };
+        writeln!(
+            deliveries_html,
+            r#"<tr>
+                <td>{title}</td>
+                <td>{state}</td>
+                <td>{n_retries}</td>
+                <td>{last_error}</td>
+            </tr>"#,
+            title = encode_minimal(&d.title),
+            n_retries = d.n_retries,
+            last_error = encode_minimal(d.last_error.as_deref().unwrap_or_default()),
+        )
+        .unwrap();
+    }
+
+    let email = encode_minimal(&subscriber.email);
+    let name = encode_minimal(&subscriber.name);
+    let mut actions_html = String::new();
+    if *role >= Role::Editor {
+        if subscriber.status == "pending_confirmation" {
+            write!(
+                actions_html,
+                r#"<form action="/admin/subscribers/{subscriber_id}/confirm" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Confirm</button>
+    </form>"#
+            )
+            .unwrap();
+        }
+        if subscriber.status != "unsubscribed" {
+            write!(
+                actions_html,
+                r#"<form action="/admin/subscribers/{subscriber_id}/unsubscribe" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Unsubscribe</button>
+    </form>"#
+            )
+            .unwrap();
+        }
+        write!(
+            actions_html,
+            r#"<form action="/admin/subscribers/{subscriber_id}/edit" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <label>Name <input type="text" name="name" value="{name}"></label>
+        <label>Email <input type="text" name="email" value="{email}"></label>
+        <button type="submit">Save</button>
+    </form>
+    <form action="/admin/subscribers/{subscriber_id}/delete" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Delete permanently</button>
+    </form>
+    <p><a href="/admin/subscribers/{subscriber_id}/personal-data">Download their personal data</a></p>
+    <form action="/admin/subscribers/{subscriber_id}/erase" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Erase their personal data</button>
+    </form>"#
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber</title>
+</head>
+<body>
+    {msg_html}
+    <dl>
+        <dt>Email</dt><dd>{email}</dd>
+        <dt>Name</dt><dd>{name}</dd>
+        <dt>Status</dt><dd>{status}</dd>
+        <dt>Subscribed at</dt><dd>{subscribed_at}</dd>
+    </dl>
+    {actions_html}
+    <h2>Confirmation tokens</h2>
+    <table>
+        <tr>
+            <th>Sent at</th>
+            <th>Expires at</th>
+            <th>State</th>
+        </tr>
+        {tokens_html}
+    </table>
+    <h2>Pending and failed deliveries</h2>
+    <table>
+        <tr>
+            <th>Issue</th>
+            <th>State</th>
+            <th>Retries</th>
+            <th>Last error</th>
+        </tr>
+        {deliveries_html}
+    </table>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+            status = subscriber.status,
+            subscribed_at = subscriber.subscribed_at.to_rfc3339(),
+        )))
+}
+
+#[tracing::instrument(name = "Get subscriber", skip(pool))]
+pub(super) async fn get_subscriber(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<SubscriberRow>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberRow,
+        r#"SELECT id, email, name, status, subscribed_at FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a subscriber.")
+}
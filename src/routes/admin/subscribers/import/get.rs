@@ -0,0 +1,110 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+/// The upload form, followed by the most recent imports and their reports.
+pub async fn import_subscribers_form(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let imports = sqlx::query!(
+        r#"
+        SELECT
+            i.import_id, i.started_at, i.finished_at, i.default_status,
+            i.n_accepted, i.n_duplicates, i.n_invalid, u.username AS "username?"
+        FROM subscriber_imports i
+        LEFT JOIN users u ON u.user_id = i.imported_by
+        ORDER BY i.started_at DESC
+        LIMIT 20
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve the recent imports.")
+    .map_err(e500)?;
+    let mut rows_html = String::new();
+    for i in &imports {
+        let state = if i.finished_at.is_some() {
+            "finished"
+        } else {
+            "in progress or interrupted"
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{started_at}</td>
+                <td>{username}</td>
+                <td>{default_status}</td>
+                <td>{n_accepted}</td>
+                <td>{n_duplicates}</td>
+                <td>{n_invalid}</td>
+                <td>{state}</td>
+                <td><a href="/admin/subscribers/import/{import_id}/report">Download the report</a></td>
+            </tr>"#,
+            started_at = i.started_at.to_rfc3339(),
+            username = encode_minimal(i.username.as_deref().unwrap_or_default()),
+            default_status = i.default_status,
+            n_accepted = i.n_accepted,
+            n_duplicates = i.n_duplicates,
+            n_invalid = i.n_invalid,
+            import_id = i.import_id,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <p>The first line of the file must name its columns: <code>email</code> and
+    <code>name</code>, optionally followed by <code>status</code>
+    (<code>pending_confirmation</code>, <code>confirmed</code> or <code>unsubscribed</code>)
+    and <code>subscribed_at</code> (YYYY-MM-DD or RFC 3339).</p>
+    <form action="/admin/subscribers/import" method="post" enctype="multipart/form-data">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <p>Rows without a status should be:</p>
+        <label><input type="radio" name="mode" value="confirmed" checked> Confirmed right away</label>
+        <label><input type="radio" name="mode" value="double_opt_in"> Sent a confirmation email</label>
+        <p><input type="file" name="file" accept=".csv,text/csv"></p>
+        <button type="submit">Import</button>
+    </form>
+    <h2>Recent imports</h2>
+    <table>
+        <tr>
+            <th>Started at</th>
+            <th>By</th>
+            <th>Default status</th>
+            <th>Accepted</th>
+            <th>Duplicates</th>
+            <th>Invalid</th>
+            <th>State</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,181 @@
+mod get;
+mod post;
+mod report;
+
+pub use get::import_subscribers_form;
+pub use post::import_subscribers;
+pub use report::download_import_report;
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use super::STATUSES;
+use crate::domain::NewSubscriber;
+use crate::routes::FormData;
+
+/// Rows are written this many at a time, each batch in its own transaction.
+const BATCH_SIZE: usize = 500;
+
+/// What happens to imported rows that don't have a `status` of their own.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ImportMode {
+    /// They are confirmed straight away, e.g. when migrating an existing list
+    Confirmed,
+    /// They get a confirmation email, like the subscription form sends
+    DoubleOptIn,
+}
+
+impl ImportMode {
+    fn default_status(&self) -> &'static str {
+        match self {
+            ImportMode::Confirmed => "confirmed",
+            ImportMode::DoubleOptIn => "pending_confirmation",
+        }
+    }
+}
+
+impl TryFrom<String> for ImportMode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "confirmed" => Ok(ImportMode::Confirmed),
+            "double_opt_in" => Ok(ImportMode::DoubleOptIn),
+            other => Err(format!("{} is not a valid import mode.", other)),
+        }
+    }
+}
+
+/// A line of the uploaded file, as found in there.
+#[derive(serde::Deserialize)]
+struct ImportRecord {
+    email: String,
+    name: String,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    subscribed_at: Option<String>,
+}
+
+/// A line of the uploaded file that is fit to be inserted.
+struct ValidRecord {
+    subscriber: NewSubscriber,
+    status: &'static str,
+    subscribed_at: DateTime<Utc>,
+}
+
+impl ImportRecord {
+    /// Apply the same validation as the subscription form, plus the optional
+    /// columns.
+    fn validate(self, mode: ImportMode) -> Result<ValidRecord, String> {
+        let status = match self.status.filter(|s| !s.is_empty()) {
+            None => mode.default_status(),
+            Some(status) => *STATUSES
+                .iter()
+                .find(|s| **s == status)
+                .ok_or_else(|| format!("{} is not a valid status.", status))?,
+        };
+        let subscribed_at = match self.subscribed_at.filter(|s| !s.is_empty()) {
+            None => Utc::now(),
+            Some(subscribed_at) => parse_subscribed_at(&subscribed_at)?,
+        };
+        let subscriber = NewSubscriber::try_from(FormData {
+            name: self.name,
+            email: self.email,
+        })?;
+        Ok(ValidRecord {
+            subscriber,
+            status,
+            subscribed_at,
+        })
+    }
+}
+
+/// Either a bare date or a full RFC 3339 timestamp.
+fn parse_subscribed_at(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|d| d.with_timezone(&Utc))
+        .map_err(|_| {
+            format!(
+                "{} is not a valid date, please use YYYY-MM-DD or RFC 3339.",
+                value
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportMode, ImportRecord};
+    use claims::assert_err;
+
+    fn record(email: &str, status: Option<&str>, subscribed_at: Option<&str>) -> ImportRecord {
+        ImportRecord {
+            email: email.into(),
+            name: "Ursula".into(),
+            status: status.map(Into::into),
+            subscribed_at: subscribed_at.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn rows_without_a_status_follow_the_import_mode() {
+        let confirmed = record("ursula@example.com", None, None)
+            .validate(ImportMode::Confirmed)
+            .unwrap();
+        let pending = record("ursula@example.com", Some(""), None)
+            .validate(ImportMode::DoubleOptIn)
+            .unwrap();
+        assert_eq!(confirmed.status, "confirmed");
+        assert_eq!(pending.status, "pending_confirmation");
+    }
+
+    #[test]
+    fn explicit_statuses_and_dates_are_kept() {
+        let valid = record(
+            "ursula@example.com",
+            Some("unsubscribed"),
+            Some("2021-03-04"),
+        )
+        .validate(ImportMode::Confirmed)
+        .unwrap();
+        assert_eq!(valid.status, "unsubscribed");
+        assert_eq!(
+            valid.subscribed_at.to_rfc3339(),
+            "2021-03-04T00:00:00+00:00"
+        );
+        let valid = record(
+            "ursula@example.com",
+            None,
+            Some("2021-03-04T10:00:00+02:00"),
+        )
+        .validate(ImportMode::Confirmed)
+        .unwrap();
+        assert_eq!(
+            valid.subscribed_at.to_rfc3339(),
+            "2021-03-04T08:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn invalid_rows_are_rejected() {
+        let test_cases = [
+            record("not-an-email", None, None),
+            record("ursula@example.com", Some("deleted"), None),
+            record("ursula@example.com", None, Some("04/03/2021")),
+        ];
+        for record in test_cases {
+            assert!(record.validate(ImportMode::Confirmed).is_err());
+        }
+    }
+
+    #[test]
+    fn only_known_modes_are_accepted() {
+        assert_eq!(
+            ImportMode::try_from("double_opt_in".to_string()),
+            Ok(ImportMode::DoubleOptIn)
+        );
+        assert_err!(ImportMode::try_from("silent".to_string()));
+    }
+}
@@ -0,0 +1,483 @@
+use actix_multipart::{Field, Multipart};
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
+use futures_util::{StreamExt, TryStreamExt};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use uuid::Uuid;
+
+use super::{ImportMode, ImportRecord, ValidRecord, BATCH_SIZE};
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::UserId;
+use crate::personal_data::find_suppressed;
+use crate::routes::{error_chain_fmt, generate_subscription_token};
+use crate::startup::SubscriptionTokenTtl;
+use crate::utils::{e400, e500, see_other};
+
+#[derive(thiserror::Error)]
+enum ImportError {
+    #[error("The first line of the file must name its columns, including `email` and `name`.")]
+    MissingColumns,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[derive(Default)]
+struct ImportSummary {
+    n_accepted: i32,
+    n_duplicates: i32,
+    n_invalid: i32,
+}
+
+enum RowOutcome {
+    Valid(ValidRecord),
+    Duplicate(&'static str),
+    Invalid(String),
+}
+
+struct ImportRow {
+    line_number: i64,
+    email: Option<String>,
+    outcome: RowOutcome,
+}
+
+/// Import the subscribers listed in an uploaded CSV file.
+///
+/// The file is read as it is uploaded and written a batch at a time, so
+/// that large lists don't have to fit in memory. What happened to each row
+/// is kept for the report.
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(payload, request, user_id, pool, token_ttl)
+)]
+pub async fn import_subscribers(
+    mut payload: Multipart,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut mode = None;
+    let mut outcome = None;
+    while let Some(field) = payload.try_next().await.map_err(e400)? {
+        match field.name() {
+            "mode" => {
+                let value = read_text_field(field).await.map_err(e400)?;
+                match ImportMode::try_from(value) {
+                    Ok(m) => mode = Some(m),
+                    Err(e) => {
+                        FlashMessage::error(e).send();
+                        return Ok(see_other("/admin/subscribers/import"));
+                    }
+                }
+            }
+            "file" if outcome.is_none() => {
+                let Some(mode) = mode else {
+                    FlashMessage::error("Please pick an import mode.").send();
+                    return Ok(see_other("/admin/subscribers/import"));
+                };
+                outcome = Some(import_file(field, mode, **user_id, &pool, token_ttl.0).await);
+            }
+            _ => {
+                let mut field = field;
+                while field.try_next().await.map_err(e400)?.is_some() {}
+            }
+        }
+    }
+
+    let (import_id, summary) = match outcome {
+        None => {
+            FlashMessage::error("Please choose a CSV file to import.").send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+        Some(Err(ImportError::MissingColumns)) => {
+            FlashMessage::error(ImportError::MissingColumns.to_string()).send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+        Some(Err(ImportError::UnexpectedError(e))) => return Err(e500(e)),
+        Some(Ok(outcome)) => outcome,
+    };
+    let details = format!(
+        "{} accepted, {} duplicate(s), {} invalid",
+        summary.n_accepted, summary.n_duplicates, summary.n_invalid
+    );
+    record_audit_event(
+        &**pool,
+        &request,
+        Some(**user_id),
+        AuditAction::SubscribersImported,
+        Some(&import_id.to_string()),
+        Some(&details),
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info(format!(
+        "The import is complete: {} subscriber(s) added, {} duplicate(s) and {} invalid row(s) skipped.",
+        summary.n_accepted, summary.n_duplicates, summary.n_invalid
+    ))
+    .send();
+    Ok(see_other("/admin/subscribers/import"))
+}
+
+async fn read_text_field(mut field: Field) -> Result<String, anyhow::Error> {
+    let mut value = Vec::new();
+    while let Some(chunk) = field.try_next().await.map_err(upload_error)? {
+        value.extend_from_slice(&chunk);
+    }
+    String::from_utf8(value).context("The form field is not valid UTF-8.")
+}
+
+// `MultipartError` can't be sent across threads, unlike `anyhow::Error`
+fn upload_error(e: actix_multipart::MultipartError) -> anyhow::Error {
+    anyhow::anyhow!("Failed to read the upload: {}", e)
+}
+
+async fn import_file(
+    mut field: Field,
+    mode: ImportMode,
+    user_id: Uuid,
+    pool: &PgPool,
+    token_ttl: std::time::Duration,
+) -> Result<(Uuid, ImportSummary), ImportError> {
+    // The CSV reader wants an `AsyncRead + Send`, which the upload isn't:
+    // pipe it through an in-memory buffer, read on the other end as it fills.
+    let (mut writer, reader) = tokio::io::duplex(64 * 1024);
+    let upload = async move {
+        while let Some(chunk) = field.try_next().await.map_err(upload_error)? {
+            writer.write_all(&chunk).await?;
+        }
+        // Dropping `writer` tells the reader the file is over
+        Ok::<_, anyhow::Error>(())
+    };
+    let (upload, import) = futures_util::join!(
+        upload,
+        import_records(reader, mode, user_id, pool, token_ttl)
+    );
+    let outcome = import?;
+    // A failed upload after a successful import means it was cut short
+    upload
+        .context("The upload was interrupted.")
+        .map_err(ImportError::UnexpectedError)?;
+    Ok(outcome)
+}
+
+async fn import_records(
+    reader: impl AsyncRead + Unpin + Send,
+    mode: ImportMode,
+    user_id: Uuid,
+    pool: &PgPool,
+    token_ttl: std::time::Duration,
+) -> Result<(Uuid, ImportSummary), ImportError> {
+    let mut csv = AsyncReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .create_reader(reader);
+    let headers = csv
+        .headers()
+        .await
+        .map_err(|_| ImportError::MissingColumns)?
+        .clone();
+    if !["email", "name"]
+        .iter()
+        .all(|column| headers.iter().any(|h| h == *column))
+    {
+        return Err(ImportError::MissingColumns);
+    }
+
+    let import_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (import_id, imported_by, started_at, default_status)
+        VALUES ($1, $2, now(), $3)
+        "#,
+        import_id,
+        user_id,
+        mode.default_status()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record a new import.")?;
+
+    let mut summary = ImportSummary::default();
+    let mut seen_emails = HashSet::new();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut records = csv.records();
+    let mut last_line_number = 1;
+    while let Some(record) = records.next().await {
+        let row = match record {
+            Ok(record) => {
+                last_line_number = record
+                    .position()
+                    .map(|p| p.line() as i64)
+                    .unwrap_or(last_line_number + 1);
+                to_import_row(record, last_line_number, &headers, mode, &mut seen_emails)
+            }
+            Err(e) => {
+                last_line_number = e
+                    .position()
+                    .map(|p| p.line() as i64)
+                    .unwrap_or(last_line_number + 1);
+                ImportRow {
+                    line_number: last_line_number,
+                    email: None,
+                    outcome: RowOutcome::Invalid(e.to_string()),
+                }
+            }
+        };
+        batch.push(row);
+        if batch.len() == BATCH_SIZE {
+            write_batch(import_id, &mut batch, &mut summary, pool, token_ttl).await?;
+        }
+    }
+    write_batch(import_id, &mut batch, &mut summary, pool, token_ttl).await?;
+
+    sqlx::query!(
+        r#"UPDATE subscriber_imports SET finished_at = now() WHERE import_id = $1"#,
+        import_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to mark an import as finished.")?;
+    Ok((import_id, summary))
+}
+
+fn to_import_row(
+    record: StringRecord,
+    line_number: i64,
+    headers: &StringRecord,
+    mode: ImportMode,
+    seen_emails: &mut HashSet<String>,
+) -> ImportRow {
+    let record = match record.deserialize::<ImportRecord>(Some(headers)) {
+        Ok(record) => record,
+        Err(e) => {
+            return ImportRow {
+                line_number,
+                email: None,
+                outcome: RowOutcome::Invalid(e.to_string()),
+            }
+        }
+    };
+    let email = Some(record.email.clone());
+    let outcome = match record.validate(mode) {
+        Err(e) => RowOutcome::Invalid(e),
+        Ok(valid) if !seen_emails.insert(valid.subscriber.email.as_ref().to_owned()) => {
+            RowOutcome::Duplicate("The address appears earlier in the file.")
+        }
+        Ok(valid) => RowOutcome::Valid(valid),
+    };
+    ImportRow {
+        line_number,
+        email,
+        outcome,
+    }
+}
+
+/// Insert the valid rows of `batch` that aren't subscribed yet, along with
+/// the report for every row, then empty it.
+///
+/// New pending subscribers get their confirmation email once the batch is
+/// committed.
+#[tracing::instrument(skip_all, fields(n_rows = batch.len()))]
+async fn write_batch(
+    import_id: Uuid,
+    batch: &mut Vec<ImportRow>,
+    summary: &mut ImportSummary,
+    pool: &PgPool,
+    token_ttl: std::time::Duration,
+) -> Result<(), anyhow::Error> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    // Erased addresses must not come back through an import. The report
+    // doesn't keep them either.
+    let suppressed = find_suppressed(
+        pool,
+        batch.iter().filter_map(|row| match &row.outcome {
+            RowOutcome::Valid(valid) => Some(valid.subscriber.email.as_ref()),
+            _ => None,
+        }),
+    )
+    .await?;
+    for row in batch.iter_mut() {
+        let is_suppressed = match &row.outcome {
+            RowOutcome::Valid(valid) => suppressed.contains(valid.subscriber.email.as_ref()),
+            _ => false,
+        };
+        if is_suppressed {
+            row.email = None;
+            row.outcome = RowOutcome::Invalid(
+                "The address was erased at its owner's request and can't be imported.".into(),
+            );
+        }
+    }
+
+    let mut ids = vec![];
+    let mut emails = vec![];
+    let mut names = vec![];
+    let mut statuses = vec![];
+    let mut subscribed_ats = vec![];
+    for row in batch.iter_mut() {
+        if let RowOutcome::Valid(valid) = &row.outcome {
+            ids.push(Uuid::new_v4());
+            emails.push(valid.subscriber.email.as_ref().to_owned());
+            names.push(valid.subscriber.name.as_ref().to_owned());
+            statuses.push(valid.status.to_owned());
+            subscribed_ats.push(valid.subscribed_at);
+        }
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let inserted: HashSet<Uuid> = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, status, subscribed_at, confirmed_at)
+        SELECT
+            id, email, name, status, subscribed_at,
+            -- Confirmed subscribers are confirmed by the import itself
+            CASE WHEN status = 'confirmed' THEN now() END
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[], $5::timestamptz[])
+            AS t(id, email, name, status, subscribed_at)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        &ids,
+        &emails,
+        &names,
+        &statuses,
+        &subscribed_ats
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to insert a batch of subscribers.")?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+
+    // Tokens and report entries, row by row
+    let mut to_confirm_line_numbers = vec![];
+    let mut token_subscriber_ids = vec![];
+    let mut tokens = vec![];
+    let mut line_numbers = vec![];
+    let mut report_emails = vec![];
+    let mut outcomes = vec![];
+    let mut messages = vec![];
+    let mut ids = ids.into_iter();
+    for row in batch.iter() {
+        let (outcome, message) = match &row.outcome {
+            RowOutcome::Valid(valid) => {
+                let subscriber_id = ids.next().unwrap();
+                if !inserted.contains(&subscriber_id) {
+                    summary.n_duplicates += 1;
+                    (
+                        "duplicate",
+                        Some("The address is already on the list.".to_owned()),
+                    )
+                } else if valid.status == "pending_confirmation" {
+                    summary.n_accepted += 1;
+                    let token = generate_subscription_token();
+                    token_subscriber_ids.push(subscriber_id);
+                    tokens.push(token.clone());
+                    to_confirm_line_numbers.push(row.line_number);
+                    (
+                        "accepted",
+                        Some("A confirmation email will be sent.".to_owned()),
+                    )
+                } else {
+                    summary.n_accepted += 1;
+                    ("accepted", None)
+                }
+            }
+            RowOutcome::Duplicate(message) => {
+                summary.n_duplicates += 1;
+                ("duplicate", Some(message.to_string()))
+            }
+            RowOutcome::Invalid(message) => {
+                summary.n_invalid += 1;
+                ("invalid", Some(message.clone()))
+            }
+        };
+        line_numbers.push(row.line_number);
+        report_emails.push(row.email.clone());
+        outcomes.push(outcome.to_owned());
+        messages.push(message);
+    }
+
+    let expires_at = Utc::now()
+        + chrono::Duration::from_std(token_ttl)
+            .expect("The subscription token TTL is out of range.");
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, created_at, expires_at)
+        SELECT token, subscriber_id, now(), $3
+        FROM UNNEST($1::text[], $2::uuid[]) AS t(token, subscriber_id)
+        "#,
+        &tokens,
+        &token_subscriber_ids,
+        expires_at
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the confirmation tokens of imported subscribers.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_import_rows (import_id, line_number, email, outcome, message)
+        SELECT $1, * FROM UNNEST($2::bigint[], $3::text[], $4::text[], $5::text[])
+        "#,
+        import_id,
+        &line_numbers,
+        &report_emails as &[Option<String>],
+        &outcomes,
+        &messages as &[Option<String>]
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the import report.")?;
+    // Sent by the confirmation email worker, so that the upload doesn't wait
+    // on the email provider
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_queue (subscription_token, import_id, line_number)
+        SELECT token, $1, line_number
+        FROM UNNEST($2::text[], $3::bigint[]) AS t(token, line_number)
+        "#,
+        import_id,
+        &tokens,
+        &to_confirm_line_numbers
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to queue the confirmation emails of imported subscribers.")?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriber_imports
+        SET n_accepted = $2, n_duplicates = $3, n_invalid = $4
+        WHERE import_id = $1
+        "#,
+        import_id,
+        summary.n_accepted,
+        summary.n_duplicates,
+        summary.n_invalid
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the import counters.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import a batch of subscribers.")?;
+    batch.clear();
+    Ok(())
+}
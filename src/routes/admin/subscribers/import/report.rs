@@ -0,0 +1,72 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+/// What happened to each line of an imported file, as CSV.
+#[tracing::instrument(name = "Download an import report", skip(pool))]
+pub async fn download_import_report(
+    import_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import_id = import_id.into_inner();
+    let import = sqlx::query!(
+        r#"SELECT import_id FROM subscriber_imports WHERE import_id = $1"#,
+        import_id
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to retrieve an import.")
+    .map_err(e500)?;
+    if import.is_none() {
+        FlashMessage::error("The import could not be found.").send();
+        return Ok(see_other("/admin/subscribers/import"));
+    }
+    let rows = sqlx::query!(
+        r#"
+        SELECT line_number, email, outcome, message
+        FROM subscriber_import_rows
+        WHERE import_id = $1
+        ORDER BY line_number
+        "#,
+        import_id
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve an import report.")
+    .map_err(e500)?;
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer
+        .write_record(["line", "email", "outcome", "message"])
+        .map_err(e500)?;
+    for r in rows {
+        writer
+            .write_record([
+                r.line_number.to_string(),
+                r.email.unwrap_or_default(),
+                r.outcome,
+                r.message.unwrap_or_default(),
+            ])
+            .map_err(e500)?;
+    }
+    let body = writer
+        .into_inner()
+        .context("Failed to write the import report as CSV.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "import_{}.csv",
+                import_id
+            ))],
+        })
+        .body(body))
+}
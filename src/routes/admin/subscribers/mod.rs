@@ -0,0 +1,105 @@
+mod export;
+mod get;
+mod import;
+mod personal_data;
+mod post;
+
+pub use export::export_subscribers;
+pub use get::{list_subscribers, subscriber_details};
+pub use import::*;
+pub use personal_data::{admin_download_personal_data, admin_erase_personal_data};
+pub use post::{
+    admin_confirm_subscriber, admin_unsubscribe_subscriber, delete_subscriber, edit_subscriber,
+};
+
+/// The values taken by `subscriptions.status`.
+const STATUSES: [&str; 3] = ["pending_confirmation", "confirmed", "unsubscribed"];
+
+/// Search and filters as submitted by the form on `/admin/subscribers`:
+/// empty fields are ignored.
+#[derive(serde::Deserialize)]
+pub struct SubscriberQuery {
+    q: Option<String>,
+    status: Option<String>,
+}
+
+/// A validated `SubscriberQuery`.
+#[derive(Debug)]
+struct SubscriberFilter {
+    /// `ILIKE` pattern matched against both the email and the name
+    pattern: Option<String>,
+    status: Option<String>,
+}
+
+impl SubscriberQuery {
+    fn parse(&self) -> Result<SubscriberFilter, String> {
+        let non_empty = |v: &Option<String>| {
+            v.as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_owned)
+        };
+        let status = non_empty(&self.status);
+        if let Some(status) = &status {
+            if !STATUSES.contains(&status.as_str()) {
+                return Err(format!("{} is not a valid status.", status));
+            }
+        }
+        Ok(SubscriberFilter {
+            pattern: non_empty(&self.q).map(|q| format!("%{}%", escape_like(&q))),
+            status,
+        })
+    }
+
+    /// The search as a query string, to carry it over to other pages.
+    fn to_query_string(&self) -> String {
+        [("q", &self.q), ("status", &self.status)]
+            .into_iter()
+            .filter_map(|(key, value)| {
+                value
+                    .as_deref()
+                    .map(|v| format!("{}={}", key, urlencoding::encode(v)))
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+// `%` and `_` typed in the search box are matched literally.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberQuery;
+    use claims::assert_err;
+
+    fn query(q: &str, status: &str) -> SubscriberQuery {
+        SubscriberQuery {
+            q: Some(q.into()),
+            status: Some(status.into()),
+        }
+    }
+
+    #[test]
+    fn empty_fields_are_ignored() {
+        let filter = query(" ", "").parse().unwrap();
+        assert!(filter.pattern.is_none());
+        assert!(filter.status.is_none());
+    }
+
+    #[test]
+    fn wildcards_in_the_search_are_matched_literally() {
+        let filter = query("100%_sure", "confirmed").parse().unwrap();
+        assert_eq!(filter.pattern.as_deref(), Some(r"%100\%\_sure%"));
+        assert_eq!(filter.status.as_deref(), Some("confirmed"));
+    }
+
+    #[test]
+    fn unknown_statuses_are_rejected() {
+        assert_err!(query("", "deleted").parse());
+    }
+}
@@ -0,0 +1,102 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::get::get_subscriber;
+use crate::audit::AuditAction;
+use crate::authentication::UserId;
+use crate::personal_data::{erase_personal_data, export_personal_data, record_personal_data_event};
+use crate::routes::personal_data_response;
+use crate::utils::{e500, see_other};
+
+/// The same JSON file subscribers get when they ask for their data, for
+/// requests that reach us some other way.
+#[tracing::instrument(
+    name = "Download a subscriber's personal data",
+    skip(request, pool, user_id)
+)]
+pub async fn admin_download_personal_data(
+    subscriber_id: web::Path<Uuid>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(subscriber) = get_subscriber(subscriber_id.into_inner(), &pool)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(not_found());
+    };
+    let personal_data = export_personal_data(&pool, &subscriber.email)
+        .await
+        .map_err(e500)?;
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    record_personal_data_event(
+        &mut connection,
+        &request,
+        Some(**user_id),
+        AuditAction::SubscriberDataExported,
+        &personal_data.subscriber_ids,
+        None,
+    )
+    .await
+    .map_err(e500)?;
+    Ok(personal_data_response(&personal_data))
+}
+
+/// Erase everything tied to the subscriber's address, which can't be
+/// imported again afterwards.
+#[tracing::instrument(
+    name = "Erase a subscriber's personal data",
+    skip(request, pool, user_id)
+)]
+pub async fn admin_erase_personal_data(
+    subscriber_id: web::Path<Uuid>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(subscriber) = get_subscriber(subscriber_id.into_inner(), &pool)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(not_found());
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let subscriber_ids = erase_personal_data(&mut transaction, &subscriber.email)
+        .await
+        .map_err(e500)?;
+    record_personal_data_event(
+        &mut transaction,
+        &request,
+        Some(**user_id),
+        AuditAction::SubscriberErased,
+        &subscriber_ids,
+        None,
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase personal data.")
+        .map_err(e500)?;
+
+    FlashMessage::info("The subscriber's personal data has been erased.").send();
+    Ok(see_other("/admin/subscribers"))
+}
+
+fn not_found() -> HttpResponse {
+    FlashMessage::error("The subscriber could not be found.").send();
+    see_other("/admin/subscribers")
+}
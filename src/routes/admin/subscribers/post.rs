@@ -0,0 +1,299 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::get::get_subscriber;
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::UserId;
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::routes::{confirm_subscriber, mark_subscriber_as_unsubscribed};
+use crate::utils::{e500, see_other};
+use crate::welcome_email_worker::enqueue_welcome_email;
+
+/// Confirm a subscriber without them following the link, e.g. when they
+/// can't find the confirmation email. They get the welcome email all the same.
+#[tracing::instrument(name = "Confirm a subscriber by hand", skip(request, pool, user_id))]
+pub async fn admin_confirm_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    if get_subscriber(subscriber_id, &pool)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(not_found());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let newly_confirmed = confirm_subscriber(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `confirmed`.")
+        .map_err(e500)?;
+    if newly_confirmed {
+        enqueue_welcome_email(&mut transaction, subscriber_id)
+            .await
+            .context("Failed to enqueue the welcome email.")
+            .map_err(e500)?;
+        record_audit_event(
+            &mut *transaction,
+            &request,
+            Some(**user_id),
+            AuditAction::SubscriberConfirmed,
+            Some(&subscriber_id.to_string()),
+            None,
+        )
+        .await
+        .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")
+        .map_err(e500)?;
+
+    if newly_confirmed {
+        FlashMessage::info("The subscriber has been confirmed.")
+    } else {
+        FlashMessage::error("Only subscribers pending confirmation can be confirmed.")
+    }
+    .send();
+    Ok(see_other(&details_page(subscriber_id)))
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(request, pool, user_id))]
+pub async fn admin_unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    if get_subscriber(subscriber_id, &pool)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(not_found());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let newly_unsubscribed = mark_subscriber_as_unsubscribed(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `unsubscribed`.")
+        .map_err(e500)?;
+    if newly_unsubscribed {
+        record_audit_event(
+            &mut *transaction,
+            &request,
+            Some(**user_id),
+            AuditAction::SubscriberUnsubscribed,
+            Some(&subscriber_id.to_string()),
+            None,
+        )
+        .await
+        .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber.")
+        .map_err(e500)?;
+
+    if newly_unsubscribed {
+        FlashMessage::info("The subscriber has been unsubscribed.")
+    } else {
+        FlashMessage::error("The subscriber was already unsubscribed.")
+    }
+    .send();
+    Ok(see_other(&details_page(subscriber_id)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct EditFormData {
+    name: String,
+    email: String,
+}
+
+/// Fix the name or the email address of a subscriber, with the same
+/// validation as the subscription form.
+#[tracing::instrument(name = "Edit a subscriber", skip(form, request, pool, user_id))]
+pub async fn edit_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<EditFormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber(subscriber_id, &pool).await.map_err(e500)? else {
+        return Ok(not_found());
+    };
+    let name = match SubscriberName::parse(form.0.name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&details_page(subscriber_id)));
+        }
+    };
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&details_page(subscriber_id)));
+        }
+    };
+
+    let mut changed_fields = vec![];
+    if name.as_ref() != subscriber.name {
+        changed_fields.push("name");
+    }
+    if email.as_ref() != subscriber.email {
+        changed_fields.push("email");
+    }
+    if changed_fields.is_empty() {
+        FlashMessage::info("Nothing to change.").send();
+        return Ok(see_other(&details_page(subscriber_id)));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let updated = sqlx::query!(
+        r#"UPDATE subscriptions SET name = $1, email = $2 WHERE id = $3"#,
+        name.as_ref(),
+        email.as_ref(),
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await;
+    match updated {
+        Ok(_) => {}
+        // Left to the unique constraint, which also catches concurrent edits
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            FlashMessage::error(format!(
+                "{} is already used by another subscriber.",
+                email.as_ref()
+            ))
+            .send();
+            return Ok(see_other(&details_page(subscriber_id)));
+        }
+        Err(e) => {
+            return Err(e500(
+                anyhow::Error::new(e).context("Failed to update a subscriber."),
+            ))
+        }
+    }
+    // Pending deliveries are addressed by email
+    sqlx::query!(
+        r#"UPDATE issue_delivery_queue SET subscriber_email = $1 WHERE subscriber_email = $2"#,
+        email.as_ref(),
+        subscriber.email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to readdress the pending deliveries of a subscriber.")
+    .map_err(e500)?;
+    // Only which fields changed: the audit log outlives the subscriber's data
+    record_audit_event(
+        &mut *transaction,
+        &request,
+        Some(**user_id),
+        AuditAction::SubscriberEdited,
+        Some(&subscriber_id.to_string()),
+        Some(&format!("Changed: {}", changed_fields.join(", "))),
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to edit a subscriber.")
+        .map_err(e500)?;
+
+    FlashMessage::info("The subscriber has been updated.").send();
+    Ok(see_other(&details_page(subscriber_id)))
+}
+
+/// Remove a subscriber along with their tokens and pending deliveries.
+#[tracing::instrument(name = "Delete a subscriber", skip(request, pool, user_id))]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber(subscriber_id, &pool).await.map_err(e500)? else {
+        return Ok(not_found());
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the confirmation tokens of a subscriber.")
+    .map_err(e500)?;
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+        subscriber.email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the pending deliveries of a subscriber.")
+    .map_err(e500)?;
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete a subscriber.")
+        .map_err(e500)?;
+    record_audit_event(
+        &mut *transaction,
+        &request,
+        Some(**user_id),
+        AuditAction::SubscriberDeleted,
+        Some(&subscriber_id.to_string()),
+        None,
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber.")
+        .map_err(e500)?;
+
+    FlashMessage::info("The subscriber has been deleted.").send();
+    Ok(see_other("/admin/subscribers"))
+}
+
+fn details_page(subscriber_id: Uuid) -> String {
+    format!("/admin/subscribers/{}", subscriber_id)
+}
+
+fn not_found() -> HttpResponse {
+    FlashMessage::error("The subscriber could not be found.").send();
+    see_other("/admin/subscribers")
+}
@@ -0,0 +1,107 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use qrcode::{render::svg, QrCode};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::authentication::get_username;
+use crate::authentication::{
+    count_unused_recovery_codes, generate_totp_secret, get_totp_secret, otpauth_uri, UserId,
+};
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+/// Show the two-factor status of the current user, or the enrollment
+/// instructions if it is not turned on yet.
+pub async fn two_factor_form(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
+    let user_id = user_id.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let body_html = if get_totp_secret(*user_id, &pool)
+        .await
+        .map_err(e500)?
+        .is_some()
+    {
+        let n_recovery_codes = count_unused_recovery_codes(*user_id, &pool)
+            .await
+            .map_err(e500)?;
+        format!(
+            r#"<p>Two-factor authentication is enabled.</p>
+          <p>You have {n_recovery_codes} unused recovery codes left.</p>
+          <form action="/admin/2fa/disable" method="post">
+            <input hidden type="text" name="csrf_token" value="{csrf_token}">
+            <label>Current password
+              <input type="password" placeholder="Enter current password" name="current_password">
+            </label>
+            <button type="submit">Disable two-factor authentication</button>
+          </form>"#
+        )
+    } else {
+        // Keep the same secret across reloads until the enrollment is confirmed
+        let secret = match session.get_pending_totp_secret().map_err(e500)? {
+            Some(secret) => secret,
+            None => {
+                let secret = generate_totp_secret();
+                session.insert_pending_totp_secret(&secret).map_err(e500)?;
+                secret
+            }
+        };
+        let username = get_username(*user_id, &pool).await.map_err(e500)?;
+        let uri = otpauth_uri(&secret, &username).map_err(e500)?;
+        let qr_svg = QrCode::new(uri.as_bytes())
+            .map_err(e500)?
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .build();
+        // The XML declaration is not allowed inside an HTML document
+        let qr_svg = qr_svg
+            .find("<svg")
+            .map_or(qr_svg.as_str(), |start| &qr_svg[start..]);
+        format!(
+            r#"<p>Two-factor authentication is disabled.</p>
+          <p>Scan this QR code with your authenticator app:</p>
+          {qr_svg}
+          <p>Or add this link: <a href="{uri}">{uri}</a></p>
+          <p>Or enter this key manually: <code id="totp-secret">{secret}</code></p>
+          <form action="/admin/2fa" method="post">
+            <input hidden type="text" name="csrf_token" value="{csrf_token}">
+            <label>Code from the app
+              <input type="text" placeholder="123456" name="code" autocomplete="one-time-code">
+            </label>
+            <button type="submit">Enable two-factor authentication</button>
+          </form>"#,
+            uri = encode_minimal(&uri),
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+        <html lang="en">
+
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8">
+          <title>Two-factor authentication</title>
+        </head>
+
+        <body>
+          {msg_html}
+          {body_html}
+          <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+
+        </html>"#
+        )))
+}
@@ -0,0 +1,140 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::authentication::get_username;
+use crate::authentication::{
+    generate_recovery_codes, get_totp_secret, matching_totp_step, reserve_password_check,
+    settle_password_check, validate_credentials, AuthError, Credentials, FallbackPasswordHash,
+    UserId,
+};
+use crate::configuration::{LoginThrottlingSettings, PasswordHashingSettings};
+use crate::session_state::TypedSession;
+use crate::utils::{client_ip, e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmFormData {
+    code: String,
+}
+
+/// Finish the enrollment: the code proves the authenticator app holds the
+/// secret. The recovery codes are only ever shown in this response.
+#[tracing::instrument(
+    name = "Enable two-factor authentication",
+    skip(form, session, pool, user_id)
+)]
+pub async fn confirm_two_factor(
+    form: web::Form<ConfirmFormData>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if get_totp_secret(*user_id, &pool)
+        .await
+        .map_err(e500)?
+        .is_some()
+    {
+        FlashMessage::error("Two-factor authentication is already enabled.").send();
+        return Ok(see_other("/admin/2fa"));
+    }
+    let Some(secret) = session.get_pending_totp_secret().map_err(e500)? else {
+        return Ok(see_other("/admin/2fa"));
+    };
+    let Some(step) = matching_totp_step(&secret, form.code.trim()).map_err(e500)? else {
+        FlashMessage::error("The code is invalid. Please try again.").send();
+        return Ok(see_other("/admin/2fa"));
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    crate::authentication::enable_two_factor(*user_id, &secret, step, &recovery_codes, &pool)
+        .await
+        .map_err(e500)?;
+    session.remove_pending_totp_secret();
+
+    let mut codes_html = String::new();
+    for code in &recovery_codes {
+        writeln!(codes_html, "<li><code>{code}</code></li>").unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+        <html lang="en">
+
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8">
+          <title>Two-factor authentication</title>
+        </head>
+
+        <body>
+          <p>Two-factor authentication has been enabled.</p>
+          <p>Store these recovery codes somewhere safe. Each of them can be used once to log in
+          without your authenticator app, and they won't be shown again.</p>
+          <ul id="recovery-codes">
+            {codes_html}
+          </ul>
+          <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+
+        </html>"#
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DisableFormData {
+    current_password: Secret<String>,
+}
+
+/// The password check goes through the login throttling: a stolen session
+/// must not be a way around it to guess the password.
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip(form, request, pool, user_id, throttling, hashing, fallback_hash)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn disable_two_factor(
+    form: web::Form<DisableFormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    throttling: web::Data<LoginThrottlingSettings>,
+    hashing: web::Data<PasswordHashingSettings>,
+    fallback_hash: web::Data<FallbackPasswordHash>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let client_ip = client_ip(&request);
+    if !reserve_password_check(&username, &client_ip, &throttling, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("Too many failed attempts. Please try again later.").send();
+        return Ok(see_other("/admin/2fa"));
+    }
+    let credentials = Credentials {
+        username: username.clone(),
+        password: form.0.current_password,
+    };
+    if let Err(e) = validate_credentials(credentials, &hashing, &fallback_hash, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/2fa"))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+    settle_password_check(&username, &client_ip, &pool)
+        .await
+        .map_err(e500)?;
+
+    crate::authentication::disable_two_factor(*user_id, &pool)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/2fa"))
+}
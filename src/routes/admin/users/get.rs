@@ -0,0 +1,183 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::authentication::{Role, UserId};
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+pub async fn list_users(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        // Messages can echo back the submitted username
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let users = get_users(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for u in &users {
+        let username = encode_minimal(&u.username);
+        let status = if u.is_active { "active" } else { "deactivated" };
+        // Owners can't lock themselves out by editing their own account
+        if u.user_id == **user_id {
+            writeln!(
+                rows_html,
+                r#"<tr>
+                <td>{username} (you)</td>
+                <td>{role}</td>
+                <td>{status}</td>
+                <td></td>
+            </tr>"#,
+                role = u.role,
+            )
+            .unwrap();
+            continue;
+        }
+        let mut role_options = String::new();
+        for role in Role::ALL {
+            let selected = if role.as_str() == u.role {
+                " selected"
+            } else {
+                ""
+            };
+            write!(
+                role_options,
+                r#"<option value="{role}"{selected}>{role}</option>"#
+            )
+            .unwrap();
+        }
+        let (status_action, next_status) = if u.is_active {
+            ("Deactivate", false)
+        } else {
+            ("Reactivate", true)
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{username}</td>
+                <td>
+                    <form action="/admin/users/{id}/role" method="post">
+                        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                        <select name="role">{role_options}</select>
+                        <button type="submit">Change role</button>
+                    </form>
+                </td>
+                <td>{status}</td>
+                <td>
+                    <form action="/admin/users/{id}/status" method="post">
+                        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                        <input hidden type="text" name="is_active" value="{next_status}">
+                        <button type="submit">{status_action}</button>
+                    </form>
+                    <form action="/admin/users/{id}/delete" method="post">
+                        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                        <button type="submit">Delete</button>
+                    </form>
+                </td>
+            </tr>"#,
+            id = u.user_id,
+        )
+        .unwrap();
+    }
+
+    let mut invite_role_options = String::new();
+    for role in Role::ALL {
+        let selected = if role == Role::Viewer {
+            " selected"
+        } else {
+            ""
+        };
+        write!(
+            invite_role_options,
+            r#"<option value="{role}"{selected}>{role}</option>"#
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Users</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr>
+            <th>Username</th>
+            <th>Role</th>
+            <th>Status</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <h2>Invite a user</h2>
+    <p>Share the initial password with the new user: they can change it once logged in.</p>
+    <form action="/admin/users" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <label>Username
+            <input
+                type="text"
+                placeholder="Enter the username"
+                name="username"
+            >
+        </label>
+        <label>Role
+            <select name="role">{invite_role_options}</select>
+        </label>
+        <br>
+        <label>Initial password
+            <input
+                type="password"
+                placeholder="Enter the initial password"
+                name="password"
+            >
+        </label>
+        <label>Confirm initial password
+            <input
+                type="password"
+                placeholder="Type the initial password again"
+                name="password_check"
+            >
+        </label>
+        <br>
+        <button type="submit">Invite</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+struct UserRow {
+    user_id: Uuid,
+    username: String,
+    role: String,
+    is_active: bool,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_users(pool: &PgPool) -> Result<Vec<UserRow>, anyhow::Error> {
+    let users = sqlx::query_as!(
+        UserRow,
+        r#"SELECT user_id, username, role, is_active FROM users ORDER BY username"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the users.")?;
+    Ok(users)
+}
@@ -0,0 +1,331 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{check_password_policy, create_user, Role, UserId};
+use crate::configuration::{PasswordHashingSettings, PasswordPolicySettings};
+use crate::utils::{e500, see_other};
+
+const MAX_USERNAME_LENGTH: usize = 64;
+
+#[derive(serde::Deserialize)]
+pub struct InviteFormData {
+    username: String,
+    role: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+/// Create an account for a new admin user. The invitee logs in with the
+/// initial password chosen by the owner and can then change it.
+#[tracing::instrument(name = "Invite a user", skip(form, request, user_id, pool, hashing, policy), fields(username = %form.username))]
+pub async fn invite_user(
+    form: web::Form<InviteFormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    policy: web::Data<PasswordPolicySettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let InviteFormData {
+        username,
+        role,
+        password,
+        password_check,
+    } = form.0;
+    let username = username.trim();
+    if username.is_empty() {
+        FlashMessage::error("The username can't be empty.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    if !is_valid_username(username) {
+        FlashMessage::error(format!(
+            "Usernames can only contain letters, digits, `.`, `_` and `-`, up to {} characters.",
+            MAX_USERNAME_LENGTH
+        ))
+        .send();
+        return Ok(see_other("/admin/users"));
+    }
+    let Ok(role) = Role::try_from(role) else {
+        FlashMessage::error("Please pick a valid role.").send();
+        return Ok(see_other("/admin/users"));
+    };
+    if password.expose_secret().is_empty() {
+        FlashMessage::error("The initial password can't be empty.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    if password.expose_secret() != password_check.expose_secret() {
+        FlashMessage::error("You entered two different passwords - the field values must match.")
+            .send();
+        return Ok(see_other("/admin/users"));
+    }
+    if let Err(violation) = check_password_policy(password.expose_secret(), username, None, &policy)
+    {
+        FlashMessage::error(violation.to_string()).send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let invited_user_id = create_user(username, password, role, &hashing, &mut *transaction)
+        .await
+        .map_err(e500)?;
+    if let Some(invited_user_id) = invited_user_id {
+        record_audit_event(
+            &mut *transaction,
+            &request,
+            Some(**user_id),
+            AuditAction::UserInvited,
+            Some(&invited_user_id.to_string()),
+            Some(&format!("Invited {} as {}", username, role)),
+        )
+        .await
+        .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to invite a user.")
+        .map_err(e500)?;
+
+    match invited_user_id {
+        Some(_) => FlashMessage::info(format!("{} has been invited as {}.", username, role)),
+        None => FlashMessage::error(format!("The username {} is already taken.", username)),
+    }
+    .send();
+    Ok(see_other("/admin/users"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleFormData {
+    role: String,
+}
+
+#[tracing::instrument(name = "Change the role of a user", skip(form, request, pool, user_id))]
+pub async fn change_user_role(
+    target_user_id: web::Path<Uuid>,
+    form: web::Form<RoleFormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_user_id = target_user_id.into_inner();
+    if let Some(response) = reject_own_account(target_user_id, &user_id) {
+        return Ok(response);
+    }
+    let Ok(role) = Role::try_from(form.0.role) else {
+        FlashMessage::error("Please pick a valid role.").send();
+        return Ok(see_other("/admin/users"));
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let n_updated = sqlx::query!(
+        r#"UPDATE users SET role = $1 WHERE user_id = $2"#,
+        role.as_str(),
+        target_user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to change the role of a user.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated > 0 {
+        record_audit_event(
+            &mut *transaction,
+            &request,
+            Some(**user_id),
+            AuditAction::UserRoleChanged,
+            Some(&target_user_id.to_string()),
+            Some(&format!("New role: {}", role)),
+        )
+        .await
+        .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change the role of a user.")
+        .map_err(e500)?;
+
+    send_outcome(n_updated, "The role has been changed.");
+    Ok(see_other("/admin/users"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct StatusFormData {
+    is_active: bool,
+}
+
+/// Deactivated users can't log in, and their open sessions stop working.
+#[tracing::instrument(
+    name = "Change the status of a user",
+    skip(form, request, pool, user_id)
+)]
+pub async fn change_user_status(
+    target_user_id: web::Path<Uuid>,
+    form: web::Form<StatusFormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_user_id = target_user_id.into_inner();
+    if let Some(response) = reject_own_account(target_user_id, &user_id) {
+        return Ok(response);
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let n_updated = sqlx::query!(
+        r#"UPDATE users SET is_active = $1 WHERE user_id = $2"#,
+        form.is_active,
+        target_user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to change the status of a user.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated > 0 {
+        record_audit_event(
+            &mut *transaction,
+            &request,
+            Some(**user_id),
+            AuditAction::UserStatusChanged,
+            Some(&target_user_id.to_string()),
+            Some(if form.is_active {
+                "Reactivated"
+            } else {
+                "Deactivated"
+            }),
+        )
+        .await
+        .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change the status of a user.")
+        .map_err(e500)?;
+
+    send_outcome(
+        n_updated,
+        if form.is_active {
+            "The user has been reactivated."
+        } else {
+            "The user has been deactivated."
+        },
+    );
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Delete a user", skip(request, pool, user_id))]
+pub async fn delete_user(
+    target_user_id: web::Path<Uuid>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_user_id = target_user_id.into_inner();
+    if let Some(response) = reject_own_account(target_user_id, &user_id) {
+        return Ok(response);
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"DELETE FROM idempotency WHERE user_id = $1"#,
+        target_user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the saved responses of a user.")
+    .map_err(e500)?;
+    let n_deleted = sqlx::query!(r#"DELETE FROM users WHERE user_id = $1"#, target_user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete a user.")
+        .map_err(e500)?
+        .rows_affected();
+    if n_deleted > 0 {
+        record_audit_event(
+            &mut *transaction,
+            &request,
+            Some(**user_id),
+            AuditAction::UserDeleted,
+            Some(&target_user_id.to_string()),
+            None,
+        )
+        .await
+        .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a user.")
+        .map_err(e500)?;
+
+    send_outcome(n_deleted, "The user has been deleted.");
+    Ok(see_other("/admin/users"))
+}
+
+/// Usernames end up in pages, logs and audit details: keep them to
+/// characters that need no escaping anywhere.
+fn is_valid_username(username: &str) -> bool {
+    username.len() <= MAX_USERNAME_LENGTH
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+// Owners changing their own account could leave nobody able to manage users.
+fn reject_own_account(target_user_id: Uuid, user_id: &UserId) -> Option<HttpResponse> {
+    if target_user_id != **user_id {
+        return None;
+    }
+    FlashMessage::error("You can't change your own account from this page.").send();
+    Some(see_other("/admin/users"))
+}
+
+fn send_outcome(n_affected: u64, success_message: &str) {
+    if n_affected == 0 {
+        FlashMessage::error("The user could not be found.").send();
+    } else {
+        FlashMessage::info(success_message).send();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_username;
+
+    #[test]
+    fn usernames_are_restricted_to_a_safe_character_set() {
+        assert!(is_valid_username("new-editor"));
+        assert!(is_valid_username("jane.doe_2"));
+        for username in [
+            "<script>alert(1)</script>",
+            "jane doe",
+            "jané",
+            &"a".repeat(65),
+        ] {
+            assert!(!is_valid_username(username), "{} was accepted", username);
+        }
+    }
+}
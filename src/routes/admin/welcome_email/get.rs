@@ -0,0 +1,84 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+use crate::welcome_email_worker::get_welcome_email;
+
+pub async fn welcome_email_form(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = session.csrf_token().map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+    let template = get_welcome_email(&pool)
+        .await
+        .context("Failed to retrieve the welcome email.")
+        .map_err(e500)?;
+    let checked = if template.enabled { "checked" } else { "" };
+    let subject = encode_minimal(&template.subject);
+    let text_content = encode_minimal(&template.text_content);
+    let html_content = encode_minimal(&template.html_content);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Welcome email</title>
+</head>
+<body>
+    {msg_html}
+    <p>This email is sent to every subscriber once they confirm their subscription.</p>
+    <form action="/admin/welcome_email" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <label>
+            <input type="checkbox" name="enabled" value="true" {checked}>
+            Send the welcome email
+        </label>
+        <br>
+        <label>Subject:<br>
+            <input
+                type="text"
+                placeholder="Enter the email subject"
+                name="subject"
+                value="{subject}"
+            >
+        </label>
+        <br>
+        <label>Plain text content:<br>
+            <textarea
+                placeholder="Enter the content in plain text"
+                name="text_content"
+                rows="20"
+                cols="50"
+            >{text_content}</textarea>
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea
+                placeholder="Enter the content in HTML format"
+                name="html_content"
+                rows="20"
+                cols="50"
+            >{html_content}</textarea>
+        </label>
+        <br>
+        <button type="submit">Save</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
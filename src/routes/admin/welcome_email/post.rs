@@ -0,0 +1,55 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    // Unchecked checkboxes are not submitted at all
+    #[serde(default)]
+    enabled: bool,
+    subject: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(name = "Update the welcome email", skip(form, pool))]
+pub async fn update_welcome_email(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.0;
+    if form.subject.trim().is_empty()
+        || form.text_content.trim().is_empty()
+        || form.html_content.trim().is_empty()
+    {
+        FlashMessage::error("The subject and both contents of the welcome email are required.")
+            .send();
+        return Ok(see_other("/admin/welcome_email"));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE welcome_email
+        SET
+            enabled = $1,
+            subject = $2,
+            text_content = $3,
+            html_content = $4,
+            updated_at = now()
+        "#,
+        form.enabled,
+        form.subject,
+        form.text_content,
+        form.html_content
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to update the welcome email.")
+    .map_err(e500)?;
+
+    FlashMessage::info("The welcome email has been updated.").send();
+    Ok(see_other("/admin/welcome_email"))
+}
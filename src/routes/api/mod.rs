@@ -0,0 +1,3 @@
+mod newsletters;
+
+pub use newsletters::{api_json_config, api_publish_newsletter};
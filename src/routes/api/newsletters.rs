@@ -0,0 +1,106 @@
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{ApiScope, ApiToken};
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::routes::publish_issue;
+use crate::utils::e500;
+use actix_web::error::InternalError;
+use actix_web::web::ReqData;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    idempotency_key: String,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue through the API",
+    skip(body, request, pool, token),
+    fields(user_id=%token.user_id, api_token_id=%token.api_token_id)
+)]
+pub async fn api_publish_newsletter(
+    body: web::Json<BodyData>,
+    request: HttpRequest,
+    token: ReqData<ApiToken>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token = token.into_inner();
+    if !token.allows(ApiScope::PublishNewsletters) {
+        return Err(api_error(
+            HttpResponse::Forbidden(),
+            "forbidden",
+            format!(
+                "The API token does not grant the '{}' scope.",
+                ApiScope::PublishNewsletters
+            ),
+        ));
+    }
+    let BodyData {
+        title,
+        text_content,
+        html_content,
+        idempotency_key,
+    } = body.0;
+    let idempotency_key: IdempotencyKey =
+        idempotency_key.try_into().map_err(|e: anyhow::Error| {
+            api_error(HttpResponse::BadRequest(), "invalid_request", e.to_string())
+        })?;
+    let mut transaction = match try_processing(&pool, &idempotency_key, token.user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+
+    let issue_id = publish_issue(
+        &mut transaction,
+        &title,
+        &text_content,
+        &html_content,
+        Some(token.api_token_id),
+    )
+    .await
+    .map_err(e500)?;
+    record_audit_event(
+        &mut *transaction,
+        &request,
+        Some(token.user_id),
+        AuditAction::IssuePublished,
+        Some(&issue_id.to_string()),
+        Some(&format!("Published with API token {}", token.api_token_id)),
+    )
+    .await
+    .map_err(e500)?;
+
+    let response = HttpResponse::Accepted().json(serde_json::json!({
+        "status": "accepted",
+        "newsletter_issue_id": issue_id.to_string(),
+    }));
+    let response = save_response(transaction, &idempotency_key, token.user_id, response)
+        .await
+        .map_err(e500)?;
+    Ok(response)
+}
+
+/// Reject malformed JSON bodies with the same error shape as the handlers.
+pub fn api_json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|e, _req| {
+        api_error(HttpResponse::BadRequest(), "invalid_request", e.to_string())
+    })
+}
+
+fn api_error(
+    mut builder: actix_web::HttpResponseBuilder,
+    error: &str,
+    message: String,
+) -> actix_web::Error {
+    let response = builder.json(serde_json::json!({
+        "error": error,
+        "message": message,
+    }));
+    InternalError::from_response(anyhow::anyhow!(message), response).into()
+}
@@ -1,14 +1,23 @@
 use actix_web::{http::header::ContentType, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
 use std::fmt::Write;
 
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+pub async fn login_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Anonymous sessions get a CSRF token too, against login CSRF
+    let csrf_token = session.csrf_token().map_err(e500)?;
     let mut error_html = String::new();
     for m in flash_messages.iter() {
-        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
+        writeln!(error_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
     }
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
             r#"<!DOCTYPE html>
@@ -22,6 +31,7 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
             <body>
               {error_html}
               <form action="/login" method="post">
+                <input hidden type="text" name="csrf_token" value="{csrf_token}">
                 <label>Username
                   <input type="text" placeholder="Enter Username" name="username">
                 </label>
@@ -30,8 +40,9 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
                 </label>
                 <button type="submit">Login</button>
               </form>
+              <p><a href="/password_reset">Forgot your password?</a></p>
             </body>
 
             </html>"#,
-        ))
+        )))
 }
@@ -1,7 +1,7 @@
 use actix_web::{
     error::InternalError,
     http::{header::LOCATION, StatusCode},
-    web, HttpResponse, ResponseError,
+    web, HttpRequest, HttpResponse, ResponseError,
 };
 use actix_web_flash_messages::FlashMessage;
 use secrecy::Secret;
@@ -9,9 +9,15 @@ use serde::Deserialize;
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials},
+    audit::{record_audit_event, AuditAction},
+    authentication::{
+        get_session_generation, get_totp_secret, reserve_password_check, settle_password_check,
+        start_session, validate_credentials, AuthError, Credentials, FallbackPasswordHash,
+    },
+    configuration::{LoginThrottlingSettings, PasswordHashingSettings},
     routes::error_chain_fmt,
     session_state::TypedSession,
+    utils::client_ip,
 };
 
 #[derive(Deserialize)]
@@ -21,33 +27,98 @@ pub struct FormData {
 }
 
 #[tracing::instrument(
-    skip(form, pool, session),
-    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    skip(form, request, pool, session, throttling, hashing, fallback_hash),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+        client_ip=tracing::field::Empty
+    )
 )]
 pub async fn login(
     form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     session: TypedSession,
+    throttling: web::Data<LoginThrottlingSettings>,
+    hashing: web::Data<PasswordHashingSettings>,
+    fallback_hash: web::Data<FallbackPasswordHash>,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
+    let client_ip = client_ip(&request);
 
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    tracing::Span::current().record("client_ip", tracing::field::display(&client_ip));
 
-    match validate_credentials(credentials, &pool).await {
+    // Attempts count as failures until proven otherwise: refuse throttled ones
+    // before paying for the password hash verification, and make concurrent
+    // ones wait for their turn
+    if !reserve_password_check(&credentials.username, &client_ip, &throttling, &pool)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+    {
+        tracing::info!("Refusing a throttled login attempt.");
+        return Err(login_redirect(LoginError::TooManyAttempts));
+    }
+
+    let username = credentials.username.clone();
+    match validate_credentials(credentials, &hashing, &fallback_hash, &pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            settle_password_check(&username, &client_ip, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            let two_factor_enabled = get_totp_secret(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+                .is_some();
             session.renew();
+            if two_factor_enabled {
+                session
+                    .insert_awaiting_totp_user_id(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/login/2fa"))
+                    .finish());
+            }
+            let session_generation = get_session_generation(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            let session_id = start_session(user_id, &request, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             session
-                .insert_user_id(user_id)
+                .insert_user_id(user_id, session_generation, session_id)
                 .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            record_audit_event(
+                &**pool,
+                &request,
+                Some(user_id),
+                AuditAction::LoginSucceeded,
+                None,
+                None,
+            )
+            .await
+            .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             Ok(HttpResponse::SeeOther()
                 .insert_header((LOCATION, "/admin/dashboard"))
                 .finish())
         }
         Err(e) => {
+            if let AuthError::InvalidCredentials(_) = e {
+                record_audit_event(
+                    &**pool,
+                    &request,
+                    None,
+                    AuditAction::LoginFailed,
+                    Some(&username),
+                    Some("Invalid credentials"),
+                )
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            }
             let e = match e {
                 AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
@@ -66,6 +137,8 @@ pub async fn login(
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts. Please try again later.")]
+    TooManyAttempts,
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -0,0 +1,48 @@
+use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use std::fmt::Write;
+
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+
+pub async fn login_two_factor_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_awaiting_totp_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+    let csrf_token = session.csrf_token().map_err(e500)?;
+
+    let mut error_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(error_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+
+            <head>
+              <meta http-equiv="content-type" content="text/html; charset=utf-8">
+              <title>Two-factor authentication</title>
+            </head>
+
+            <body>
+              {error_html}
+              <p>Enter the code from your authenticator app, or one of your recovery codes.</p>
+              <form action="/login/2fa" method="post">
+                <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                <label>Code
+                  <input type="text" placeholder="123456" name="code" autocomplete="one-time-code">
+                </label>
+                <button type="submit">Verify</button>
+              </form>
+            </body>
+
+            </html>"#,
+        )))
+}
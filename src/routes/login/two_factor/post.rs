@@ -0,0 +1,105 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use std::time::Duration;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    clear_failed_second_factors, get_session_generation, is_second_factor_locked,
+    record_failed_second_factor, start_session, validate_second_factor,
+};
+use crate::configuration::LoginThrottlingSettings;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+
+// Past this many wrong codes, counted across logins, the second factor is
+// locked out for the configured lockout period.
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: String,
+}
+
+/// Second step of the login for users with two-factor authentication:
+/// they are only logged in once a TOTP or recovery code checks out.
+#[tracing::instrument(
+    skip(form, request, pool, session, throttling),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn login_two_factor(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    throttling: web::Data<LoginThrottlingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session.get_awaiting_totp_user_id().map_err(e500)? else {
+        return Ok(see_other("/login"));
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    if is_second_factor_locked(user_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        tracing::info!("Refusing a second factor while it is locked out.");
+        return Ok(reset_login(&session));
+    }
+    if validate_second_factor(user_id, &form.code, &pool)
+        .await
+        .map_err(e500)?
+    {
+        clear_failed_second_factors(user_id, &pool)
+            .await
+            .map_err(e500)?;
+        let session_generation = get_session_generation(user_id, &pool).await.map_err(e500)?;
+        let session_id = start_session(user_id, &request, &pool)
+            .await
+            .map_err(e500)?;
+        session.renew();
+        session.remove_awaiting_totp_user_id();
+        session
+            .insert_user_id(user_id, session_generation, session_id)
+            .map_err(e500)?;
+        record_audit_event(
+            &**pool,
+            &request,
+            Some(user_id),
+            AuditAction::LoginSucceeded,
+            None,
+            Some("With a second factor"),
+        )
+        .await
+        .map_err(e500)?;
+        return Ok(see_other("/admin/dashboard"));
+    }
+
+    record_audit_event(
+        &**pool,
+        &request,
+        Some(user_id),
+        AuditAction::LoginFailed,
+        None,
+        Some("Invalid second factor"),
+    )
+    .await
+    .map_err(e500)?;
+    let lockout = Duration::from_secs(throttling.lockout_seconds);
+    if record_failed_second_factor(user_id, MAX_FAILED_ATTEMPTS, lockout, &pool)
+        .await
+        .map_err(e500)?
+    {
+        tracing::warn!("Too many invalid two-factor codes, the second factor is locked out.");
+        return Ok(reset_login(&session));
+    }
+    FlashMessage::error("The code is invalid.").send();
+    Ok(see_other("/login/2fa"))
+}
+
+// The password has to be entered again once the lockout is over.
+fn reset_login(session: &TypedSession) -> HttpResponse {
+    session.remove_awaiting_totp_user_id();
+    FlashMessage::error("Too many invalid codes. Please try again later.").send();
+    see_other("/login")
+}
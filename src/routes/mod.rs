@@ -1,13 +1,23 @@
 mod admin;
+mod api;
 mod health_check;
 mod home;
 mod login;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_personal_data;
+mod subscriptions_resend_confirmation;
+mod subscriptions_unsubscribe;
 
 pub use admin::*;
+pub use api::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
+pub use password_reset::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_personal_data::*;
+pub use subscriptions_resend_confirmation::*;
+pub use subscriptions_unsubscribe::*;
@@ -0,0 +1,97 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::authentication::check_password_reset_token;
+use crate::utils::{e500, see_other};
+
+pub async fn password_reset_request_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+
+            <head>
+              <meta http-equiv="content-type" content="text/html; charset=utf-8">
+              <title>Forgot password</title>
+            </head>
+
+            <body>
+              {msg_html}
+              <p>Enter the email address of your account and we will send you a link to choose a new password.</p>
+              <form action="/password_reset" method="post">
+                <label>Email
+                  <input type="email" placeholder="Enter your email" name="email">
+                </label>
+                <button type="submit">Send reset link</button>
+              </form>
+              <p><a href="/login">&lt;- Back to login</a></p>
+            </body>
+
+            </html>"#,
+        ))
+}
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+pub async fn password_reset_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if check_password_reset_token(&parameters.token, &pool)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        FlashMessage::error("This password reset link is invalid or has expired.").send();
+        return Ok(see_other("/password_reset"));
+    }
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+    let token = encode_minimal(&parameters.token);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+
+            <head>
+              <meta http-equiv="content-type" content="text/html; charset=utf-8">
+              <title>Reset password</title>
+            </head>
+
+            <body>
+              {msg_html}
+              <form action="/password_reset/confirm" method="post">
+                <input hidden type="text" name="token" value="{token}">
+                <label>New password
+                  <input type="password" placeholder="Enter new password" name="new_password">
+                </label>
+                <br>
+                <label>Confirm new password
+                  <input type="password" placeholder="Type the new password again" name="new_password_check">
+                </label>
+                <br>
+                <button type="submit">Reset password</button>
+              </form>
+            </body>
+
+            </html>"#,
+        )))
+}
@@ -0,0 +1,182 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::get_username;
+use crate::authentication::{
+    check_password_policy, check_password_reset_token, enqueue_password_reset_email,
+    password_reset_throttle_keys,
+};
+use crate::configuration::{PasswordHashingSettings, PasswordPolicySettings};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::routes::try_claim_resend_slot;
+use crate::startup::ConfirmationResendCooldown;
+use crate::utils::{client_ip, e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct RequestFormData {
+    email: String,
+}
+
+/// Email a reset link to the user with this address, if there is one.
+///
+/// The request is queued either way, and the worker drops it if no user has
+/// the address: neither the answer nor how long it takes may reveal which
+/// emails are known.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, request, pool, cooldown),
+    fields(client_ip = tracing::field::Empty)
+)]
+pub async fn request_password_reset(
+    form: web::Form<RequestFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    cooldown: web::Data<ConfirmationResendCooldown>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = form.0.email.trim().to_owned();
+    let client_ip = client_ip(&request);
+    tracing::Span::current().record("client_ip", tracing::field::display(&client_ip));
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let [ip_key, address_key] = password_reset_throttle_keys(&email, &client_ip);
+    for (throttle_key, cooldown) in [
+        (ip_key, cooldown.per_ip),
+        (address_key, cooldown.per_address),
+    ] {
+        if !try_claim_resend_slot(&mut transaction, &throttle_key, cooldown)
+            .await
+            .context("Failed to check the password reset request cooldown.")
+            .map_err(e500)?
+        {
+            FlashMessage::error("Too many password reset requests. Please try again later.").send();
+            return Ok(see_other("/password_reset"));
+        }
+    }
+    enqueue_password_reset_email(&mut transaction, &email)
+        .await
+        .context("Failed to queue a password reset email.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a password reset request.")
+        .map_err(e500)?;
+
+    FlashMessage::info(
+        "If an account uses this email, we have sent it a link to reset the password.",
+    )
+    .send();
+    Ok(see_other("/login"))
+}
+
+#[tracing::instrument(
+    name = "Send a password reset email",
+    skip(email_client, recipient, base_url, token)
+)]
+pub async fn send_password_reset_email(
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
+    username: &str,
+    base_url: &str,
+    token: &str,
+    ttl_minutes: u64,
+) -> Result<(), anyhow::Error> {
+    let reset_link = format!("{}/password_reset/confirm?token={}", base_url, token);
+    let plain_body = format!(
+        "Someone asked to reset the password of the {} account.\n\
+        Visit {} to choose a new one. The link expires in {} minutes.\n\
+        If it wasn't you, you can ignore this email.",
+        username, reset_link, ttl_minutes
+    );
+    let html_body = format!(
+        "Someone asked to reset the password of the {} account.<br />\
+        Click <a href=\"{}\">here</a> to choose a new one. The link expires in {} minutes.<br />\
+        If it wasn't you, you can ignore this email.",
+        htmlescape::encode_minimal(username),
+        reset_link,
+        ttl_minutes
+    );
+    email_client
+        .send_email(recipient, "Reset your password", &html_body, &plain_body)
+        .await
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetFormData {
+    token: String,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[tracing::instrument(name = "Reset a password", skip(form, request, pool, hashing, policy))]
+pub async fn reset_password(
+    form: web::Form<ResetFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    policy: web::Data<PasswordPolicySettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ResetFormData {
+        token,
+        new_password,
+        new_password_check,
+    } = form.0;
+    let form_url = format!(
+        "/password_reset/confirm?token={}",
+        urlencoding::encode(&token)
+    );
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&form_url));
+    }
+    let Some(user_id) = check_password_reset_token(&token, &pool)
+        .await
+        .map_err(e500)?
+    else {
+        FlashMessage::error("This password reset link is invalid or has expired.").send();
+        return Ok(see_other("/password_reset"));
+    };
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    if let Err(violation) =
+        check_password_policy(new_password.expose_secret(), &username, None, &policy)
+    {
+        FlashMessage::error(violation.to_string()).send();
+        return Ok(see_other(&form_url));
+    }
+
+    match crate::authentication::reset_password(&token, new_password, &hashing, &pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => {
+            record_audit_event(
+                &**pool,
+                &request,
+                Some(user_id),
+                AuditAction::PasswordReset,
+                None,
+                None,
+            )
+            .await
+            .map_err(e500)?;
+            FlashMessage::info("Your password has been reset. You can now log in.").send();
+            Ok(see_other("/login"))
+        }
+        None => {
+            FlashMessage::error("This password reset link is invalid or has expired.").send();
+            Ok(see_other("/password_reset"))
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use crate::{
+    confirmation_email_worker::enqueue_confirmation_email,
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
-    startup::ApplicationBaseUrl,
+    email_client::EmailSender,
+    routes::try_claim_resend_slot,
+    startup::{ConfirmationResendCooldown, SubscriptionTokenTtl},
+    utils::client_ip,
 };
-use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
@@ -13,8 +16,8 @@ use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct FormData {
-    name: String,
-    email: String,
+    pub(crate) name: String,
+    pub(crate) email: String,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -51,7 +54,7 @@ impl ResponseError for SubscribeError {
     }
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -61,51 +64,164 @@ fn generate_subscription_token() -> String {
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, request, pool, token_ttl, cooldown),
     fields(
         subscriber_email = %form.email,
-        subscriber_name= %form.name
+        subscriber_name= %form.name,
+        client_ip = tracing::field::Empty
     )
 )]
 
 pub async fn subscribe(
     form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    base_url: web::Data<ApplicationBaseUrl>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    cooldown: web::Data<ConfirmationResendCooldown>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let client_ip = client_ip(&request);
+    tracing::Span::current().record("client_ip", tracing::field::display(&client_ip));
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    // Subscribing again while pending sends a new confirmation email, so it
+    // shares the cooldowns of `/subscriptions/resend-confirmation`. They are
+    // claimed whatever the address, for throttling not to reveal who is on
+    // the list; the ip one only if the address one was free.
+    let mut may_send = true;
+    for (throttle_key, cooldown) in [
+        (
+            format!("address:{}", new_subscriber.email.as_ref().to_lowercase()),
+            cooldown.per_address,
+        ),
+        (format!("ip:{}", client_ip), cooldown.per_ip),
+    ] {
+        may_send = try_claim_resend_slot(&mut transaction, &throttle_key, cooldown)
+            .await
+            .context("Failed to check the confirmation email cooldown.")?;
+        if !may_send {
+            break;
+        }
+    }
+
+    let existing_subscriber = get_subscriber_by_email(&mut transaction, &new_subscriber.email)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to look up the subscriber in the database.")?;
+    let existing_subscriber = match existing_subscriber {
+        Some(subscriber) => subscriber,
+        None => match insert_subscriber(&mut transaction, &new_subscriber)
+            .await
+            .context("Failed to insert new subscriber in the database.")?
+        {
+            Some(subscriber_id) => ExistingSubscriber {
+                id: subscriber_id,
+                status: "pending_confirmation".into(),
+            },
+            // The same address was subscribed concurrently: carry on as if
+            // it had been there all along.
+            None => get_subscriber_by_email(&mut transaction, &new_subscriber.email)
+                .await
+                .context("Failed to look up the subscriber in the database.")?
+                .context("The subscriber we collided with has disappeared.")?,
+        },
+    };
+    let subscriber_id = match existing_subscriber {
+        // Answer exactly as we would for a new address: the response must not
+        // reveal who is already on the list. Confirmation emails are queued
+        // rather than sent, so that it doesn't take longer either.
+        subscriber if subscriber.status == "confirmed" => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a new subscriber.")?;
+            return Ok(HttpResponse::Ok().finish());
+        }
+        // Same answer again, without an email: the address or the client
+        // got one too recently
+        _ if !may_send => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a new subscriber.")?;
+            return Ok(HttpResponse::Ok().finish());
+        }
+        subscriber => {
+            if subscriber.status == "unsubscribed" {
+                restart_double_opt_in(&mut transaction, subscriber.id)
+                    .await
+                    .context("Failed to reset the status of a returning subscriber.")?;
+            }
+            subscriber.id
+        }
+    };
 
     let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        &subscription_token,
+        token_ttl.0,
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
+    enqueue_confirmation_email(&mut transaction, &subscription_token)
         .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+        .context("Failed to queue a confirmation email.")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
 
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub struct ExistingSubscriber {
+    pub id: Uuid,
+    pub status: String,
+}
+
+#[tracing::instrument(name = "Get subscriber by email", skip(email, transaction))]
+pub async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        email.as_ref(),
     )
+    .fetch_optional(&mut **transaction)
     .await
-    .context("Failed to send a confirmation email.")?;
+}
 
-    Ok(HttpResponse::Ok().finish())
+#[tracing::instrument(
+    name = "Restart double opt-in for a returning subscriber",
+    skip(transaction)
+)]
+async fn restart_double_opt_in(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'pending_confirmation', subscribed_at = now()
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
 }
 
+/// Returns `None` if the address is already taken, e.g. by a subscription
+/// made concurrently.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -113,23 +229,24 @@ pub async fn subscribe(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
         r#"
         INSERT INTO subscriptions (id, email, name, subscribed_at, status)
         VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now(),
         "pending_confirmation"
     )
-    .execute(&mut **transaction)
+    .fetch_optional(&mut **transaction)
     .await?;
 
-    Ok(subscriber_id)
+    Ok(row.map(|r| r.id))
 }
 
 #[tracing::instrument(
@@ -140,12 +257,16 @@ pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
+    ttl: std::time::Duration,
 ) -> Result<(), StoreTokenError> {
+    let expires_at = Utc::now()
+        + chrono::Duration::from_std(ttl).expect("The subscription token TTL is out of range.");
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-            VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, created_at, expires_at)
+            VALUES ($1, $2, now(), $3)"#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        expires_at
     )
     .execute(&mut **transaction)
     .await
@@ -156,14 +277,14 @@ pub async fn store_token(
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url)
+    skip(email_client, recipient, base_url)
 )]
 pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
@@ -178,7 +299,7 @@ Click <a href=\"{}\">here</a> to confirm your subscription.",
         confirmation_link
     );
     email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
+        .send_email(recipient, "Welcome!", &html_body, &plain_body)
         .await
 }
 
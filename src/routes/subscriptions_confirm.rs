@@ -1,10 +1,14 @@
-use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use actix_web::http::header::ContentType;
+use actix_web::http::header::{Accept, Header};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use super::error_chain_fmt;
+use crate::welcome_email_worker::enqueue_welcome_email;
 
 #[derive(Deserialize)]
 pub struct Parameters {
@@ -17,6 +21,8 @@ pub enum ConfirmationError {
     UnexpectedError(#[from] anyhow::Error),
     #[error("There is no subscriber associated with the provided token.")]
     UnknownToken,
+    #[error("The confirmation token has expired.")]
+    ExpiredToken,
 }
 
 impl std::fmt::Debug for ConfirmationError {
@@ -29,49 +35,176 @@ impl ResponseError for ConfirmationError {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
             Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters))]
+pub enum ConfirmationOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+}
+
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, request, pool))]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ConfirmationError> {
-    let subscriber_id = get_subscriber_id_from_token(&pool, &parameters.subscription_token)
+    // Subscribers land here from their inbox and get a page telling them
+    // what happened, API clients asking for JSON get a machine-readable answer.
+    let wants_json = Accept::parse(&request)
+        .map(|accept| accept.preference().essence_str() == "application/json")
+        .unwrap_or(false);
+    let (status, code, title, message) =
+        match try_confirm(&pool, &parameters.subscription_token).await {
+            Ok(ConfirmationOutcome::Confirmed) => (
+                StatusCode::OK,
+                "confirmed",
+                "Subscription confirmed",
+                "Thanks for confirming your subscription! \
+             You will receive our next issue as soon as it is out.",
+            ),
+            Ok(ConfirmationOutcome::AlreadyConfirmed) => (
+                StatusCode::OK,
+                "already_confirmed",
+                "Subscription already confirmed",
+                "Your subscription is already confirmed, there is nothing else to do.",
+            ),
+            Err(e @ ConfirmationError::UnknownToken) => (
+                e.status_code(),
+                "unknown_token",
+                "Invalid confirmation link",
+                "This confirmation link is not valid. \
+             Make sure you copied the whole link from the email, or subscribe again.",
+            ),
+            Err(e @ ConfirmationError::ExpiredToken) => (
+                e.status_code(),
+                "expired_token",
+                "Confirmation link expired",
+                "This confirmation link has expired. \
+             Please subscribe again to receive a new one.",
+            ),
+            Err(e) => return Err(e),
+        };
+
+    if wants_json {
+        let key = if status.is_success() {
+            "status"
+        } else {
+            "error"
+        };
+        return Ok(HttpResponse::build(status).json(serde_json::json!({
+            key: code,
+            "message": message,
+        })));
+    }
+    Ok(HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>{message}</p>
+</body>
+</html>"#
+        )))
+}
+
+async fn try_confirm(
+    pool: &PgPool,
+    subscription_token: &str,
+) -> Result<ConfirmationOutcome, ConfirmationError> {
+    let token = get_subscriber_id_from_token(pool, subscription_token)
         .await
         .context("Failed to retrieve the subscriber id associated with the provided token.")?
         .ok_or(ConfirmationError::UnknownToken)?;
+    // Following the link again after it expired must still tell a confirmed
+    // subscriber that there is nothing left to do
+    if token.status == "confirmed" {
+        return Ok(ConfirmationOutcome::AlreadyConfirmed);
+    }
+    if token.expires_at < Utc::now() {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+    let subscriber_id = token.subscriber_id;
 
-    confirm_subscriber(&pool, subscriber_id)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let newly_confirmed = confirm_subscriber(&mut transaction, subscriber_id)
         .await
         .context("Failed to update the subscriber status to `confirmed`.")?;
+    if !newly_confirmed {
+        // Unsubscribing drops the pending tokens, an old one must not bring
+        // the subscription back
+        return Err(ConfirmationError::UnknownToken);
+    }
+    // The welcome email goes through a queue: a slow or failing email
+    // provider must not get in the way of the confirmation itself.
+    enqueue_welcome_email(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to enqueue the welcome email.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(ConfirmationOutcome::Confirmed)
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+/// Only subscribers pending confirmation get confirmed: returns `false` for
+/// the others, be they confirmed already or unsubscribed.
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, transaction)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed', confirmed_at = now()
+        WHERE id = $1 AND status = 'pending_confirmation'
+        "#,
         subscriber_id,
     )
-    .execute(pool)
-    .await?;
-    Ok(())
+    .execute(&mut **transaction)
+    .await?
+    .rows_affected();
+    Ok(n_updated == 1)
+}
+
+pub struct StoredToken {
+    pub subscriber_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    /// The current status of the subscriber
+    pub status: String,
 }
 
 #[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
 pub async fn get_subscriber_id_from_token(
     pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredToken,
+        r#"
+        SELECT t.subscriber_id, t.expires_at, s.status
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
+        "#,
         subscription_token,
     )
     .fetch_optional(pool)
-    .await?;
-    Ok(result.map(|r| r.subscriber_id))
+    .await
 }
@@ -0,0 +1,353 @@
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use super::{error_chain_fmt, try_claim_resend_slot};
+use crate::audit::AuditAction;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::personal_data::{
+    check_personal_data_link_token, enqueue_personal_data_email, erase_personal_data,
+    export_personal_data, personal_data_throttle_key, record_personal_data_event, PersonalData,
+};
+use crate::startup::ConfirmationResendCooldown;
+use crate::utils::client_ip;
+
+/// What a subscriber can ask about the data we hold on their address.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PersonalDataRequest {
+    /// A copy of the data
+    Access,
+    /// Erasing the data, subscription included
+    Erasure,
+}
+
+impl PersonalDataRequest {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PersonalDataRequest::Access => "access",
+            PersonalDataRequest::Erasure => "erasure",
+        }
+    }
+
+    fn path(&self) -> &'static str {
+        match self {
+            PersonalDataRequest::Access => "/subscriptions/personal-data",
+            PersonalDataRequest::Erasure => "/subscriptions/personal-data/erase",
+        }
+    }
+}
+
+impl TryFrom<String> for PersonalDataRequest {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "access" => Ok(Self::Access),
+            "erasure" => Ok(Self::Erasure),
+            other => Err(format!(
+                "{} is not a personal data request. Use either `access` or `erasure`.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PersonalDataRequestFormData {
+    email: String,
+    request: PersonalDataRequest,
+}
+
+#[derive(Deserialize)]
+pub struct PersonalDataLinkParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum PersonalDataError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Too many requests were made for this address, please try again later.")]
+    TooManyRequests,
+    #[error("The link is invalid or has expired.")]
+    InvalidLink(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PersonalDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PersonalDataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) | Self::InvalidLink(_) => StatusCode::BAD_REQUEST,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Build the link that carries out `request`.
+///
+/// The token stands for the address, which stays out of the link: links
+/// end up in browser histories and server logs.
+pub fn personal_data_link(base_url: &str, request: PersonalDataRequest, token: &str) -> String {
+    format!("{}{}?token={}", base_url, request.path(), token)
+}
+
+impl PersonalDataLinkParameters {
+    /// The address the link was sent to.
+    async fn verify(
+        &self,
+        request: PersonalDataRequest,
+        pool: &PgPool,
+    ) -> Result<String, PersonalDataError> {
+        check_personal_data_link_token(pool, &self.token, request)
+            .await?
+            .ok_or_else(|| {
+                PersonalDataError::InvalidLink(anyhow::anyhow!(
+                    "The personal data link is unknown, expired or for another request."
+                ))
+            })
+    }
+
+    fn query_string(&self) -> String {
+        format!("token={}", urlencoding::encode(&self.token))
+    }
+}
+
+/// Email a link to see, or to erase, the data we hold on an address.
+///
+/// The request is queued whether or not we hold anything on the address, and
+/// the worker drops it if we don't: neither the answer nor how long it takes
+/// may reveal who is on the list.
+#[tracing::instrument(
+    name = "Request personal data",
+    skip(form, request, pool, cooldown),
+    fields(request_kind = ?form.request, client_ip = tracing::field::Empty)
+)]
+pub async fn request_personal_data(
+    form: web::Form<PersonalDataRequestFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    cooldown: web::Data<ConfirmationResendCooldown>,
+) -> Result<HttpResponse, PersonalDataError> {
+    let PersonalDataRequestFormData {
+        email,
+        request: kind,
+    } = form.0;
+    let email = SubscriberEmail::parse(email).map_err(PersonalDataError::ValidationError)?;
+    let client_ip = client_ip(&request);
+    tracing::Span::current().record("client_ip", tracing::field::display(&client_ip));
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    for (throttle_key, cooldown) in [
+        (format!("personal-data:ip:{}", client_ip), cooldown.per_ip),
+        (
+            personal_data_throttle_key(email.as_ref()),
+            cooldown.per_address,
+        ),
+    ] {
+        if !try_claim_resend_slot(&mut transaction, &throttle_key, cooldown)
+            .await
+            .context("Failed to check the personal data request cooldown.")?
+        {
+            return Err(PersonalDataError::TooManyRequests);
+        }
+    }
+    enqueue_personal_data_email(&mut transaction, email.as_ref(), kind)
+        .await
+        .context("Failed to queue a personal data link.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a personal data request.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Email the link that carries out `kind`, valid for `ttl_minutes`.
+pub async fn send_personal_data_link(
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
+    kind: PersonalDataRequest,
+    link: &str,
+    ttl_minutes: u64,
+) -> Result<(), anyhow::Error> {
+    let (subject, what) = match kind {
+        PersonalDataRequest::Access => (
+            "Your personal data",
+            "a copy of the personal data we hold on this address",
+        ),
+        PersonalDataRequest::Erasure => (
+            "Erasing your personal data",
+            "to erase the personal data we hold on this address, subscription included",
+        ),
+    };
+    let plain_body = format!(
+        "We received a request for {what}.\n\
+        Visit {link} to go ahead. The link expires in {ttl_minutes} minutes.\n\
+        If you didn't ask for it, you can ignore this email."
+    );
+    let html_body = format!(
+        "We received a request for {what}.<br />\
+        Click <a href=\"{link}\">here</a> to go ahead. The link expires in {ttl_minutes} minutes.<br />\
+        If you didn't ask for it, you can ignore this email."
+    );
+    email_client
+        .send_email(recipient, subject, &html_body, &plain_body)
+        .await
+}
+
+/// Ask for confirmation before acting: link scanners and mail previews
+/// follow `GET` links on their own.
+#[tracing::instrument(name = "Show the personal data access form", skip_all)]
+pub async fn personal_data_access_form(
+    parameters: web::Query<PersonalDataLinkParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PersonalDataError> {
+    parameters
+        .verify(PersonalDataRequest::Access, &pool)
+        .await?;
+    Ok(confirmation_page(
+        PersonalDataRequest::Access,
+        &parameters,
+        "Download a copy of the data we hold on your address?",
+        "Download",
+    ))
+}
+
+/// Every row tied to the address, as a JSON file.
+#[tracing::instrument(name = "Download personal data", skip_all)]
+pub async fn download_personal_data(
+    parameters: web::Query<PersonalDataLinkParameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PersonalDataError> {
+    let email = parameters
+        .verify(PersonalDataRequest::Access, &pool)
+        .await?;
+    let personal_data = export_personal_data(&pool, &email).await?;
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    record_personal_data_event(
+        &mut connection,
+        &request,
+        None,
+        AuditAction::SubscriberDataExported,
+        &personal_data.subscriber_ids,
+        Some("At the subscriber's request"),
+    )
+    .await?;
+    Ok(personal_data_response(&personal_data))
+}
+
+#[tracing::instrument(name = "Show the personal data erasure form", skip_all)]
+pub async fn personal_data_erasure_form(
+    parameters: web::Query<PersonalDataLinkParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PersonalDataError> {
+    parameters
+        .verify(PersonalDataRequest::Erasure, &pool)
+        .await?;
+    Ok(confirmation_page(
+        PersonalDataRequest::Erasure,
+        &parameters,
+        "Erase the data we hold on your address? You will be unsubscribed, \
+        and we won't be able to add you back to the list without you signing up again.",
+        "Erase",
+    ))
+}
+
+#[tracing::instrument(name = "Erase personal data on request", skip_all)]
+pub async fn erase_requested_personal_data(
+    parameters: web::Query<PersonalDataLinkParameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PersonalDataError> {
+    let email = parameters
+        .verify(PersonalDataRequest::Erasure, &pool)
+        .await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber_ids = erase_personal_data(&mut transaction, &email).await?;
+    record_personal_data_event(
+        &mut transaction,
+        &request,
+        None,
+        AuditAction::SubscriberErased,
+        &subscriber_ids,
+        Some("At the subscriber's request"),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase personal data.")?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Data erased</title>
+</head>
+<body>
+    <p>The data we held on your address has been erased.</p>
+</body>
+</html>"#,
+    ))
+}
+
+fn confirmation_page(
+    kind: PersonalDataRequest,
+    parameters: &PersonalDataLinkParameters,
+    question: &str,
+    button: &str,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your personal data</title>
+</head>
+<body>
+    <p>{question}</p>
+    <form action="{path}?{query}" method="post">
+        <button type="submit">{button}</button>
+    </form>
+</body>
+</html>"#,
+            path = kind.path(),
+            query = htmlescape::encode_attribute(&parameters.query_string())
+        ))
+}
+
+/// An export from `export_personal_data` as a JSON file download.
+pub(crate) fn personal_data_response(personal_data: &PersonalData) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("personal_data.json".into())],
+        })
+        .body(personal_data.document.to_string())
+}
@@ -0,0 +1,169 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+
+use super::{error_chain_fmt, generate_subscription_token, get_subscriber_by_email, store_token};
+use crate::{
+    confirmation_email_worker::enqueue_confirmation_email,
+    domain::SubscriberEmail,
+    startup::{ConfirmationResendCooldown, SubscriptionTokenTtl},
+    utils::client_ip,
+};
+
+#[derive(Deserialize)]
+pub struct ResendConfirmationFormData {
+    email: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ResendConfirmationError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("A confirmation email was requested too recently, please try again later.")]
+    TooManyRequests,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ResendConfirmationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ResendConfirmationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Resending a confirmation email",
+    skip(form, request, pool, token_ttl, cooldown),
+    fields(subscriber_email = %form.email, client_ip = tracing::field::Empty)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendConfirmationFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    cooldown: web::Data<ConfirmationResendCooldown>,
+) -> Result<HttpResponse, ResendConfirmationError> {
+    let email =
+        SubscriberEmail::parse(form.0.email).map_err(ResendConfirmationError::ValidationError)?;
+    let client_ip = client_ip(&request);
+    tracing::Span::current().record("client_ip", tracing::field::display(&client_ip));
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    // Both cooldowns are claimed before looking the address up, so throttling
+    // behaves the same whether or not the address is on the list. Returning
+    // early drops the transaction, which releases any claim already taken.
+    for (throttle_key, cooldown) in [
+        (format!("ip:{}", client_ip), cooldown.per_ip),
+        (
+            format!("address:{}", email.as_ref().to_lowercase()),
+            cooldown.per_address,
+        ),
+    ] {
+        if !try_claim_resend_slot(&mut transaction, &throttle_key, cooldown)
+            .await
+            .context("Failed to check the confirmation resend cooldown.")?
+        {
+            return Err(ResendConfirmationError::TooManyRequests);
+        }
+    }
+
+    let subscriber = get_subscriber_by_email(&mut transaction, &email)
+        .await
+        .context("Failed to look up the subscriber in the database.")?;
+    // Unknown, confirmed and unsubscribed addresses get the same answer as
+    // pending ones, without an email: the response must not reveal who is on the list.
+    let subscriber_id = match subscriber {
+        Some(subscriber) if subscriber.status == "pending_confirmation" => subscriber.id,
+        _ => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to record a resend request.")?;
+            return Ok(HttpResponse::Ok().finish());
+        }
+    };
+
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        &subscription_token,
+        token_ttl.0,
+    )
+    .await
+    .context("Failed to store the confirmation token for a pending subscriber.")?;
+    enqueue_confirmation_email(&mut transaction, &subscription_token)
+        .await
+        .context("Failed to queue a confirmation email.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new confirmation token.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Record a resend for `throttle_key`, unless the previous one happened less
+/// than `cooldown` ago. Returns whether the resend is allowed.
+///
+/// Subscribing again shares these keys. Personal data requests are throttled
+/// the same way, under their own keys.
+#[tracing::instrument(name = "Claim a confirmation resend slot", skip(transaction))]
+pub(crate) async fn try_claim_resend_slot(
+    transaction: &mut Transaction<'_, Postgres>,
+    throttle_key: &str,
+    cooldown: Duration,
+) -> Result<bool, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(cooldown)?;
+    let n_claimed = sqlx::query!(
+        r#"
+        INSERT INTO confirmation_resend_throttle (throttle_key, last_sent_at)
+        VALUES ($1, now())
+        ON CONFLICT (throttle_key) DO UPDATE
+        SET last_sent_at = EXCLUDED.last_sent_at
+        WHERE confirmation_resend_throttle.last_sent_at <= $2
+        "#,
+        throttle_key,
+        cutoff
+    )
+    .execute(&mut **transaction)
+    .await?
+    .rows_affected();
+    Ok(n_claimed == 1)
+}
+
+/// Forget the resends that happened more than `retention` ago, long after
+/// their cooldown ran out.
+#[tracing::instrument(skip(pool), err)]
+pub async fn purge_resend_throttles(
+    pool: &PgPool,
+    retention: Duration,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(retention)?;
+    let n_deleted = sqlx::query!(
+        r#"DELETE FROM confirmation_resend_throttle WHERE last_sent_at < $1"#,
+        cutoff
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_deleted)
+}
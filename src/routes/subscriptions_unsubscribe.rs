@@ -0,0 +1,167 @@
+use actix_web::http::header::ContentType;
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::error_chain_fmt;
+use crate::startup::HmacSecret;
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    tag: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid.")]
+    InvalidLink(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidLink(_) => StatusCode::BAD_REQUEST,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Build the link a subscriber can follow to leave the newsletter.
+///
+/// The subscriber id is signed with our HMAC secret, so links can't be
+/// forged for other subscribers and nothing needs to be stored per subscriber.
+pub fn unsubscribe_link(base_url: &str, subscriber_id: Uuid, hmac_secret: &HmacSecret) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&tag={}",
+        base_url,
+        subscriber_id,
+        hex::encode(
+            subscriber_mac(subscriber_id, hmac_secret)
+                .finalize()
+                .into_bytes()
+        )
+    )
+}
+
+fn subscriber_mac(subscriber_id: Uuid, hmac_secret: &HmacSecret) -> Hmac<sha2::Sha256> {
+    let mut mac =
+        Hmac::<sha2::Sha256>::new_from_slice(hmac_secret.0.expose_secret().as_bytes()).unwrap();
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+impl UnsubscribeParameters {
+    fn verify(&self, hmac_secret: &HmacSecret) -> Result<Uuid, UnsubscribeError> {
+        let tag = hex::decode(&self.tag)
+            .context("Failed to decode the unsubscribe tag.")
+            .map_err(UnsubscribeError::InvalidLink)?;
+        subscriber_mac(self.subscriber_id, hmac_secret)
+            .verify_slice(&tag)
+            .context("The unsubscribe tag does not match the subscriber id.")
+            .map_err(UnsubscribeError::InvalidLink)?;
+        Ok(self.subscriber_id)
+    }
+
+    fn query_string(&self) -> String {
+        format!("subscriber_id={}&tag={}", self.subscriber_id, self.tag)
+    }
+}
+
+/// Show a confirmation form rather than unsubscribing straight away:
+/// link scanners and mail previews follow `GET` links on their own.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(parameters, hmac_secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    parameters.verify(&hmac_secret)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?{query}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            query = htmlescape::encode_attribute(&parameters.query_string())
+        )))
+}
+
+/// Handles both our own form and RFC 8058 one-click requests, which POST
+/// `List-Unsubscribe=One-Click` to the URL found in `List-Unsubscribe`.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, pool, hmac_secret))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = parameters.verify(&hmac_secret)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    mark_subscriber_as_unsubscribed(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `unsubscribed`.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber.")?;
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed. You will not receive any further issues.</p>
+</body>
+</html>"#,
+    ))
+}
+
+/// Returns `false` if the subscriber was already unsubscribed. Pending
+/// confirmation tokens are dropped, so that an old confirmation link can't
+/// subscribe them again.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(transaction))]
+pub async fn mark_subscriber_as_unsubscribed(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let n_updated = sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1 AND status <> 'unsubscribed'"#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?
+    .rows_affected();
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(n_updated == 1)
+}
@@ -1,6 +1,7 @@
 use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
 use actix_web::dev::Payload;
 use actix_web::{FromRequest, HttpRequest};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use std::future::{ready, Ready};
 use uuid::Uuid;
 
@@ -8,19 +9,94 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_GENERATION_KEY: &'static str = "session_generation";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const AWAITING_TOTP_USER_ID_KEY: &'static str = "awaiting_totp_user_id";
+    const PENDING_TOTP_SECRET_KEY: &'static str = "pending_totp_secret";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
 
     pub fn renew(&self) {
         self.0.renew();
     }
 
-    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
-        self.0.insert(Self::USER_ID_KEY, user_id)
+    /// Log the user in. `session_generation` is the user's current one, the
+    /// session stops working as soon as it is bumped. `session_id` refers to
+    /// the `user_sessions` row tracking this session.
+    pub fn insert_user_id(
+        &self,
+        user_id: Uuid,
+        session_generation: i32,
+        session_id: Uuid,
+    ) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::USER_ID_KEY, user_id)?;
+        self.0
+            .insert(Self::SESSION_GENERATION_KEY, session_generation)?;
+        self.insert_session_id(session_id)
     }
 
     pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    pub fn get_session_generation(&self) -> Result<Option<i32>, SessionGetError> {
+        self.0.get(Self::SESSION_GENERATION_KEY)
+    }
+
+    /// Remember a user whose password checked out but who still has to
+    /// enter a two-factor code. They are not logged in yet.
+    pub fn insert_awaiting_totp_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::AWAITING_TOTP_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_awaiting_totp_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::AWAITING_TOTP_USER_ID_KEY)
+    }
+
+    pub fn remove_awaiting_totp_user_id(&self) {
+        self.0.remove(Self::AWAITING_TOTP_USER_ID_KEY);
+    }
+
+    /// The secret shown during enrollment, kept until the user confirms it
+    /// with a valid code.
+    pub fn insert_pending_totp_secret(&self, secret: &str) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_TOTP_SECRET_KEY, secret)
+    }
+
+    pub fn get_pending_totp_secret(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::PENDING_TOTP_SECRET_KEY)
+    }
+
+    pub fn remove_pending_totp_secret(&self) {
+        self.0.remove(Self::PENDING_TOTP_SECRET_KEY);
+    }
+
+    /// The synchronizer token every form of this session has to send back,
+    /// created the first time a form is rendered.
+    pub fn csrf_token(&self) -> Result<String, SessionInsertError> {
+        if let Some(token) = self.get_csrf_token().ok().flatten() {
+            return Ok(token);
+        }
+        let mut rng = thread_rng();
+        let token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(32)
+            .collect();
+        self.0.insert(Self::CSRF_TOKEN_KEY, &token)?;
+        Ok(token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
     pub fn log_out(self) {
         self.0.purge()
     }
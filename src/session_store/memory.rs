@@ -0,0 +1,120 @@
+use super::{generate_session_key, SessionState};
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Keeps session state in the process memory, shared by all the workers.
+///
+/// Sessions are lost on restart and not shared across instances: meant for
+/// tests and local development.
+#[derive(Clone, Default)]
+pub struct MemorySessionStore {
+    sessions: Arc<Mutex<HashMap<String, (SessionState, Instant)>>>,
+}
+
+fn expires_at(ttl: &Duration) -> Instant {
+    Instant::now() + std::time::Duration::try_from(*ttl).unwrap_or_default()
+}
+
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(session_key.as_ref()) {
+            Some((state, expires_at)) if *expires_at > Instant::now() => Ok(Some(state.clone())),
+            Some(_) => {
+                sessions.remove(session_key.as_ref());
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        sessions.insert(
+            session_key.as_ref().to_owned(),
+            (session_state, expires_at(ttl)),
+        );
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            if let Some(session) = sessions.get_mut(session_key.as_ref()) {
+                if session.1 > Instant::now() {
+                    *session = (session_state, expires_at(ttl));
+                    return Ok(session_key);
+                }
+            }
+        }
+        // Infallible for this store
+        Ok(self.save(session_state, ttl).await.unwrap())
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(session_key.as_ref()) {
+            session.1 = expires_at(ttl);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.sessions.lock().unwrap().remove(session_key.as_ref());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemorySessionStore;
+    use actix_session::storage::SessionStore;
+    use actix_web::cookie::time::Duration;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn saved_state_can_be_loaded_until_it_expires() {
+        let store = MemorySessionStore::default();
+        let state = HashMap::from([("user_id".to_string(), "\"42\"".to_string())]);
+
+        let live = store
+            .save(state.clone(), &Duration::minutes(5))
+            .await
+            .unwrap();
+        let expired = store.save(state.clone(), &Duration::ZERO).await.unwrap();
+
+        assert_eq!(store.load(&live).await.unwrap(), Some(state));
+        assert_eq!(store.load(&expired).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn deleted_sessions_are_gone() {
+        let store = MemorySessionStore::default();
+        let key = store
+            .save(HashMap::new(), &Duration::minutes(5))
+            .await
+            .unwrap();
+
+        store.delete(&key).await.unwrap();
+
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,106 @@
+mod memory;
+mod postgres;
+
+use crate::configuration::{SessionBackend, Settings};
+use actix_session::storage::{
+    LoadError, RedisSessionStore, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::Duration;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+pub use memory::MemorySessionStore;
+pub use postgres::{purge_expired_sessions, PostgresSessionStore};
+
+type SessionState = HashMap<String, String>;
+
+/// The session store picked by `SessionSettings::backend`.
+///
+/// `SessionMiddleware` is generic over its store, this lets us choose one at
+/// runtime without handlers (or `TypedSession`) noticing.
+#[derive(Clone)]
+pub enum AppSessionStore {
+    Redis(RedisSessionStore),
+    Postgres(PostgresSessionStore),
+    Memory(MemorySessionStore),
+}
+
+impl AppSessionStore {
+    pub async fn build(configuration: &Settings, pool: PgPool) -> Result<Self, anyhow::Error> {
+        let store = match configuration.session.backend {
+            SessionBackend::Redis => {
+                Self::Redis(RedisSessionStore::new(configuration.redis_uri.expose_secret()).await?)
+            }
+            SessionBackend::Postgres => Self::Postgres(PostgresSessionStore::new(pool)),
+            SessionBackend::Memory => Self::Memory(MemorySessionStore::default()),
+        };
+        Ok(store)
+    }
+}
+
+impl SessionStore for AppSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            Self::Redis(store) => store.load(session_key).await,
+            Self::Postgres(store) => store.load(session_key).await,
+            Self::Memory(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Redis(store) => store.save(session_state, ttl).await,
+            Self::Postgres(store) => store.save(session_state, ttl).await,
+            Self::Memory(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Redis(store) => store.update(session_key, session_state, ttl).await,
+            Self::Postgres(store) => store.update(session_key, session_state, ttl).await,
+            Self::Memory(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Redis(store) => store.update_ttl(session_key, ttl).await,
+            Self::Postgres(store) => store.update_ttl(session_key, ttl).await,
+            Self::Memory(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Redis(store) => store.delete(session_key).await,
+            Self::Postgres(store) => store.delete(session_key).await,
+            Self::Memory(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+/// 64 alphanumeric characters, the same kind of key `RedisSessionStore` hands out.
+fn generate_session_key() -> SessionKey {
+    use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+
+    let key: String = std::iter::repeat_with(|| char::from(OsRng.sample(Alphanumeric)))
+        .take(64)
+        .collect();
+    key.try_into()
+        .expect("A 64 characters key is always a valid session key")
+}
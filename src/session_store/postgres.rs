@@ -0,0 +1,137 @@
+use super::{generate_session_key, SessionState};
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use sqlx::PgPool;
+
+/// Keeps session state in the `sessions` table.
+///
+/// Expired rows are never loaded, the cleanup worker deletes them.
+#[derive(Clone)]
+pub struct PostgresSessionStore {
+    pool: PgPool,
+}
+
+impl PostgresSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Delete the session state that expired.
+#[tracing::instrument(skip(pool), err)]
+pub async fn purge_expired_sessions(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_deleted = sqlx::query!(r#"DELETE FROM sessions WHERE expires_at < now()"#)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    Ok(n_deleted)
+}
+
+impl SessionStore for PostgresSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            "SELECT state FROM sessions WHERE session_key = $1 AND expires_at > now()",
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load the session state.")
+        .map_err(LoadError::Other)?;
+        row.map(|r| serde_json::from_str(&r.state))
+            .transpose()
+            .context("Failed to deserialize the session state.")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, now() + make_interval(secs => $3))
+            "#,
+            session_key.as_ref(),
+            state,
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save the session state.")
+        .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(UpdateError::Serialization)?;
+        let n_updated = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET state = $2, expires_at = now() + make_interval(secs => $3)
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref(),
+            state,
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session state.")
+        .map_err(UpdateError::Other)?
+        .rows_affected();
+        if n_updated > 0 {
+            return Ok(session_key);
+        }
+        // The session expired in the meantime: start a new one, like
+        // `RedisSessionStore` does.
+        self.save(session_state, ttl).await.map_err(|e| match e {
+            SaveError::Serialization(e) => UpdateError::Serialization(e),
+            SaveError::Other(e) => UpdateError::Other(e),
+        })
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET expires_at = now() + make_interval(secs => $2)
+            WHERE session_key = $1
+            "#,
+            session_key.as_ref(),
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to extend the session.")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "DELETE FROM sessions WHERE session_key = $1",
+            session_key.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete the session.")?;
+        Ok(())
+    }
+}
@@ -1,11 +1,37 @@
-use crate::authentication::reject_anonymous_users;
-use crate::configuration::Settings;
-use crate::email_client::EmailClient;
+use crate::authentication::{
+    reject_anonymous_users, reject_invalid_api_tokens, reject_invalid_csrf_tokens, require_editor,
+    require_owner, FallbackPasswordHash,
+};
+use crate::configuration::{
+    ApplicationSettings, DatabaseSettings, LoginThrottlingSettings, PasswordHashingSettings,
+    PasswordPolicySettings, SessionSettings, Settings,
+};
+use crate::confirmation_email_worker::run_confirmation_worker_until_stopped;
+use crate::email_client::EmailSender;
+use crate::issue_delivery_worker::run_worker_until_stopped;
+use crate::password_reset_email_worker::run_password_reset_worker_until_stopped;
+use crate::personal_data_email_worker::run_personal_data_worker_until_stopped;
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, publish_newsletter, publish_newsletter_form, subscribe,
+    admin_confirm_subscriber, admin_dashboard, admin_download_personal_data,
+    admin_erase_personal_data, admin_unsubscribe_subscriber, api_json_config,
+    api_publish_newsletter, change_email, change_email_form, change_password, change_password_form,
+    change_user_role, change_user_status, clear_lockout, confirm, confirm_two_factor,
+    create_api_token, dead_letters, delete_subscriber, delete_user, disable_two_factor,
+    download_import_report, download_personal_data, edit_subscriber, erase_requested_personal_data,
+    export_audit_events, export_subscribers, health_check, home, import_subscribers,
+    import_subscribers_form, invite_user, list_api_tokens, list_audit_events, list_lockouts,
+    list_sessions, list_subscribers, list_users, log_out, login, login_form, login_two_factor,
+    login_two_factor_form, password_reset_form, password_reset_request_form,
+    personal_data_access_form, personal_data_erasure_form, publish_newsletter,
+    publish_newsletter_form, request_password_reset, request_personal_data, requeue_dead_letters,
+    resend_confirmation, reset_password, revoke_api_token, revoke_other_sessions, revoke_session,
+    subscribe, subscriber_details, two_factor_form, unsubscribe, unsubscribe_form,
+    update_welcome_email, welcome_email_form,
 };
-use actix_session::storage::RedisSessionStore;
+use crate::session_store::AppSessionStore;
+use crate::subscription_cleanup_worker::run_cleanup_until_stopped;
+use crate::welcome_email_worker::run_welcome_worker_until_stopped;
+use actix_session::config::{BrowserSession, TtlExtensionPolicy};
 use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
 use actix_web::{dev::Server, web, App, HttpServer};
@@ -16,36 +42,34 @@ use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{PgPool, Pool, Postgres};
 use std::net::TcpListener;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    connection_pool: PgPool,
+    configuration: Settings,
 }
 
+/// A background worker started by the application, by name.
+pub type Worker = (&'static str, JoinHandle<Result<(), anyhow::Error>>);
+
 impl Application {
     pub async fn build(
         configuration: &Settings,
         connection_pool: Option<Pool<Postgres>>,
     ) -> Result<Self, anyhow::Error> {
-        let connection_pool = connection_pool.unwrap_or_else(|| {
-            PgPoolOptions::new()
-                .acquire_timeout(std::time::Duration::from_secs(2))
-                .connect_lazy_with(configuration.database.with_db())
-        });
-
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url.clone(),
-            sender_email,
-            configuration.email_client.authorization_token.clone(),
-            timeout,
-        );
+        let connection_pool =
+            connection_pool.unwrap_or_else(|| get_connection_pool(&configuration.database));
+        let email_client = configuration.email_client.clone().client();
+        // Fail at startup rather than on the first login
+        configuration
+            .password_hashing
+            .params()
+            .map_err(|e| anyhow::anyhow!("Invalid password hashing parameters: {}", e))?;
+
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
@@ -53,22 +77,73 @@ impl Application {
         let listener = TcpListener::bind(address)?;
 
         let port = listener.local_addr().unwrap().port();
+        let session_store = AppSessionStore::build(configuration, connection_pool.clone()).await?;
         let server = run(
             listener,
-            connection_pool,
+            connection_pool.clone(),
             email_client,
-            configuration.application.base_url.clone(),
-            HmacSecret(configuration.application.hmac_secret.clone()),
-            configuration.redis_uri.clone(),
-        )
-        .await?;
-        Ok(Self { port, server })
+            &configuration.application,
+            configuration.login_throttling.clone(),
+            configuration.password_hashing.clone(),
+            configuration.password_policy.clone(),
+            session_store,
+            configuration.session.clone(),
+        )?;
+        Ok(Self {
+            port,
+            server,
+            connection_pool,
+            configuration: configuration.clone(),
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// Start the background workers, sharing the connection pool of the
+    /// HTTP server. They only return if they fail: it is up to the caller to
+    /// watch their handles.
+    pub fn spawn_workers(&self) -> Vec<Worker> {
+        let pool = || self.connection_pool.clone();
+        let configuration = || self.configuration.clone();
+        vec![
+            (
+                "Background worker",
+                tokio::spawn(run_worker_until_stopped(pool(), configuration())),
+            ),
+            (
+                "Welcome email worker",
+                tokio::spawn(run_welcome_worker_until_stopped(pool(), configuration())),
+            ),
+            (
+                "Confirmation email worker",
+                tokio::spawn(run_confirmation_worker_until_stopped(
+                    pool(),
+                    configuration(),
+                )),
+            ),
+            (
+                "Personal data email worker",
+                tokio::spawn(run_personal_data_worker_until_stopped(
+                    pool(),
+                    configuration(),
+                )),
+            ),
+            (
+                "Password reset email worker",
+                tokio::spawn(run_password_reset_worker_until_stopped(
+                    pool(),
+                    configuration(),
+                )),
+            ),
+            (
+                "Subscription cleanup",
+                tokio::spawn(run_cleanup_until_stopped(pool(), configuration())),
+            ),
+        ]
+    }
+
     // A more expressive name that makes it clear that
     // this function only returns when the application is stopped.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
@@ -76,58 +151,294 @@ impl Application {
     }
 }
 
+pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+    PgPoolOptions::new()
+        .acquire_timeout(std::time::Duration::from_secs(2))
+        .connect_lazy_with(configuration.with_db())
+}
+
 // We need to define a wrapper type in order to retrieve the URL
 // in the `subscribe` handler.
 // Retrieval from the context, in actix-web, is type-based: using
 // a raw `String` would expose us to conflicts.
 pub struct ApplicationBaseUrl(pub String);
 
-async fn run(
+/// How long a subscription confirmation token stays valid after being issued.
+#[derive(Clone, Copy)]
+pub struct SubscriptionTokenTtl(pub std::time::Duration);
+
+/// How long a subscriber has to wait before asking for another confirmation
+/// email, tracked separately for the address and for the requesting IP.
+#[derive(Clone, Copy)]
+pub struct ConfirmationResendCooldown {
+    pub per_address: std::time::Duration,
+    pub per_ip: std::time::Duration,
+}
+
+/// The reverse proxies allowed to tell us the client address.
+#[derive(Clone, Default)]
+pub struct TrustedProxies(pub Vec<std::net::IpAddr>);
+
+#[allow(clippy::too_many_arguments)]
+fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
-    base_url: String,
-    hmac_secret: HmacSecret,
-    redis_uri: Secret<String>,
+    email_client: Arc<dyn EmailSender>,
+    application: &ApplicationSettings,
+    login_throttling: LoginThrottlingSettings,
+    password_hashing: PasswordHashingSettings,
+    password_policy: PasswordPolicySettings,
+    session_store: AppSessionStore,
+    session_settings: SessionSettings,
 ) -> Result<Server, anyhow::Error> {
+    let fallback_password_hash = FallbackPasswordHash::new(&password_hashing)?;
     let db_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_client: web::Data<dyn EmailSender> = web::Data::from(email_client);
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url.clone()));
+    let hmac_secret = HmacSecret(application.hmac_secret.clone());
+    let subscription_token_ttl = SubscriptionTokenTtl(application.subscription_token_ttl());
+    let resend_cooldown = ConfirmationResendCooldown {
+        per_address: application.resend_confirmation_address_cooldown(),
+        per_ip: application.resend_confirmation_ip_cooldown(),
+    };
+    let trusted_proxies = TrustedProxies(application.trusted_proxies.clone());
 
     let secret_key = Key::from(hmac_secret.0.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
-    let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
 
     let server = HttpServer::new(move || {
         App::new()
             .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                    .session_lifecycle(
+                        BrowserSession::default()
+                            .state_ttl(session_settings.ttl())
+                            // Idle sessions expire, active ones are kept alive
+                            .state_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+                    )
+                    .cookie_name(session_settings.cookie_name.clone())
+                    .cookie_same_site(session_settings.cookie_same_site.into())
+                    .cookie_secure(session_settings.cookie_secure)
+                    .cookie_domain(session_settings.cookie_domain.clone())
+                    .build(),
+            )
             .wrap(TracingLogger::default())
             .route("/", web::get().to(home))
             .service(
                 web::scope("/admin")
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
                     .wrap(from_fn(reject_anonymous_users))
                     .route("/dashboard", web::get().to(admin_dashboard))
-                    .route("/newsletters", web::get().to(publish_newsletter_form))
-                    .route("/newsletters", web::post().to(publish_newsletter))
+                    .service(
+                        web::resource("/newsletters")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(publish_newsletter_form))
+                            .route(web::post().to(publish_newsletter)),
+                    )
+                    .service(
+                        web::resource("/dead_letters")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(dead_letters))
+                            .route(web::post().to(requeue_dead_letters)),
+                    )
+                    .service(
+                        web::resource("/welcome_email")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(welcome_email_form))
+                            .route(web::post().to(update_welcome_email)),
+                    )
+                    .service(
+                        web::resource("/api_tokens")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(list_api_tokens))
+                            .route(web::post().to(create_api_token)),
+                    )
+                    .service(
+                        web::resource("/api_tokens/{api_token_id}/revoke")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(revoke_api_token)),
+                    )
+                    .service(
+                        web::resource("/audit")
+                            .wrap(from_fn(require_owner))
+                            .route(web::get().to(list_audit_events)),
+                    )
+                    .service(
+                        web::resource("/audit/export")
+                            .wrap(from_fn(require_owner))
+                            .route(web::get().to(export_audit_events)),
+                    )
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    // Registered before `/subscribers/{subscriber_id}`, which would match too
+                    .service(
+                        web::resource("/subscribers/import")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(import_subscribers_form))
+                            .route(web::post().to(import_subscribers)),
+                    )
+                    .service(
+                        web::resource("/subscribers/import/{import_id}/report")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(download_import_report)),
+                    )
+                    .service(
+                        web::resource("/subscribers/export")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(export_subscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(subscriber_details),
+                    )
+                    .service(
+                        web::resource("/subscribers/{subscriber_id}/confirm")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(admin_confirm_subscriber)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{subscriber_id}/unsubscribe")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(admin_unsubscribe_subscriber)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{subscriber_id}/edit")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(edit_subscriber)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{subscriber_id}/delete")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(delete_subscriber)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{subscriber_id}/personal-data")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(admin_download_personal_data)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{subscriber_id}/erase")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(admin_erase_personal_data)),
+                    )
+                    .service(
+                        web::resource("/users")
+                            .wrap(from_fn(require_owner))
+                            .route(web::get().to(list_users))
+                            .route(web::post().to(invite_user)),
+                    )
+                    .service(
+                        web::resource("/users/{user_id}/role")
+                            .wrap(from_fn(require_owner))
+                            .route(web::post().to(change_user_role)),
+                    )
+                    .service(
+                        web::resource("/users/{user_id}/status")
+                            .wrap(from_fn(require_owner))
+                            .route(web::post().to(change_user_status)),
+                    )
+                    .service(
+                        web::resource("/users/{user_id}/delete")
+                            .wrap(from_fn(require_owner))
+                            .route(web::post().to(delete_user)),
+                    )
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
+                    .service(
+                        web::resource("/lockouts")
+                            .wrap(from_fn(require_owner))
+                            .route(web::get().to(list_lockouts))
+                            .route(web::post().to(clear_lockout)),
+                    )
+                    .route("/email", web::get().to(change_email_form))
+                    .route("/email", web::post().to(change_email))
+                    .route("/sessions", web::get().to(list_sessions))
+                    .route(
+                        "/sessions/revoke_others",
+                        web::post().to(revoke_other_sessions),
+                    )
+                    .route(
+                        "/sessions/{session_id}/revoke",
+                        web::post().to(revoke_session),
+                    )
+                    .route("/2fa", web::get().to(two_factor_form))
+                    .route("/2fa", web::post().to(confirm_two_factor))
+                    .route("/2fa/disable", web::post().to(disable_two_factor))
                     .route("/logout", web::post().to(log_out)),
             )
-            .route("/login", web::get().to(login_form))
-            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/api/v1")
+                    .wrap(from_fn(reject_invalid_api_tokens))
+                    .app_data(api_json_config())
+                    .route("/newsletters", web::post().to(api_publish_newsletter)),
+            )
+            .service(
+                web::resource("/login")
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
+                    .route(web::get().to(login_form))
+                    .route(web::post().to(login)),
+            )
+            .service(
+                web::resource("/login/2fa")
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
+                    .route(web::get().to(login_two_factor_form))
+                    .route(web::post().to(login_two_factor)),
+            )
+            .route(
+                "/password_reset",
+                web::get().to(password_reset_request_form),
+            )
+            .route("/password_reset", web::post().to(request_password_reset))
+            .route(
+                "/password_reset/confirm",
+                web::get().to(password_reset_form),
+            )
+            .route("/password_reset/confirm", web::post().to(reset_password))
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/resend-confirmation",
+                web::post().to(resend_confirmation),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/personal-data/request",
+                web::post().to(request_personal_data),
+            )
+            .route(
+                "/subscriptions/personal-data",
+                web::get().to(personal_data_access_form),
+            )
+            .route(
+                "/subscriptions/personal-data",
+                web::post().to(download_personal_data),
+            )
+            .route(
+                "/subscriptions/personal-data/erase",
+                web::get().to(personal_data_erasure_form),
+            )
+            .route(
+                "/subscriptions/personal-data/erase",
+                web::post().to(erase_requested_personal_data),
+            )
             // .route("/newsletters", web::post().to(publish_newsletter))
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
             .app_data(web::Data::new(hmac_secret.clone()))
+            .app_data(web::Data::new(subscription_token_ttl))
+            .app_data(web::Data::new(resend_cooldown))
+            .app_data(web::Data::new(trusted_proxies.clone()))
+            .app_data(web::Data::new(login_throttling.clone()))
+            .app_data(web::Data::new(password_hashing.clone()))
+            .app_data(web::Data::new(fallback_password_hash.clone()))
+            .app_data(web::Data::new(password_policy.clone()))
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,84 @@
+use crate::{
+    authentication::{purge_login_failures, purge_password_reset_tokens, purge_user_sessions},
+    configuration::Settings,
+    personal_data::purge_personal_data_links,
+    routes::purge_resend_throttles,
+    session_store::purge_expired_sessions,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+use std::time::Duration;
+
+pub async fn run_cleanup_until_stopped(
+    connection_pool: PgPool,
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let retention = configuration.application.unconfirmed_subscriber_retention();
+    let session_ttl = configuration.session.ttl().unsigned_abs();
+    cleanup_loop(connection_pool, retention, session_ttl).await
+}
+
+async fn cleanup_loop(
+    pool: PgPool,
+    retention: Duration,
+    session_ttl: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        // Each purge logs its own failures and does not hold back the others,
+        // we'll try again next round.
+        let _ = purge_stale_subscriptions(&pool, retention).await;
+        let _ = purge_resend_throttles(&pool, retention).await;
+        let _ = purge_password_reset_tokens(&pool, retention).await;
+        let _ = purge_login_failures(&pool, retention).await;
+        let _ = purge_user_sessions(&pool, session_ttl).await;
+        let _ = purge_personal_data_links(&pool).await;
+        let _ = purge_expired_sessions(&pool).await;
+        tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+    }
+}
+
+pub struct PurgeOutcome {
+    pub n_tokens: u64,
+    pub n_subscribers: u64,
+}
+
+/// Delete confirmation tokens that expired more than `retention` ago, then
+/// the pending subscribers who were left without any token to confirm with.
+#[tracing::instrument(skip(pool), fields(n_tokens, n_subscribers), err)]
+pub async fn purge_stale_subscriptions(
+    pool: &PgPool,
+    retention: Duration,
+) -> Result<PurgeOutcome, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(retention)?;
+    let mut transaction = pool.begin().await?;
+    let n_tokens = sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE expires_at < $1"#,
+        cutoff
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    let n_subscribers = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions s
+        WHERE
+            s.status = 'pending_confirmation' AND
+            s.subscribed_at < $1 AND
+            NOT EXISTS (
+                SELECT 1 FROM subscription_tokens t WHERE t.subscriber_id = s.id
+            )
+        "#,
+        cutoff
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    transaction.commit().await?;
+    tracing::Span::current()
+        .record("n_tokens", n_tokens)
+        .record("n_subscribers", n_subscribers);
+    Ok(PurgeOutcome {
+        n_tokens,
+        n_subscribers,
+    })
+}
@@ -1,5 +1,10 @@
 use actix_web::http::header::LOCATION;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::startup::TrustedProxies;
 
 // Return an opaque 500 while preserving the error root's cause for logging.
 pub fn e500<T>(e: T) -> actix_web::Error
@@ -9,8 +14,123 @@ where
     actix_web::error::ErrorInternalServerError(e)
 }
 
+// Return a 400 with the user-representation of the validation error as body.
+// The error root cause is preserved for logging purposes.
+pub fn e400<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorBadRequest(e)
+}
+
 pub fn see_other(location: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .insert_header((LOCATION, location))
         .finish()
 }
+
+/// The address of the client, without the port.
+///
+/// `X-Forwarded-For` is only looked at when the connection comes from one of
+/// the configured trusted proxies: anybody else could put anything in it.
+pub fn client_ip(request: &HttpRequest) -> String {
+    let Some(peer) = request.peer_addr() else {
+        return "unknown".into();
+    };
+    let trusted_proxies = request
+        .app_data::<web::Data<TrustedProxies>>()
+        .map(|t| t.0.as_slice())
+        .unwrap_or_default();
+    let forwarded_for: Vec<&str> = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    resolve_client_ip(peer.ip(), &forwarded_for, trusted_proxies).to_string()
+}
+
+/// Walk `X-Forwarded-For` back from the peer, through trusted proxies only:
+/// the first hop that isn't one of them is the client.
+fn resolve_client_ip(peer: IpAddr, forwarded_for: &[&str], trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    for hop in forwarded_for.iter().rev() {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        let hop = hop.trim();
+        let Some(hop) = hop
+            .parse::<IpAddr>()
+            .ok()
+            .or_else(|| hop.parse::<SocketAddr>().ok().map(|a| a.ip()))
+        else {
+            break;
+        };
+        client = hop;
+    }
+    client
+}
+
+/// A CSV cell that spreadsheets won't evaluate: cells starting with `=`, `+`,
+/// `-` or `@` are read as formulas, so they get prefixed with a `'`.
+pub fn csv_cell(value: &str) -> Cow<'_, str> {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Midnight UTC on `date`, given as YYYY-MM-DD.
+pub fn start_of_day(date: &str) -> Result<DateTime<Utc>, String> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+        .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| format!("{} is not a valid date, please use YYYY-MM-DD.", date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_cell, resolve_client_ip};
+    use std::net::IpAddr;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_without_trusted_proxies() {
+        let client = resolve_client_ip(ip("203.0.113.7"), &["198.51.100.1"], &[]);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_for_is_believed_from_trusted_proxies_only() {
+        let proxy = ip("10.0.0.1");
+        // Whatever the client put in front of the header is skipped
+        let client = resolve_client_ip(proxy, &["1.2.3.4", " 203.0.113.7"], &[proxy]);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn chains_of_trusted_proxies_are_followed() {
+        let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+        let client = resolve_client_ip(proxies[0], &["203.0.113.7", "10.0.0.2"], &proxies);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn garbage_stops_at_the_last_trusted_hop() {
+        let proxy = ip("10.0.0.1");
+        let client = resolve_client_ip(proxy, &["not-an-address"], &[proxy]);
+        assert_eq!(client, proxy);
+    }
+
+    #[test]
+    fn csv_cells_that_look_like_formulas_are_neutralised() {
+        for formula in ["=HYPERLINK(\"http://evil\")", "+1", "-1+1", "@SUM(A1)"] {
+            assert_eq!(csv_cell(formula), format!("'{}", formula));
+        }
+        assert_eq!(csv_cell("Invited jane as viewer"), "Invited jane as viewer");
+        assert_eq!(csv_cell(""), "");
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    configuration::{IssueDeliverySettings, Settings},
+    domain::SubscriberEmail,
+    email_client::EmailSender,
+    issue_delivery_worker::{backoff, send_with_unsubscribe_link, ExecutionOutcome},
+    routes::unsubscribe_link,
+    startup::HmacSecret,
+};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+pub async fn run_welcome_worker_until_stopped(
+    connection_pool: PgPool,
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let email_client = configuration.email_client.client();
+    let hmac_secret = HmacSecret(configuration.application.hmac_secret);
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.issue_delivery,
+        configuration.application.base_url,
+        hmac_secret,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    settings: IssueDeliverySettings,
+    base_url: String,
+    hmac_secret: HmacSecret,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_welcome_task(
+            &pool,
+            email_client.as_ref(),
+            &settings,
+            &base_url,
+            &hmac_secret,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Send the welcome email to one newly confirmed subscriber, if any is queued.
+///
+/// Welcome emails are retried with the same policy as newsletter issues,
+/// but they are dropped rather than dead-lettered once attempts run out.
+#[tracing::instrument(skip_all, fields(subscriber_id = tracing::field::Empty), err)]
+pub async fn try_execute_welcome_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    settings: &IssueDeliverySettings,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("subscriber_id", display(task.subscriber_id));
+    if task.status != "confirmed" {
+        tracing::info!("Skipping a welcome email. The subscriber is no longer confirmed.");
+        delete_task(transaction, &task).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    let email = match SubscriberEmail::parse(task.email.clone()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Dropping a welcome email to a confirmed subscriber. \
+                 Their stored contact details are invalid",
+            );
+            delete_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let template = get_welcome_email(pool).await?;
+    if let Err(e) = send_with_unsubscribe_link(
+        email_client,
+        &email,
+        &template.subject,
+        &template.html_content,
+        &template.text_content,
+        &unsubscribe_link(base_url, task.subscriber_id, hmac_secret),
+    )
+    .await
+    {
+        if task.n_retries + 1 >= settings.max_attempts {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send the welcome email to a confirmed subscriber. \
+                 No attempts left, dropping it.",
+            );
+            delete_task(transaction, &task).await?;
+        } else {
+            let backoff = backoff(settings, task.n_retries);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send the welcome email to a confirmed subscriber. \
+                 Retrying in {} seconds.",
+                backoff.as_secs(),
+            );
+            retry_task_later(transaction, &task, &format!("{:?}", e), backoff).await?;
+        }
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    delete_task(transaction, &task).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    subscriber_id: Uuid,
+    email: String,
+    status: String,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT q.subscriber_id, s.email, s.status, q.n_retries
+        FROM welcome_email_queue q
+        JOIN subscriptions s ON s.id = q.subscriber_id
+        WHERE q.execute_after <= now()
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    Ok(r.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(mut transaction: PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM welcome_email_queue WHERE subscriber_id = $1"#,
+        task.subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_task_later(
+    mut transaction: PgTransaction,
+    task: &Task,
+    error: &str,
+    backoff: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = chrono::Utc::now() + chrono::Duration::from_std(backoff)?;
+    sqlx::query!(
+        r#"
+        UPDATE welcome_email_queue
+        SET
+            n_retries = n_retries + 1,
+            last_error = $2,
+            execute_after = $3
+        WHERE subscriber_id = $1
+        "#,
+        task.subscriber_id,
+        error,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub struct WelcomeEmail {
+    pub enabled: bool,
+    pub subject: String,
+    pub text_content: String,
+    pub html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_welcome_email(pool: &PgPool) -> Result<WelcomeEmail, anyhow::Error> {
+    let template = sqlx::query_as!(
+        WelcomeEmail,
+        r#"SELECT enabled, subject, text_content, html_content FROM welcome_email"#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(template)
+}
+
+/// Queue the welcome email for a subscriber who just confirmed, unless
+/// admins have turned it off.
+#[tracing::instrument(skip(transaction))]
+pub async fn enqueue_welcome_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO welcome_email_queue (subscriber_id)
+        SELECT $1 FROM welcome_email WHERE enabled
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,286 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestUser};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Release notes",
+        "text_content": "Release notes as plain text",
+        "html_content": "<p>Release notes as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    })
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_manage_api_tokens(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app.get_api_tokens().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn viewers_cannot_manage_api_tokens(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    // Act
+    let response = app
+        .post_create_api_token("name=ci&scope=newsletters%3Apublish")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn created_tokens_are_shown_once_and_stored_hashed(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let token = app.create_api_token().await;
+
+    // Assert
+    assert!(token.starts_with("z2p_"));
+    let row = sqlx::query!("SELECT name, token_hash, scopes FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(row.name, "ci");
+    assert_ne!(row.token_hash, token);
+    assert_eq!(row.scopes, vec!["newsletters:publish".to_string()]);
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<td>ci</td>"));
+    assert!(!html_page.contains(&token));
+}
+
+#[sqlx::test]
+async fn tokens_need_a_name_and_a_valid_scope(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        ("name=ci", "Please pick at least one scope."),
+        (
+            "name=&scope=newsletters%3Apublish",
+            "The token name can&#x27;t be empty.",
+        ),
+        (
+            "name=ci&scope=newsletters%3Adelete",
+            "newsletters:delete is not a valid scope.",
+        ),
+    ];
+
+    for (body, message) in test_cases {
+        // Act
+        let response = app.post_create_api_token(body).await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/api_tokens");
+        let html_page = app.get_api_tokens_html().await;
+        assert!(html_page.contains(message), "{}", html_page);
+    }
+    let n_tokens = sqlx::query!(r#"SELECT count(*) as "count!" FROM api_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+}
+
+#[sqlx::test]
+async fn requests_without_a_valid_bearer_token_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    for token in [None, Some("z2p_not-a-real-token")] {
+        // Act
+        let response = app
+            .post_api_newsletters(token, &newsletter_request_body())
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert!(response.headers().contains_key("WWW-Authenticate"));
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "unauthorized");
+    }
+}
+
+#[sqlx::test]
+async fn revoked_tokens_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token().await;
+    let api_token_id = sqlx::query!("SELECT api_token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .api_token_id;
+
+    // Act
+    let response = app.post_revoke_api_token(api_token_id).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>The API token has been revoked.</i></p>"));
+    let response = app
+        .post_api_newsletters(Some(&token), &newsletter_request_body())
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn you_cannot_revoke_someone_elses_token(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token().await;
+    let api_token_id = sqlx::query!("SELECT api_token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .api_token_id;
+    app.post_logout().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    // Act
+    app.post_revoke_api_token(api_token_id).await;
+
+    // Assert
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>There is no such active API token.</i></p>"));
+    let response = app
+        .post_api_newsletters(Some(&token), &newsletter_request_body())
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[sqlx::test]
+async fn tokens_stop_publishing_when_their_owner_becomes_a_viewer(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token().await;
+    sqlx::query!(
+        "UPDATE users SET role = 'viewer' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_api_newsletters(Some(&token), &newsletter_request_body())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "forbidden");
+}
+
+#[sqlx::test]
+async fn malformed_bodies_are_rejected_with_a_json_error(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token().await;
+
+    // Act
+    let response = app
+        .post_api_newsletters(
+            Some(&token),
+            &serde_json::json!({ "title": "Release notes" }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "invalid_request");
+}
+
+#[sqlx::test]
+async fn newsletters_published_with_a_token_are_delivered(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_api_newsletters(Some(&token), &newsletter_request_body())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "accepted");
+    let issue = sqlx::query!("SELECT newsletter_issue_id, api_token_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        body["newsletter_issue_id"],
+        issue.newsletter_issue_id.to_string()
+    );
+    let api_token_id = sqlx::query!("SELECT api_token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .api_token_id;
+    assert_eq!(issue.api_token_id, Some(api_token_id));
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email
+}
+
+#[sqlx::test]
+async fn api_publishing_is_idempotent(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = newsletter_request_body();
+
+    // Act
+    let first = app.post_api_newsletters(Some(&token), &body).await;
+    let second = app.post_api_newsletters(Some(&token), &body).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 202);
+    assert_eq!(second.status().as_u16(), 202);
+    assert_eq!(first.text().await.unwrap(), second.text().await.unwrap());
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email once
+}
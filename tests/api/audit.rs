@@ -0,0 +1,298 @@
+use crate::helpers::{publish_newsletter, spawn_app, TestApp, TestUser};
+use sqlx::{Pool, Postgres};
+
+struct Event {
+    actor_username: Option<String>,
+    action: String,
+    target: Option<String>,
+    ip_address: Option<String>,
+}
+
+async fn events(app: &TestApp) -> Vec<Event> {
+    sqlx::query_as!(
+        Event,
+        "SELECT actor_username, action, target, ip_address FROM audit_events ORDER BY audit_event_id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn insert_events(app: &TestApp, n: i32, action: &str) {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events (occurred_at, action, details)
+        SELECT now(), $2, 'Event ' || i FROM generate_series(1, $1) AS i
+        "#,
+        n,
+        action
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[sqlx::test]
+async fn only_owners_can_browse_the_audit_log(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    // Act
+    let page = app.get_audit("").await;
+    let export = app.get_audit_export("").await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 403);
+    assert_eq!(export.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn logins_and_logouts_are_recorded(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    app.test_user.login(&app).await;
+    app.post_logout().await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password"
+    }))
+    .await;
+
+    // Assert
+    let events = events(&app).await;
+    let actions: Vec<_> = events.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, ["login_succeeded", "logged_out", "login_failed"]);
+    assert_eq!(
+        events[0].actor_username.as_deref(),
+        Some(app.test_user.username.as_str())
+    );
+    assert_eq!(events[2].actor_username, None);
+    assert_eq!(
+        events[2].target.as_deref(),
+        Some(app.test_user.username.as_str())
+    );
+    assert!(events
+        .iter()
+        .all(|e| e.ip_address.as_deref() == Some("127.0.0.1")));
+}
+
+#[sqlx::test]
+async fn publishing_an_issue_is_recorded(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    publish_newsletter(&app).await;
+
+    // Assert
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let event = events(&app).await.pop().unwrap();
+    assert_eq!(event.action, "issue_published");
+    assert_eq!(event.target, Some(issue_id.to_string()));
+}
+
+#[sqlx::test]
+async fn password_changes_and_user_management_are_recorded(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let new_password = "quietly folded paper lanterns";
+
+    // Act
+    app.post_change_password(&serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": new_password,
+        "new_password_check": new_password,
+    }))
+    .await;
+    app.post_invite_user(&serde_json::json!({
+        "username": "invitee",
+        "role": "viewer",
+        "password": new_password,
+        "password_check": new_password,
+    }))
+    .await;
+    let invitee_id = sqlx::query!("SELECT user_id FROM users WHERE username = 'invitee'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .user_id;
+    app.post_user_action(invitee_id, "role", &serde_json::json!({"role": "editor"}))
+        .await;
+    app.post_user_action(invitee_id, "delete", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    let events = events(&app).await;
+    let actions: Vec<_> = events.iter().skip(1).map(|e| e.action.as_str()).collect();
+    assert_eq!(
+        actions,
+        [
+            "password_changed",
+            "user_invited",
+            "user_role_changed",
+            "user_deleted"
+        ]
+    );
+    assert!(events[2..]
+        .iter()
+        .all(|e| e.target == Some(invitee_id.to_string())));
+}
+
+#[sqlx::test]
+async fn events_can_be_filtered(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.post_login(&serde_json::json!({
+        "username": "somebody",
+        "password": "wrong-password"
+    }))
+    .await;
+    // The failure above slows down further logins from this address.
+    sqlx::query!("DELETE FROM login_failures")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let failed_logins = app.get_audit_html("action=login_failed").await;
+    let by_test_user = app
+        .get_audit_html(&format!("actor={}", app.test_user.username))
+        .await;
+    let tomorrow = (chrono::Utc::now() + chrono::Duration::days(1)).format("%Y-%m-%d");
+    let from_tomorrow = app.get_audit_html(&format!("since={}", tomorrow)).await;
+
+    // Assert
+    assert!(failed_logins.contains("<td>somebody</td>"));
+    assert!(!failed_logins.contains("<td>login_succeeded</td>"));
+    assert!(by_test_user.contains("<td>login_succeeded</td>"));
+    assert!(!by_test_user.contains("<td>login_failed</td>"));
+    assert!(!from_tomorrow.contains("<td>login_succeeded</td>"));
+}
+
+#[sqlx::test]
+async fn invalid_filters_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    for query in ["action=not_an_action", "since=yesterday"] {
+        // Act
+        let response = app.get_audit(query).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[sqlx::test]
+async fn the_audit_log_is_paginated(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    insert_events(&app, 60, "user_invited").await;
+
+    // Act - Part 1 - The first page
+    let html_page = app.get_audit_html("action=user_invited").await;
+    assert_eq!(html_page.matches("<td>user_invited</td>").count(), 50);
+    assert!(html_page.contains("<td>Event 60</td>"));
+    let start = html_page.find("before=").unwrap() + 7;
+    let before: String = html_page[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+
+    // Act - Part 2 - The next one
+    let html_page = app
+        .get_audit_html(&format!("action=user_invited&before={}", before))
+        .await;
+    assert_eq!(html_page.matches("<td>user_invited</td>").count(), 10);
+    assert!(html_page.contains("<td>Event 1</td>"));
+    assert!(!html_page.contains("Older events"));
+}
+
+#[sqlx::test]
+async fn the_audit_log_can_be_exported_as_csv(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        "INSERT INTO audit_events (occurred_at, action, details) VALUES (now(), 'user_invited', 'Invited \"a, b\" as viewer')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.get_audit_export("action=user_invited").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    let mut lines = body.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        "occurred_at,actor_user_id,actor_username,action,target,ip_address,details"
+    );
+    assert!(lines
+        .next()
+        .unwrap()
+        .ends_with(r#",,,user_invited,,,"Invited ""a, b"" as viewer""#));
+    assert!(lines.next().is_none());
+}
+
+#[sqlx::test]
+async fn exported_cells_are_not_evaluated_as_formulas(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        "INSERT INTO audit_events (occurred_at, action, target, details) VALUES (now(), 'user_invited', '@SUM(A1)', '=HYPERLINK(\"http://evil.example\")')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.get_audit_export("action=user_invited").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    let line = body.lines().nth(1).unwrap();
+    assert!(line.ends_with(r#",,,user_invited,'@SUM(A1),,"'=HYPERLINK(""http://evil.example"")""#));
+}
+
+#[sqlx::test]
+async fn audit_events_cannot_be_altered(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let update = sqlx::query!("UPDATE audit_events SET action = 'logged_out'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query!("DELETE FROM audit_events")
+        .execute(&app.db_pool)
+        .await;
+
+    // Assert
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
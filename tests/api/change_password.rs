@@ -1,4 +1,4 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
 use sqlx::{Pool, Postgres};
 use uuid::Uuid;
 
@@ -135,3 +135,121 @@ async fn changing_password_works(pool: Pool<Postgres>) {
     let response = app.post_login(&login_body).await;
     assert_is_redirect_to(&response, "/admin/dashboard");
 }
+
+#[sqlx::test]
+async fn the_password_is_kept_if_the_change_cannot_be_audited(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+    let old_hash = password_hash(&app).await;
+    // Sabotage the database
+    sqlx::query!("ALTER TABLE audit_events DROP COLUMN details;")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    assert_eq!(password_hash(&app).await, old_hash);
+}
+
+async fn password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
+
+async fn post_new_password(app: &TestApp, new_password: &str) -> String {
+    app.test_user.login(app).await;
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": new_password,
+            "new_password_check": new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    app.get_change_password_html().await
+}
+
+#[sqlx::test]
+async fn new_password_must_be_long_enough(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let html_page = post_new_password(&app, "a").await;
+
+    // Assert
+    assert!(
+        html_page.contains("<p><i>The new password must be at least 12 characters long.</i></p>")
+    );
+}
+
+#[sqlx::test]
+async fn new_password_must_not_be_too_long(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let html_page = post_new_password(&app, &"a".repeat(129)).await;
+
+    // Assert
+    assert!(
+        html_page.contains("<p><i>The new password must be at most 128 characters long.</i></p>")
+    );
+}
+
+#[sqlx::test]
+async fn new_password_must_be_hard_to_guess(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let html_page = post_new_password(&app, "password1234").await;
+
+    // Assert
+    assert!(html_page.contains("<p><i>The new password is too easy to guess."));
+}
+
+#[sqlx::test]
+async fn new_password_must_not_be_the_username(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let username = app.test_user.username.clone();
+
+    // Act
+    let html_page = post_new_password(&app, &username).await;
+
+    // Assert
+    assert!(html_page.contains("<p><i>The new password can&#x27;t be your username.</i></p>"));
+}
+
+#[sqlx::test]
+async fn new_password_must_differ_from_the_current_one(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let current_password = app.test_user.password.clone();
+
+    // Act
+    let html_page = post_new_password(&app, &current_password).await;
+
+    // Assert
+    assert!(html_page
+        .contains("<p><i>The new password must be different from the current one.</i></p>"));
+}
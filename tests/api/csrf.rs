@@ -0,0 +1,144 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use sqlx::{Pool, Postgres};
+
+async fn post_without_header(
+    app: &TestApp,
+    path: &str,
+    body: &serde_json::Value,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}{}", &app.address, path))
+        .form(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    })
+}
+
+async fn n_issues(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[sqlx::test]
+async fn admin_forms_embed_the_session_csrf_token(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let csrf_token = app.csrf_token().await;
+
+    // Act
+    let html_page = app.get_publish_newsletter_html().await;
+
+    // Assert
+    assert!(html_page.contains(&format!(
+        r#"<input hidden type="text" name="csrf_token" value="{}">"#,
+        csrf_token
+    )));
+}
+
+#[sqlx::test]
+async fn admin_posts_without_a_csrf_token_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response =
+        post_without_header(&app, "/admin/newsletters", &newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(n_issues(&app).await, 0);
+}
+
+#[sqlx::test]
+async fn admin_posts_with_a_wrong_csrf_token_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let mut body = newsletter_request_body();
+    body["csrf_token"] = "not-the-right-token".into();
+
+    // Act
+    let response = post_without_header(&app, "/admin/newsletters", &body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(n_issues(&app).await, 0);
+}
+
+#[sqlx::test]
+async fn admin_posts_with_the_csrf_token_in_the_form_are_accepted(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let mut body = newsletter_request_body();
+    body["csrf_token"] = app.csrf_token().await.into();
+
+    // Act
+    let response = post_without_header(&app, "/admin/newsletters", &body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert_eq!(n_issues(&app).await, 1);
+}
+
+#[sqlx::test]
+async fn logging_out_requires_a_csrf_token(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = post_without_header(&app, "/admin/logout", &serde_json::json!({})).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn logins_without_a_csrf_token_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+
+    // Act
+    let response = post_without_header(&app, "/login", &body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn a_new_session_gets_a_new_csrf_token(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let old_token = app.csrf_token().await;
+    app.post_logout().await;
+
+    // Act
+    let new_token = app.csrf_token().await;
+
+    // Assert
+    assert_ne!(old_token, new_token);
+}
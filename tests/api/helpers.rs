@@ -4,11 +4,20 @@ use once_cell::sync::Lazy;
 use serde::Serialize;
 use sqlx::{PgPool, Pool, Postgres};
 use std::collections::HashMap;
+use std::sync::Arc;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::startup::Application;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::IssueDeliverySettings;
+use zero2prod::confirmation_email_worker::try_execute_confirmation_task;
+use zero2prod::email_client::EmailSender;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::password_reset_email_worker::try_execute_password_reset_task;
+use zero2prod::personal_data_email_worker::try_execute_personal_data_task;
+use zero2prod::startup::{Application, HmacSecret, Worker};
+use zero2prod::welcome_email_worker::try_execute_welcome_task;
 use zero2prod::{
-    configuration::Settings,
+    configuration::{SessionBackend, Settings},
     telemetry::{get_subscriber, init_subscriber},
 };
 
@@ -35,6 +44,12 @@ pub struct TestApp {
     pub port: u16,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: Arc<dyn EmailSender>,
+    pub issue_delivery: IssueDeliverySettings,
+    pub base_url: String,
+    pub hmac_secret: HmacSecret,
+    pub personal_data_link_ttl: std::time::Duration,
+    pub password_reset_token_ttl: std::time::Duration,
 }
 
 pub struct ConfirmationLinks {
@@ -43,16 +58,192 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.issue_delivery,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_all_pending_confirmation_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_confirmation_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.issue_delivery,
+                &self.base_url,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_all_pending_personal_data_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_personal_data_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.issue_delivery,
+                &self.base_url,
+                self.personal_data_link_ttl,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_all_pending_password_reset_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_password_reset_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.issue_delivery,
+                &self.base_url,
+                self.password_reset_token_ttl,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_all_pending_welcome_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_welcome_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.issue_delivery,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Start the background workers of the application against the test
+    /// database. Tests otherwise drive the workers by hand.
+    pub async fn spawn_workers(&self) -> Vec<Worker> {
+        let mut configuration = Settings::new().expect("Failed to read configuration.");
+        configuration.application.port = 0;
+        configuration.email_client.base_url = self.email_server.uri();
+        configuration.session.backend = SessionBackend::Memory;
+        let application = Application::build(&configuration, Some(self.db_pool.clone()))
+            .await
+            .expect("Failed to build application.");
+        application.spawn_workers()
+    }
+
+    /// The CSRF token of the current session, as embedded in its forms.
+    pub async fn csrf_token(&self) -> String {
+        csrf_token_of(&self.api_client, &self.address).await
+    }
+
+    /// Subscribe, then send the confirmation email the worker would send.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
-        self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+        let response = self
+            .api_client
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        if response.status().is_success() {
+            self.dispatch_all_pending_confirmation_emails().await;
+        }
+        response
+    }
+
+    /// Forget when confirmation emails and personal data links were last sent.
+    pub async fn clear_email_cooldowns(&self) {
+        sqlx::query!("DELETE FROM confirmation_resend_throttle")
+            .execute(&self.db_pool)
+            .await
+            .unwrap();
+    }
+
+    /// Ask for a new confirmation email, then send it as the worker would.
+    pub async fn post_resend_confirmation(&self, body: String) -> reqwest::Response {
+        let response = self
+            .api_client
+            .post(format!(
+                "{}/subscriptions/resend-confirmation",
+                &self.address
+            ))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
             .await
+            .expect("Failed to execute request.");
+        if response.status().is_success() {
+            self.dispatch_all_pending_confirmation_emails().await;
+        }
+        response
+    }
+
+    /// `request` is either `access` or `erasure`.
+    pub async fn post_personal_data_request(
+        &self,
+        email: &str,
+        request: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/subscriptions/personal-data/request",
+                &self.address
+            ))
+            .form(&serde_json::json!({ "email": email, "request": request }))
+            .send()
+            .await
             .expect("Failed to execute request.")
     }
 
+    /// Ask for a personal data link and return the one that was emailed.
+    pub async fn get_personal_data_link(&self, email: &str, request: &str) -> reqwest::Url {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Personal data link")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_personal_data_request(email, request)
+            .await
+            .error_for_status()
+            .unwrap();
+        self.dispatch_all_pending_personal_data_emails().await;
+        let email_request = &self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        self.get_confirmation_links(email_request).html
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body =
             serde_html_form::from_bytes::<HashMap<String, String>>(&email_request.body).unwrap();
@@ -75,11 +266,257 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
-    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+    /// Extract the unsubscribe link from the `List-Unsubscribe` header
+    /// of a newsletter issue sent through the (Mailgun-style) mock server.
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body =
+            serde_html_form::from_bytes::<HashMap<String, String>>(&email_request.body).unwrap();
+        let raw_link = body["h:List-Unsubscribe"]
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_publish_newsletter_html(&self) -> String {
+        self.get_publish_newsletter().await.text().await.unwrap()
+    }
+
+    pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_dead_letters_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/dead_letters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_requeue_dead_letters<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/dead_letters", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_welcome_email_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/welcome_email", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_welcome_email<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
         self.api_client
-            .post(&format!("{}/newsletters", &self.address))
-            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
-            .json(&body)
+            .post(format!("{}/admin/welcome_email", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_users_html(&self) -> String {
+        self.get_users().await.text().await.unwrap()
+    }
+
+    pub async fn post_invite_user<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/users", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `query` is appended as is, e.g. `status=confirmed&page=2`.
+    pub async fn get_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscribers_html(&self, query: &str) -> String {
+        self.get_subscribers(query).await.text().await.unwrap()
+    }
+
+    pub async fn get_subscriber_html(&self, subscriber_id: Uuid) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `action` is one of `confirm`, `unsubscribe`, `edit` or `delete`.
+    pub async fn post_subscriber_action<Body>(
+        &self,
+        subscriber_id: Uuid,
+        action: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                &self.address, subscriber_id, action
+            ))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `query` is appended as is, e.g. `format=ndjson&status=confirmed`.
+    pub async fn get_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_import_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers/import", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Upload `csv` as a multipart form, the way a browser would.
+    pub async fn post_import(&self, mode: &str, csv: &str) -> reqwest::Response {
+        let csrf_token = self.csrf_token().await;
+        self.post_import_with_csrf_token(mode, csv, &csrf_token)
+            .await
+    }
+
+    pub async fn post_import_with_csrf_token(
+        &self,
+        mode: &str,
+        csv: &str,
+        csrf_token: &str,
+    ) -> reqwest::Response {
+        let boundary = "zero2prod-import-boundary";
+        let body = format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"csrf_token\"\r\n\r\n\
+            {csrf_token}\r\n\
+            --{boundary}\r\n\
+            Content-Disposition: form-data; name=\"mode\"\r\n\r\n\
+            {mode}\r\n\
+            --{boundary}\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"subscribers.csv\"\r\n\
+            Content-Type: text/csv\r\n\r\n\
+            {csv}\r\n\
+            --{boundary}--\r\n"
+        );
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_import_report(&self, import_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/import/{}/report",
+                &self.address, import_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `action` is one of `role`, `status` or `delete`.
+    pub async fn post_user_action<Body>(
+        &self,
+        user_id: Uuid,
+        action: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/users/{}/{}",
+                &self.address, user_id, action
+            ))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -90,7 +527,8 @@ impl TestApp {
         Body: Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
             // This `reqwest` method makes sure that the body is URL-encoded
             // and the `Content-Type` header is set accordingly.
             .form(body)
@@ -101,7 +539,7 @@ impl TestApp {
 
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -112,7 +550,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -124,7 +562,7 @@ impl TestApp {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -135,7 +573,8 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
             .form(body)
             .send()
             .await
@@ -146,9 +585,286 @@ impl TestApp {
         self.get_change_password().await.text().await.unwrap()
     }
 
+    pub async fn post_password_reset_request(&self, email: &str) -> reqwest::Response {
+        let response = self
+            .api_client
+            .post(format!("{}/password_reset", &self.address))
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        self.dispatch_all_pending_password_reset_emails().await;
+        response
+    }
+
+    pub async fn get_password_reset_request_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/password_reset", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_password_reset<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/password_reset/confirm", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_email_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/email", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_change_email(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/email", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_lockouts(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/lockouts", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_lockouts_html(&self) -> String {
+        self.get_lockouts().await.text().await.unwrap()
+    }
+
+    pub async fn post_clear_lockout(&self, throttle_key: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/lockouts", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(&serde_json::json!({ "throttle_key": throttle_key }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_api_tokens(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/api_tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.get_api_tokens().await.text().await.unwrap()
+    }
+
+    /// `body` is sent as is, since checkboxes repeat the `scope` key.
+    pub async fn post_create_api_token(&self, body: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/api_tokens", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_revoke_api_token(&self, api_token_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/api_tokens/{}/revoke",
+                &self.address, api_token_id
+            ))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Create a token with the publishing scope for the logged in user.
+    pub async fn create_api_token(&self) -> String {
+        let html_page = self
+            .post_create_api_token("name=ci&scope=newsletters%3Apublish")
+            .await
+            .text()
+            .await
+            .unwrap();
+        extract_between(&html_page, r#"<code id="api-token">"#, "</code>")
+            .expect("No API token on the page.")
+    }
+
+    pub async fn post_api_newsletters<Body>(
+        &self,
+        token: Option<&str>,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let mut request = self
+            .api_client
+            .post(format!("{}/api/v1/newsletters", &self.address))
+            .json(body);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await.expect("Failed to execute request.")
+    }
+
+    pub async fn get_sessions(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_sessions_html(&self) -> String {
+        self.get_sessions().await.text().await.unwrap()
+    }
+
+    pub async fn post_revoke_session(&self, session_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/sessions/{}/revoke",
+                &self.address, session_id
+            ))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_revoke_other_sessions(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/sessions/revoke_others", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `query` is appended as is, e.g. `action=login_failed`.
+    pub async fn get_audit(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_audit_html(&self, query: &str) -> String {
+        self.get_audit(query).await.text().await.unwrap()
+    }
+
+    pub async fn get_audit_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit/export?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login/2fa", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_login_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/2fa", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_two_factor(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/2fa", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_two_factor_html(&self) -> String {
+        self.get_two_factor().await.text().await.unwrap()
+    }
+
+    pub async fn post_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/2fa", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_disable_two_factor(&self, current_password: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/2fa/disable", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(&serde_json::json!({ "current_password": current_password }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Enroll the logged-in user in two-factor authentication, returning the
+    /// TOTP secret and the recovery codes.
+    ///
+    /// The enrollment is confirmed with the code of the previous time step,
+    /// leaving the current and next ones free to log in with.
+    pub async fn enable_two_factor(&self) -> (String, Vec<String>) {
+        let html_page = self.get_two_factor_html().await;
+        let secret = extract_between(&html_page, r#"<code id="totp-secret">"#, "</code>")
+            .expect("No TOTP secret on the enrollment page.");
+        let html_page = self
+            .post_two_factor(&totp_code(&secret, -1))
+            .await
+            .text()
+            .await
+            .unwrap();
+        let codes_html = extract_between(&html_page, r#"<ul id="recovery-codes">"#, "</ul>")
+            .expect("No recovery codes after the enrollment.");
+        let recovery_codes = codes_html
+            .split("<code>")
+            .skip(1)
+            .map(|c| c.split("</code>").next().unwrap().to_owned())
+            .collect();
+        (secret, recovery_codes)
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -156,6 +872,13 @@ impl TestApp {
 }
 
 pub async fn spawn_app(connection_pool: Pool<Postgres>) -> TestApp {
+    spawn_app_with_session_backend(connection_pool, SessionBackend::Memory).await
+}
+
+pub async fn spawn_app_with_session_backend(
+    connection_pool: Pool<Postgres>,
+    session_backend: SessionBackend,
+) -> TestApp {
     // The first time `initialize` is invoked the code in `TRACING` is executed.
     // All other invocations will instead skip execution.
     Lazy::force(&TRACING);
@@ -165,6 +888,7 @@ pub async fn spawn_app(connection_pool: Pool<Postgres>) -> TestApp {
     let mut configuration = Settings::new().expect("Failed to read configuration.");
     configuration.application.port = 0;
     configuration.email_client.base_url = email_server.uri();
+    configuration.session.backend = session_backend;
 
     let application = Application::build(&configuration, Some(connection_pool.clone()))
         .await
@@ -191,9 +915,29 @@ pub async fn spawn_app(connection_pool: Pool<Postgres>) -> TestApp {
         email_server,
         test_user,
         api_client: client,
+        email_client: configuration.email_client.client(),
+        issue_delivery: configuration.issue_delivery,
+        personal_data_link_ttl: configuration.application.personal_data_link_ttl(),
+        password_reset_token_ttl: configuration.application.password_reset_token_ttl(),
+        base_url: configuration.application.base_url,
+        hmac_secret: HmacSecret(configuration.application.hmac_secret),
     }
 }
 
+/// The CSRF token of the session held by `client`, read from the login form.
+pub async fn csrf_token_of(client: &reqwest::Client, address: &str) -> String {
+    let html_page = client
+        .get(format!("{}/login", address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+    extract_between(&html_page, r#"name="csrf_token" value=""#, "\"")
+        .expect("No CSRF token on the login page.")
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
@@ -203,17 +947,31 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: String,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::generate_with_role("owner")
+    }
+
+    pub fn generate_with_role(role: &str) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role: role.into(),
         }
     }
-    async fn store(&self, pool: &PgPool) {
+    pub async fn login(&self, app: &TestApp) {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password
+        }))
+        .await;
+    }
+
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::new(
             Algorithm::Argon2id,
@@ -225,14 +983,123 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash)
-        VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)",
             self.user_id,
             self.username,
             password_hash,
+            self.role,
         )
         .execute(pool)
         .await
         .expect("Failed to store test user.");
     }
 }
+
+pub async fn publish_newsletter(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    // As if the cooldowns had run out, for tests to subscribe again or resend
+    app.clear_email_cooldowns().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Insert `n` subscribers straight into the database, every other one
+/// confirmed. The i-th one (from 1) subscribed at `base + i * step`.
+pub async fn insert_subscribers(
+    app: &TestApp,
+    n: i32,
+    base: chrono::DateTime<chrono::Utc>,
+    step: chrono::Duration,
+) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT
+            gen_random_uuid(),
+            'reader' || i || '@example.com',
+            'Reader ' || i,
+            $2::timestamptz + i * make_interval(secs => $3),
+            CASE WHEN i % 2 = 0 THEN 'confirmed' ELSE 'pending_confirmation' END
+        FROM generate_series(1, $1) AS i
+        "#,
+        n,
+        base,
+        step.num_seconds() as f64
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// The id of the only subscriber.
+pub async fn subscriber_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+/// The TOTP code `step_offset` time steps away from now.
+pub fn totp_code(secret: &str, step_offset: i64) -> String {
+    let totp = totp_rs::TOTP::new(
+        totp_rs::Algorithm::SHA1,
+        6,
+        1,
+        30,
+        totp_rs::Secret::Encoded(secret.to_owned())
+            .to_bytes()
+            .unwrap(),
+        None,
+        "".into(),
+    )
+    .unwrap();
+    let now = chrono::Utc::now().timestamp();
+    totp.generate((now + step_offset * 30) as u64)
+}
+
+fn extract_between(text: &str, start: &str, end: &str) -> Option<String> {
+    let rest = &text[text.find(start)? + start.len()..];
+    Some(rest[..rest.find(end)?].to_owned())
+}
@@ -1,5 +1,8 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use sqlx::{Pool, Postgres};
+use std::time::Duration;
 
 #[sqlx::test]
 async fn an_error_flash_message_is_set_on_failure(pool: Pool<Postgres>) {
@@ -40,3 +43,73 @@ async fn redirect_to_admin_dashboard_after_login_success(pool: Pool<Postgres>) {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
+
+#[sqlx::test]
+async fn weak_password_hashes_are_upgraded_after_login(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let weak_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(8192, 1, 1, None).unwrap(),
+    )
+    .hash_password(app.test_user.password.as_bytes(), &salt)
+    .unwrap()
+    .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        weak_hash,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.test_user.login(&app).await;
+
+    // Assert - The rehash happens in the background
+    let mut password_hash = stored_password_hash(&app).await;
+    for _ in 0..50 {
+        if password_hash != weak_hash {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        password_hash = stored_password_hash(&app).await;
+    }
+    assert!(password_hash.contains("m=15000,t=2,p=1"));
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[sqlx::test]
+async fn password_hashes_matching_the_policy_are_left_alone(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let password_hash = stored_password_hash(&app).await;
+
+    // Act
+    app.test_user.login(&app).await;
+
+    // Assert
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(stored_password_hash(&app).await, password_hash);
+}
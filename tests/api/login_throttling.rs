@@ -0,0 +1,287 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+use sqlx::{Pool, Postgres};
+
+const THROTTLED: &str = "<p><i>Too many failed login attempts. Please try again later.</i></p>";
+
+async fn post_wrong_password(app: &TestApp, username: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": username,
+        "password": "wrong-password"
+    }))
+    .await
+}
+
+async fn post_right_password(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await
+}
+
+/// Skip the delays between attempts, lockouts stay in place.
+async fn skip_login_delays(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE login_failures SET blocked_until = now() - interval '1 second' WHERE NOT locked_out"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn lock_out_test_user(app: &TestApp) {
+    for _ in 0..5 {
+        skip_login_delays(app).await;
+        post_wrong_password(app, &app.test_user.username).await;
+    }
+}
+
+#[sqlx::test]
+async fn a_failed_login_delays_the_next_attempt(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    post_wrong_password(&app, &app.test_user.username).await;
+
+    // Act
+    let response = post_right_password(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(THROTTLED));
+}
+
+#[sqlx::test]
+async fn delays_grow_with_each_failure(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let username_key = format!("username:{}", app.test_user.username);
+    let delay = || async {
+        sqlx::query!(
+            r#"SELECT EXTRACT(EPOCH FROM blocked_until - last_failure_at)::float8 as "delay!"
+            FROM login_failures WHERE throttle_key = $1"#,
+            username_key
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .delay
+    };
+
+    // Act
+    post_wrong_password(&app, &app.test_user.username).await;
+    let first_delay = delay().await;
+    skip_login_delays(&app).await;
+    post_wrong_password(&app, &app.test_user.username).await;
+    let second_delay = delay().await;
+
+    // Assert
+    assert_eq!(first_delay, 1.0);
+    assert_eq!(second_delay, 2.0);
+}
+
+#[sqlx::test]
+async fn too_many_failures_lock_the_username_out(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    lock_out_test_user(&app).await;
+    skip_login_delays(&app).await;
+
+    // Act
+    let response = post_right_password(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(THROTTLED));
+    let saved = sqlx::query!(
+        "SELECT n_failures, locked_out FROM login_failures WHERE throttle_key = $1",
+        format!("username:{}", app.test_user.username)
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.n_failures, 5);
+    assert!(saved.locked_out);
+}
+
+#[sqlx::test]
+async fn too_many_failures_from_one_ip_lock_it_out_for_every_username(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    for i in 0..20 {
+        skip_login_delays(&app).await;
+        post_wrong_password(&app, &format!("user-{}", i)).await;
+    }
+    skip_login_delays(&app).await;
+
+    // Act
+    let response = post_right_password(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(THROTTLED));
+}
+
+#[sqlx::test]
+async fn forwarded_for_headers_from_untrusted_peers_do_not_escape_the_ip_lockout(
+    pool: Pool<Postgres>,
+) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    for i in 0..20 {
+        skip_login_delays(&app).await;
+        app.api_client
+            .post(format!("{}/login", &app.address))
+            .header("X-CSRF-Token", app.csrf_token().await)
+            .header("X-Forwarded-For", format!("198.51.100.{}", i))
+            .form(&serde_json::json!({
+                "username": format!("user-{}", i),
+                "password": "wrong-password",
+            }))
+            .send()
+            .await
+            .unwrap();
+    }
+    skip_login_delays(&app).await;
+
+    // Act
+    let response = post_right_password(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(THROTTLED));
+}
+
+#[sqlx::test]
+async fn concurrent_guesses_are_throttled_like_sequential_ones(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let responses = futures_util::future::join_all(
+        (0..5).map(|_| post_wrong_password(&app, &app.test_user.username)),
+    )
+    .await;
+
+    // Assert
+    assert!(responses
+        .iter()
+        .all(|response| response.status().as_u16() == 303));
+    let saved = sqlx::query!(
+        "SELECT n_failures FROM login_failures WHERE throttle_key = $1",
+        format!("username:{}", app.test_user.username)
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    // The first guess delays the others, which are refused without being tried
+    assert_eq!(saved.n_failures, 1);
+}
+
+#[sqlx::test]
+async fn a_successful_login_does_not_count_as_a_failure_of_the_ip(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    post_wrong_password(&app, "someone-else").await;
+    skip_login_delays(&app).await;
+
+    // Act
+    let response = post_right_password(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let saved = sqlx::query!(
+        "SELECT n_failures, blocked_until <= now() as \"unblocked!\" FROM login_failures WHERE throttle_key LIKE 'ip:%'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.n_failures, 1);
+    assert!(saved.unblocked);
+}
+
+#[sqlx::test]
+async fn a_successful_login_clears_the_failures_of_the_username(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    post_wrong_password(&app, &app.test_user.username).await;
+    skip_login_delays(&app).await;
+
+    // Act
+    let response = post_right_password(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let n_rows = sqlx::query!(
+        r#"SELECT count(*) as "count!" FROM login_failures WHERE throttle_key = $1"#,
+        format!("username:{}", app.test_user.username)
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_rows, 0);
+}
+
+#[sqlx::test]
+async fn owners_can_see_and_clear_lockouts(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let victim = TestUser::generate_with_role("viewer");
+    victim.store(&app.db_pool).await;
+    for _ in 0..5 {
+        skip_login_delays(&app).await;
+        post_wrong_password(&app, &victim.username).await;
+    }
+    // Failures from the test IP are not the point here
+    sqlx::query!("DELETE FROM login_failures WHERE throttle_key LIKE 'ip:%'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+    let victim_key = format!("username:{}", victim.username);
+
+    // Act - Part 1 - See the lockout
+    let html_page = app.get_lockouts_html().await;
+    assert!(html_page.contains(&victim_key));
+    assert!(html_page.contains("locked out"));
+
+    // Act - Part 2 - Clear it
+    let response = app.post_clear_lockout(&victim_key).await;
+    assert_is_redirect_to(&response, "/admin/lockouts");
+    let html_page = app.get_lockouts_html().await;
+    assert!(html_page.contains(&format!(
+        "<p><i>The failed logins of {} have been cleared.</i></p>",
+        victim_key
+    )));
+    app.post_logout().await;
+
+    // Assert
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &victim.username,
+            "password": &victim.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[sqlx::test]
+async fn only_owners_can_manage_lockouts(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    // Act
+    let response = app.get_lockouts().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.post_clear_lockout("ip:127.0.0.1").await;
+    assert_eq!(response.status().as_u16(), 403);
+}
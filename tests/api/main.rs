@@ -1,8 +1,26 @@
 mod admin_dashboard;
+mod api_tokens;
+mod audit;
 mod change_password;
+mod csrf;
 mod health_check;
 mod helpers;
 mod login;
+mod login_throttling;
 mod newsletter;
+mod password_reset;
+mod personal_data;
+mod session_store;
+mod sessions;
+mod subscriber_export;
+mod subscriber_import;
+mod subscribers;
+mod subscription_cleanup;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_resend_confirmation;
+mod subscriptions_unsubscribe;
+mod two_factor;
+mod users;
+mod welcome_email;
+mod workers;
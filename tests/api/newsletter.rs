@@ -1,6 +1,9 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    publish_newsletter, spawn_app,
+};
 use sqlx::{Pool, Postgres};
-use uuid::Uuid;
+use std::time::Duration;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -9,25 +12,31 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers(pool: Pool<Pos
     // Arrange
     let app = spawn_app(pool).await;
     create_unconfirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
     Mock::given(any())
         .respond_with(ResponseTemplate::new(200))
         // We assert that no request is fired at Postmark!
         .expect(0)
         .mount(&app.email_server)
         .await;
-    // Act
-    let newsletter_request_body = serde_json::json!(
-        {
-            "title": "Newsletter title",
-            "content": {
-                "text": "Newsletter body as plain text",
-                "html": "<p>Newsletter body as HTML</p>",
-            }
-        }
-    );
-    let response = app.post_newsletters(newsletter_request_body).await;
-    // Assert
-    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 1 - Submit newsletter form
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - \
+        emails will go out shortly.</i></p>"
+    ));
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we haven't sent the newsletter email
 }
 
@@ -36,172 +45,264 @@ async fn newsletters_are_delivered_to_confirmed_subscribers(pool: Pool<Postgres>
     // Arrange
     let app = spawn_app(pool).await;
     create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
     Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
         .expect(1)
         .mount(&app.email_server)
         .await;
-    // Act
-    let newsletter_request_body = serde_json::json!(
-        {
-            "title": "Newsletter title",
-            "content": {
-                "text": "Newsletter body as plain text",
-                "html": "<p>Newsletter body as HTML</p>",
-            }
-        }
-    );
-    let response = app.post_newsletters(newsletter_request_body).await;
-    // Assert
-    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 1 - Submit newsletter form
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - \
+        emails will go out shortly.</i></p>"
+    ));
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we have sent the newsletter email
 }
 
 #[sqlx::test]
-async fn newsletters_returns_400_for_invalid_data(pool: Pool<Postgres>) {
+async fn you_must_be_logged_in_to_see_the_newsletter_form(pool: Pool<Postgres>) {
     // Arrange
     let app = spawn_app(pool).await;
-    let test_cases = vec![
-        (
-            serde_json::json!({
-                "content": {
-                    "text": "Newsletter body as plain text",
-                    "html": "<p>Newsletter body as HTML</p>",
-                }
-            }),
-            "missing title",
-        ),
-        (
-            serde_json::json!({"title": "Newsletter!"}),
-            "missing content",
-        ),
-    ];
-
-    for (invalid_body, error_message) in test_cases {
-        let response = app.post_newsletters(invalid_body).await;
-        // Assert
-        assert_eq!(
-            400,
-            response.status().as_u16(),
-            "The API did not fail with 400 Bad Request when the payload was {}.",
-            error_message
-        );
-    }
+
+    // Act
+    let response = app.get_publish_newsletter().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
 }
 
 #[sqlx::test]
-async fn requests_missing_authorization_are_rejected(pool: Pool<Postgres>) {
+async fn you_must_be_logged_in_to_publish_a_newsletter(pool: Pool<Postgres>) {
     // Arrange
     let app = spawn_app(pool).await;
-    let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
-        .json(&serde_json::json!({
-            "title": "Newsletter title",
-            "content": {
-                "text": "Newsletter body as plain text",
-                "html": "<p>Newsletter body as HTML</p>",
-            }
-        }))
-        .send()
-        .await
-        .expect("Failed to execute request.");
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+
     // Assert
-    assert_eq!(401, response.status().as_u16());
-    assert_eq!(
-        r#"Basic realm="publish""#,
-        response.headers()["WWW-Authenticate"]
-    );
+    assert_is_redirect_to(&response, "/login");
 }
 
 #[sqlx::test]
-async fn non_existing_user_is_rejected(pool: Pool<Postgres>) {
+async fn newsletter_creation_is_idempotent(pool: Pool<Postgres>) {
     // Arrange
     let app = spawn_app(pool).await;
-    // Random credentials
-    let username = Uuid::new_v4().to_string();
-    let password = Uuid::new_v4().to_string();
-    let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
-        .basic_auth(username, Some(password))
-        .json(&serde_json::json!({
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Submit newsletter form
+    let newsletter_request_body = serde_json::json!({
         "title": "Newsletter title",
-        "content": {
-        "text": "Newsletter body as plain text",
-        "html": "<p>Newsletter body as HTML</p>",
-        }
-        }))
-        .send()
-        .await
-        .expect("Failed to execute request.");
-    // Assert
-    assert_eq!(401, response.status().as_u16());
-    assert_eq!(
-        r#"Basic realm="publish""#,
-        response.headers()["WWW-Authenticate"]
-    );
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        // We expect the idempotency key as part of the
+        // form data, not as an header
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - \
+        emails will go out shortly.</i></p>"
+    ));
+
+    // Act - Part 3 - Submit newsletter form **again**
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 4 - Follow the redirect
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - \
+        emails will go out shortly.</i></p>"
+    ));
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
 }
 
 #[sqlx::test]
-async fn invalid_password_is_rejected(pool: Pool<Postgres>) {
+async fn concurrent_form_submission_is_handled_gracefully(pool: Pool<Postgres>) {
     // Arrange
     let app = spawn_app(pool).await;
-    let username = &app.test_user.username;
-    // Random password
-    let password = Uuid::new_v4().to_string();
-    assert_ne!(app.test_user.password, password);
-    let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
-        .basic_auth(username, Some(password))
-        .json(&serde_json::json!({
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        // Setting a long delay to ensure that the second request
+        // arrives before the first one completes
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Submit two newsletter forms concurrently
+    let newsletter_request_body = serde_json::json!({
         "title": "Newsletter title",
-        "content": {
-        "text": "Newsletter body as plain text",
-        "html": "<p>Newsletter body as HTML</p>",
-        }
-        }))
-        .send()
-        .await
-        .expect("Failed to execute request.");
-    // Assert
-    assert_eq!(401, response.status().as_u16());
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response1 = app.post_publish_newsletter(&newsletter_request_body);
+    let response2 = app.post_publish_newsletter(&newsletter_request_body);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
     assert_eq!(
-        r#"Basic realm="publish""#,
-        response.headers()["WWW-Authenticate"]
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
     );
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let _mock_guard = Mock::given(path("/email"))
+#[sqlx::test]
+async fn failed_deliveries_are_scheduled_for_a_retry(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+        .respond_with(ResponseTemplate::new(500))
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!(
+        r#"
+        SELECT n_retries, last_error, execute_after > now() as "in_the_future!", dead_lettered_at
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The failed delivery should still be queued.");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.last_error.is_some());
+    assert!(task.in_the_future);
+    assert!(task.dead_lettered_at.is_none());
+}
+
+#[sqlx::test]
+async fn deliveries_are_dead_lettered_after_the_maximum_number_of_attempts(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(app.issue_delivery.max_attempts as u64)
+        .mount(&app.email_server)
         .await;
-    app.post_subscriptions(body.into())
+
+    // Act
+    publish_newsletter(&app).await;
+    for _ in 0..app.issue_delivery.max_attempts {
+        app.dispatch_all_pending_emails().await;
+        // Skip the backoff window
+        sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!("SELECT n_retries, dead_lettered_at FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
         .await
-        .error_for_status()
         .unwrap();
+    assert_eq!(task.n_retries, app.issue_delivery.max_attempts);
+    assert!(task.dead_lettered_at.is_some());
+    let html_page = app.get_dead_letters_html().await;
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+}
 
-    let email_request = &app
-        .email_server
-        .received_requests()
+#[sqlx::test]
+async fn dead_letters_can_be_requeued(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    sqlx::query!("UPDATE issue_delivery_queue SET dead_lettered_at = now(), n_retries = 5")
+        .execute(&app.db_pool)
         .await
-        .unwrap()
-        .pop()
         .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
 
-    app.get_confirmation_links(email_request)
-}
+    // Act - Part 1 - Re-queue every dead letter
+    let response = app.post_requeue_dead_letters(&serde_json::json!({})).await;
+    assert_is_redirect_to(&response, "/admin/dead_letters");
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_dead_letters_html().await;
+    assert!(html_page.contains("<p><i>Re-queued 1 failed deliveries.</i></p>"));
+    assert!(html_page.contains("There are no dead letters."));
 
-    reqwest::get(confirmation_link.html)
+    // Act - Part 3 - Deliver
+    app.dispatch_all_pending_emails().await;
+    let n_queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
         .await
         .unwrap()
-        .error_for_status()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_see_the_dead_letters(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dead_letters", &app.address))
+        .send()
+        .await
         .unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
 }
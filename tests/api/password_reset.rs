@@ -0,0 +1,396 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+const NEW_PASSWORD: &str = "quietly folded paper lanterns";
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        EMAIL,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// Ask for a reset link for the test user and return it.
+async fn request_reset_link(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_password_reset_request(EMAIL).await;
+    assert_is_redirect_to(&response, "/login");
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request).html
+}
+
+fn token_of(link: &reqwest::Url) -> String {
+    link.query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[sqlx::test]
+async fn a_reset_link_lets_the_user_choose_a_new_password(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    set_test_user_email(&app).await;
+
+    // Act - Part 1 - Ask for a link
+    let link = request_reset_link(&app).await;
+    let html_page = app.get_login_html().await;
+    assert!(html_page
+        .contains("If an account uses this email, we have sent it a link to reset the password."));
+
+    // Act - Part 2 - Follow it
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("new_password"));
+
+    // Act - Part 3 - Reset the password
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "token": token_of(&link),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your password has been reset. You can now log in.</i></p>"));
+
+    // Act - Part 4 - Log in with the new password
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[sqlx::test]
+async fn unknown_emails_get_the_same_answer_without_an_email_being_sent(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_password_reset_request(EMAIL).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page
+        .contains("If an account uses this email, we have sent it a link to reset the password."));
+}
+
+#[sqlx::test]
+async fn requests_are_answered_before_the_email_is_sent(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    set_test_user_email(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/password_reset", &app.address))
+        .form(&serde_json::json!({ "email": EMAIL }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let queued = sqlx::query!("SELECT email FROM password_reset_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.email, EMAIL);
+}
+
+#[sqlx::test]
+async fn repeated_requests_are_throttled(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    set_test_user_email(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_password_reset_request(EMAIL).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 1 - The same address again
+    let response = app.post_password_reset_request(EMAIL).await;
+    assert_is_redirect_to(&response, "/password_reset");
+    let html_page = app.get_password_reset_request_html().await;
+    assert!(html_page.contains("Too many password reset requests. Please try again later."));
+
+    // Act - Part 2 - Another address, from the same client
+    let response = app
+        .post_password_reset_request("someone_else@example.com")
+        .await;
+    assert_is_redirect_to(&response, "/password_reset");
+
+    // Assert
+    let throttle_keys = sqlx::query!(
+        "SELECT throttle_key FROM confirmation_resend_throttle WHERE throttle_key LIKE 'password-reset:%'"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(throttle_keys.len(), 2);
+    // The address is only kept hashed
+    assert!(throttle_keys
+        .iter()
+        .all(|r| !r.throttle_key.contains(EMAIL)));
+}
+
+#[sqlx::test]
+async fn reset_tokens_are_stored_hashed(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    set_test_user_email(&app).await;
+
+    // Act
+    let link = request_reset_link(&app).await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(saved.token_hash, token_of(&link));
+}
+
+#[sqlx::test]
+async fn a_reset_link_can_only_be_used_once(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+    let body = serde_json::json!({
+        "token": token_of(&link),
+        "new_password": NEW_PASSWORD,
+        "new_password_check": NEW_PASSWORD,
+    });
+    let response = app.post_password_reset(&body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act
+    let response = app.post_password_reset(&body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password_reset");
+    let html_page = app.get_password_reset_request_html().await;
+    assert!(html_page.contains("<p><i>This password reset link is invalid or has expired.</i></p>"));
+    let response = app.api_client.get(link).send().await.unwrap();
+    assert_is_redirect_to(&response, "/password_reset");
+}
+
+#[sqlx::test]
+async fn expired_reset_links_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_is_redirect_to(&response, "/password_reset");
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "token": token_of(&link),
+            "new_password": NEW_PASSWORD,
+            "new_password_check": NEW_PASSWORD,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password_reset");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[sqlx::test]
+async fn new_passwords_must_match(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+
+    // Act
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "token": token_of(&link),
+            "new_password": NEW_PASSWORD,
+            "new_password_check": "another strong passphrase for tests",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/password_reset/confirm?token={}", token_of(&link)),
+    );
+    let html_page = app
+        .api_client
+        .get(link)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(
+        "<p><i>You entered two different new passwords - the field values must match.</i></p>"
+    ));
+}
+
+#[sqlx::test]
+async fn resetting_the_password_logs_the_user_out_everywhere(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    set_test_user_email(&app).await;
+    app.test_user.login(&app).await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let link = request_reset_link(&app).await;
+
+    // Act - Reset from another browser
+    let other_browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = other_browser
+        .post(format!("{}/password_reset/confirm", &app.address))
+        .form(&serde_json::json!({
+            "token": token_of(&link),
+            "new_password": NEW_PASSWORD,
+            "new_password_check": NEW_PASSWORD,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn users_can_set_their_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - An invalid email
+    let response = app.post_change_email("not-an-email").await;
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = app.get_change_email_html().await;
+    assert!(html_page.contains("<p><i>Please enter a valid email.</i></p>"));
+
+    // Act - Part 2 - A valid one
+    let response = app.post_change_email(EMAIL).await;
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = app.get_change_email_html().await;
+    assert!(html_page.contains("<p><i>Your email has been changed.</i></p>"));
+    assert!(html_page.contains(&format!("Your email is {}.", EMAIL)));
+}
+
+#[sqlx::test]
+async fn two_users_can_not_share_an_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    sqlx::query!(
+        "UPDATE users SET email = $1 WHERE username = 'admin'",
+        EMAIL.to_uppercase()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.post_change_email(EMAIL).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = app.get_change_email_html().await;
+    assert!(html_page.contains("<p><i>This email is already used by another user.</i></p>"));
+}
+
+#[sqlx::test]
+async fn the_new_password_must_follow_the_policy(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+
+    // Act
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "token": token_of(&link),
+            "new_password": "short",
+            "new_password_check": "short",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/password_reset/confirm?token={}", token_of(&link)),
+    );
+    let html_page = app
+        .api_client
+        .get(link)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        html_page.contains("<p><i>The new password must be at least 12 characters long.</i></p>")
+    );
+}
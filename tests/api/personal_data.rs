@@ -0,0 +1,414 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    subscriber_id, TestApp, TestUser,
+};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::personal_data::{
+    address_throttle_keys, personal_data_throttle_key, suppression_hash,
+};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn enqueue_delivery(app: &TestApp) {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, published_at
+        )
+        VALUES ($1, 'Issue #1', 'Text', '<p>Html</p>', now())
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        VALUES ($1, $2)
+        "#,
+        newsletter_issue_id,
+        EMAIL
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn is_suppressed(app: &TestApp, email: &str) -> bool {
+    sqlx::query!(
+        "SELECT email_hash FROM suppressed_emails WHERE email_hash = $1",
+        suppression_hash(email)
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .is_some()
+}
+
+#[sqlx::test]
+async fn requests_for_unknown_addresses_get_the_same_answer_without_an_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_personal_data_request("nobody@example.com", "access")
+        .await;
+    app.dispatch_all_pending_personal_data_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let queued = sqlx::query!("SELECT id FROM personal_data_email_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+    let throttle_keys = sqlx::query!("SELECT throttle_key FROM confirmation_resend_throttle")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(throttle_keys
+        .iter()
+        .all(|r| !r.throttle_key.contains("nobody")));
+}
+
+#[sqlx::test]
+async fn requests_are_answered_before_the_link_is_sent(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    let n_emails = app.email_server.received_requests().await.unwrap().len();
+
+    // Act
+    let response = app.post_personal_data_request(EMAIL, "access").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.email_server.received_requests().await.unwrap().len(),
+        n_emails
+    );
+    let queued = sqlx::query!("SELECT email FROM personal_data_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.email, EMAIL);
+}
+
+#[sqlx::test]
+async fn requests_with_invalid_data_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    for (email, request) in [("not-an-email", "access"), (EMAIL, "everything")] {
+        // Act
+        let response = app.post_personal_data_request(email, request).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[sqlx::test]
+async fn requests_are_throttled(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    app.get_personal_data_link(EMAIL, "access").await;
+
+    // Act
+    let response = app.post_personal_data_request(EMAIL, "erasure").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[sqlx::test]
+async fn subscribers_can_download_their_data(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    enqueue_delivery(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    let link = app.get_personal_data_link(EMAIL, "access").await;
+
+    // Act - Part 1 - Follow the link
+    let html_page = reqwest::get(link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(r#"method="post""#));
+
+    // Act - Part 2 - Confirm
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"].to_str().unwrap(),
+        "application/json"
+    );
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["email"], EMAIL);
+    assert_eq!(data["subscriptions"][0]["id"], subscriber_id.to_string());
+    assert_eq!(data["subscriptions"][0]["status"], "confirmed");
+    assert!(data["subscriptions"][0]["confirmed_at"].is_string());
+    assert_eq!(data["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(data["welcome_email_queue"].as_array().unwrap().len(), 1);
+    assert_eq!(data["issue_deliveries"][0]["title"], "Issue #1");
+    assert_eq!(
+        data["email_throttling"][0]["throttle_key"],
+        personal_data_throttle_key(EMAIL)
+    );
+    let event = sqlx::query!(
+        "SELECT actor_user_id, target FROM audit_events WHERE action = 'subscriber_data_exported'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(event.actor_user_id.is_none());
+    assert_eq!(event.target, Some(subscriber_id.to_string()));
+}
+
+#[sqlx::test]
+async fn subscribers_can_erase_their_data(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    enqueue_delivery(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    let link = app.get_personal_data_link(EMAIL, "erasure").await;
+
+    // Act - Part 1 - Follow the link
+    let response = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(
+        n_subscribers, 1,
+        "Following the link must not erase anything."
+    );
+
+    // Act - Part 2 - Confirm
+    let html_page = reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("has been erased"));
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT count(*) FROM subscriptions) AS "subscriptions!",
+            (SELECT count(*) FROM subscription_tokens) AS "tokens!",
+            (SELECT count(*) FROM issue_delivery_queue) AS "deliveries!",
+            (SELECT count(*) FROM confirmation_resend_throttle
+                WHERE throttle_key = ANY($1)) AS "throttling!"
+        "#,
+        &address_throttle_keys(EMAIL)
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.deliveries, 0);
+    assert_eq!(remaining.throttling, 0);
+    assert!(is_suppressed(&app, EMAIL).await);
+    let event = sqlx::query!("SELECT target FROM audit_events WHERE action = 'subscriber_erased'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.target, Some(subscriber_id.to_string()));
+}
+
+#[sqlx::test]
+async fn tampered_repurposed_or_expired_links_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    let link = app.get_personal_data_link(EMAIL, "access").await;
+    assert!(!link.as_str().contains("ursula"));
+    let mut tampered = link.clone();
+    let token = link.query_pairs().find(|(k, _)| k == "token").unwrap().1;
+    tampered.set_query(Some(&format!("token={}x", token)));
+    let mut for_erasure = link.clone();
+    for_erasure.set_path("/subscriptions/personal-data/erase");
+
+    for link in [tampered, for_erasure, {
+        // Expired, last so that the others are rejected on their own merit
+        sqlx::query!("UPDATE personal_data_links SET expires_at = now() - interval '1 second'")
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+        link
+    }] {
+        // Act
+        let response = reqwest::Client::new()
+            .post(link.clone())
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The link was not rejected: {}",
+            link
+        );
+    }
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[sqlx::test]
+async fn viewers_cannot_download_or_erase_personal_data(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    // Act
+    let download = app
+        .api_client
+        .get(format!(
+            "{}/admin/subscribers/{}/personal-data",
+            &app.address, subscriber_id
+        ))
+        .send()
+        .await
+        .unwrap();
+    let erase = app
+        .post_subscriber_action(subscriber_id, "erase", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_eq!(download.status().as_u16(), 403);
+    assert_eq!(erase.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn admins_can_download_and_erase_personal_data(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+
+    // Act - Part 1 - Download
+    let data: serde_json::Value = app
+        .api_client
+        .get(format!(
+            "{}/admin/subscribers/{}/personal-data",
+            &app.address, subscriber_id
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(data["subscriptions"][0]["email"], EMAIL);
+
+    // Act - Part 2 - Erase
+    let response = app
+        .post_subscriber_action(subscriber_id, "erase", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_subscribers_html("").await;
+    assert!(html_page.contains("The subscriber&#x27;s personal data has been erased."));
+
+    // Assert
+    assert!(is_suppressed(&app, EMAIL).await);
+    let actions: Vec<_> = sqlx::query!(
+        r#"
+        SELECT action FROM audit_events
+        WHERE actor_user_id = $1 AND target = $2
+        ORDER BY occurred_at
+        "#,
+        app.test_user.user_id,
+        subscriber_id.to_string()
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.action)
+    .collect();
+    assert_eq!(
+        actions,
+        vec!["subscriber_data_exported", "subscriber_erased"]
+    );
+}
+
+#[sqlx::test]
+async fn erased_addresses_cannot_be_imported_again(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.post_subscriber_action(subscriber_id, "erase", &serde_json::json!({}))
+        .await;
+
+    // Act
+    let csv = "email,name\nUrsula_Le_Guin@gmail.com,Ursula\nle.guin@example.com,Le Guin\n";
+    app.post_import("confirmed", csv).await;
+
+    // Assert
+    let emails: Vec<_> = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect();
+    assert_eq!(emails, vec!["le.guin@example.com"]);
+    let row =
+        sqlx::query!("SELECT email, outcome FROM subscriber_import_rows WHERE line_number = 2")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(row.outcome, "invalid");
+    assert!(row.email.is_none());
+}
@@ -0,0 +1,102 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with_session_backend};
+use sqlx::{Pool, Postgres};
+use zero2prod::configuration::SessionBackend;
+use zero2prod::session_store::purge_expired_sessions;
+
+async fn count_sessions(pool: &Pool<Postgres>) -> i64 {
+    sqlx::query!(r#"SELECT count(*) as "count!" FROM sessions"#)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[sqlx::test]
+async fn the_postgres_backend_keeps_sessions_in_the_database(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app_with_session_backend(pool, SessionBackend::Postgres).await;
+
+    // Act - Part 1 - Login
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+    assert_eq!(count_sessions(&app.db_pool).await, 1);
+
+    // Act - Part 3 - Logout
+    app.post_logout().await;
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn the_redis_backend_is_still_supported(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app_with_session_backend(pool, SessionBackend::Redis).await;
+
+    // Act
+    app.test_user.login(&app).await;
+
+    // Assert
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+    assert_eq!(count_sessions(&app.db_pool).await, 0);
+}
+
+#[sqlx::test]
+async fn expired_postgres_sessions_are_ignored_and_purged(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app_with_session_backend(pool, SessionBackend::Postgres).await;
+    app.test_user.login(&app).await;
+    sqlx::query!("UPDATE sessions SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - The session is gone
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - The cleanup worker deletes it
+    purge_expired_sessions(&app.db_pool).await.unwrap();
+    let n_expired =
+        sqlx::query!(r#"SELECT count(*) as "count!" FROM sessions WHERE expires_at < now()"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_expired, 0);
+}
+
+#[sqlx::test]
+async fn the_session_cookie_follows_the_configuration(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    let cookie = response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .map(|v| v.to_str().unwrap())
+        .find(|v| v.starts_with("id="))
+        .expect("No session cookie was set");
+    assert!(cookie.contains("SameSite=Lax"), "{}", cookie);
+    assert!(cookie.contains("Secure"), "{}", cookie);
+    assert!(cookie.contains("HttpOnly"), "{}", cookie);
+}
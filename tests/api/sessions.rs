@@ -0,0 +1,190 @@
+use crate::helpers::{assert_is_redirect_to, csrf_token_of, spawn_app, TestApp};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+/// Log the test user in with a separate cookie jar, as if from another device.
+async fn log_in_from_another_browser(app: &TestApp) -> reqwest::Client {
+    let other_browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .user_agent("Other browser/1.0")
+        .build()
+        .unwrap();
+    let response = other_browser
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+            "csrf_token": csrf_token_of(&other_browser, &app.address).await,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    other_browser
+}
+
+async fn get_dashboard(app: &TestApp, browser: &reqwest::Client) -> reqwest::Response {
+    browser
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn session_id_of_other_browser(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT session_id FROM user_sessions WHERE user_agent = 'Other browser/1.0'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .session_id
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_see_your_sessions(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app.get_sessions().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn logging_in_records_a_session(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    log_in_from_another_browser(&app).await;
+
+    // Act
+    let html_page = app.get_sessions_html().await;
+
+    // Assert
+    assert!(html_page.contains("<td>Other browser/1.0</td>"));
+    assert!(html_page.contains("<td>127.0.0.1</td>"));
+    assert!(html_page.contains("This session"));
+    assert_eq!(html_page.matches("/revoke\" method=\"post\">").count(), 1);
+}
+
+#[sqlx::test]
+async fn a_revoked_session_is_logged_out(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let other_browser = log_in_from_another_browser(&app).await;
+    let session_id = session_id_of_other_browser(&app).await;
+
+    // Act
+    let response = app.post_revoke_session(session_id).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>The session has been revoked.</i></p>"));
+    assert!(!html_page.contains("Other browser/1.0"));
+    let response = get_dashboard(&app, &other_browser).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn you_cannot_revoke_someone_elses_session(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    log_in_from_another_browser(&app).await;
+    let session_id = session_id_of_other_browser(&app).await;
+    sqlx::query!(
+        "UPDATE user_sessions SET user_id = (SELECT user_id FROM users WHERE username = 'admin')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    app.post_revoke_session(session_id).await;
+
+    // Assert
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>There is no such active session.</i></p>"));
+    let revoked_at = sqlx::query!(
+        "SELECT revoked_at FROM user_sessions WHERE session_id = $1",
+        session_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .revoked_at;
+    assert!(revoked_at.is_none());
+}
+
+#[sqlx::test]
+async fn logging_out_all_other_sessions_keeps_the_current_one(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let first_browser = log_in_from_another_browser(&app).await;
+    let second_browser = log_in_from_another_browser(&app).await;
+
+    // Act
+    let response = app.post_revoke_other_sessions().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>2 other session(s) have been logged out.</i></p>"));
+    for browser in [first_browser, second_browser] {
+        let response = get_dashboard(&app, &browser).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn changing_the_password_logs_out_the_other_sessions(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let other_browser = log_in_from_another_browser(&app).await;
+    let new_password = "quietly folded paper lanterns";
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": new_password,
+            "new_password_check": new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+    let response = get_dashboard(&app, &other_browser).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn logging_out_revokes_the_session(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    app.post_logout().await;
+
+    // Assert
+    let n_live_sessions =
+        sqlx::query!(r#"SELECT count(*) as "count!" FROM user_sessions WHERE revoked_at IS NULL"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_live_sessions, 0);
+}
@@ -0,0 +1,223 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, insert_subscribers, spawn_app, TestUser,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use sqlx::{Pool, Postgres};
+
+/// Subscribers are inserted a day apart from then on.
+fn base() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 8, 1, 12, 0, 0).unwrap()
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_export_subscribers(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app.get_export("").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn viewers_cannot_export_subscribers(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    // Act
+    let response = app.get_export("").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn subscribers_are_exported_as_csv_by_default(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app.get_export("").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    let saved = sqlx::query!("SELECT id, subscribed_at, confirmed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "id,email,name,status,subscribed_at,confirmed_at".to_owned(),
+            format!(
+                "{},ursula_le_guin@gmail.com,le guin,confirmed,{},{}",
+                saved.id,
+                saved.subscribed_at.to_rfc3339(),
+                saved.confirmed_at.unwrap().to_rfc3339()
+            )
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn subscribers_can_be_exported_as_ndjson(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    insert_subscribers(&app, 2, base(), Duration::days(1)).await;
+
+    // Act
+    let response = app.get_export("format=ndjson").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"].to_str().unwrap(),
+        "application/x-ndjson"
+    );
+    let body = response.text().await.unwrap();
+    let subscribers: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(subscribers.len(), 2);
+    assert_eq!(subscribers[0]["email"], "reader1@example.com");
+    assert_eq!(subscribers[0]["name"], "Reader 1");
+    assert_eq!(subscribers[0]["status"], "pending_confirmation");
+    assert_eq!(subscribers[0]["subscribed_at"], "2024-08-02T12:00:00+00:00");
+    assert!(subscribers[0]["confirmed_at"].is_null());
+    assert_eq!(subscribers[1]["email"], "reader2@example.com");
+}
+
+#[sqlx::test]
+async fn exports_can_be_filtered_by_status_and_date(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    insert_subscribers(&app, 20, base(), Duration::days(1)).await;
+
+    // Act
+    let response = app
+        .get_export("status=confirmed&since=2024-08-05&until=2024-08-11")
+        .await;
+
+    // Assert
+    let body = response.text().await.unwrap();
+    let emails: Vec<_> = body
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').nth(1).unwrap())
+        .collect();
+    assert_eq!(
+        emails,
+        vec![
+            "reader4@example.com",
+            "reader6@example.com",
+            "reader8@example.com",
+            "reader10@example.com"
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn invalid_filters_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    for query in ["format=xml", "status=deleted", "since=yesterday"] {
+        // Act
+        let response = app.get_export(query).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The export did not fail with 400 Bad Request for `{}`.",
+            query
+        );
+    }
+}
+
+#[sqlx::test]
+async fn exported_cells_are_not_evaluated_as_formulas(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES (gen_random_uuid(), '+reader@example.com', '=HYPERLINK(\"http://evil.example\")', now(), 'confirmed')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.get_export("").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    let line = body.lines().nth(1).unwrap();
+    assert!(
+        line.contains(r#",'+reader@example.com,"'=HYPERLINK(""http://evil.example"")",confirmed,"#)
+    );
+}
+
+#[sqlx::test]
+async fn large_lists_are_exported_in_full(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    insert_subscribers(&app, 5000, base(), Duration::days(1)).await;
+
+    // Act
+    let response = app.get_export("").await;
+
+    // Assert
+    let body = response.text().await.unwrap();
+    assert_eq!(body.lines().count(), 5001);
+    assert!(body
+        .lines()
+        .last()
+        .unwrap()
+        .contains("reader5000@example.com"));
+}
+
+#[sqlx::test]
+async fn exports_are_audited(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    app.get_export("format=ndjson&status=confirmed&since=&until=")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let event = sqlx::query!(
+        "SELECT actor_username, details FROM audit_events WHERE action = 'subscribers_exported'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(event.actor_username, Some(app.test_user.username.clone()));
+    assert_eq!(
+        event.details.as_deref(),
+        Some("format=ndjson, status=confirmed")
+    );
+}
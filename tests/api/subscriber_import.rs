@@ -0,0 +1,391 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn last_import_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT import_id FROM subscriber_imports ORDER BY started_at DESC LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .import_id
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_import_subscribers(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers/import", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn viewers_cannot_import_subscribers(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    // Act
+    let response = app
+        .post_import("confirmed", "email,name\nursula@example.com,Ursula\n")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[sqlx::test]
+async fn imports_without_a_csrf_token_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_import_with_csrf_token("confirmed", "email,name\nursula@example.com,Ursula\n", "")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn the_csrf_token_must_be_the_first_field_of_the_upload(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let csrf_token = app.csrf_token().await;
+    let boundary = "zero2prod-import-boundary";
+    let body = format!(
+        "--{boundary}\r\n\
+        Content-Disposition: form-data; name=\"mode\"\r\n\r\n\
+        confirmed\r\n\
+        --{boundary}\r\n\
+        Content-Disposition: form-data; name=\"csrf_token\"\r\n\r\n\
+        {csrf_token}\r\n\
+        --{boundary}--\r\n"
+    );
+
+    // Act - Part 1 - In the query string
+    let in_query = app
+        .api_client
+        .post(format!(
+            "{}/admin/subscribers/import?csrf_token={}",
+            &app.address, csrf_token
+        ))
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .body(body.replacen(&csrf_token, "", 1))
+        .send()
+        .await
+        .unwrap();
+    // Act - Part 2 - After another field
+    let not_first = app
+        .api_client
+        .post(format!("{}/admin/subscribers/import", &app.address))
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(in_query.status().as_u16(), 403);
+    assert_eq!(not_first.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn confirmed_imports_add_valid_rows_and_skip_the_rest(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES (gen_random_uuid(), 'existing@example.com', 'Existing', now(), 'confirmed')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let csv = "\
+email,name,status,subscribed_at
+ursula@example.com,Ursula,,2023-04-01
+le.guin@example.com,Le Guin,unsubscribed,2023-04-02T10:00:00Z
+not-an-email,Someone,,
+existing@example.com,Existing,,
+ursula@example.com,Ursula again,,
+";
+
+    // Act - Part 1 - Upload the file
+    let response = app.post_import("confirmed", csv).await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_import_html().await;
+    assert!(html_page.contains(
+        "The import is complete: 2 subscriber(s) added, 2 duplicate(s) and 1 invalid row(s) skipped."
+    ));
+
+    // Assert
+    let saved = sqlx::query!(
+        r#"
+        SELECT
+            email, status, subscribed_at::date AS "subscribed_at!",
+            confirmed_at IS NOT NULL AS "has_confirmed_at!"
+        FROM subscriptions
+        WHERE email != 'existing@example.com'
+        ORDER BY email
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].email, "le.guin@example.com");
+    assert_eq!(saved[0].status, "unsubscribed");
+    assert_eq!(saved[0].subscribed_at.to_string(), "2023-04-02");
+    assert_eq!(saved[1].email, "ursula@example.com");
+    assert_eq!(saved[1].status, "confirmed");
+    assert_eq!(saved[1].subscribed_at.to_string(), "2023-04-01");
+    assert!(!saved[0].has_confirmed_at);
+    assert!(saved[1].has_confirmed_at);
+    let tokens = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(tokens.is_empty());
+}
+
+#[sqlx::test]
+async fn double_opt_in_imports_send_confirmation_emails(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nursula@example.com,Ursula\nle.guin@example.com,Le Guin\n";
+
+    // Act
+    let response = app.post_import("double_opt_in", csv).await;
+    app.dispatch_all_pending_confirmation_emails().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.status, t.subscription_token
+        FROM subscriptions s
+        JOIN subscription_tokens t ON t.subscriber_id = s.id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert!(saved.iter().all(|s| s.status == "pending_confirmation"));
+}
+
+#[sqlx::test]
+async fn confirmation_emails_are_sent_in_the_background(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\nursula@example.com,Ursula\n";
+
+    // Act - Part 1 - The upload doesn't wait on the email provider
+    let response = app.post_import("double_opt_in", csv).await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    let import_id = last_import_id(&app).await;
+    let report = app.get_import_report(import_id).await.text().await.unwrap();
+    assert!(report.contains("2,ursula@example.com,accepted,A confirmation email will be sent."));
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Act - Part 2 - The worker gives up after its last attempt
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!("UPDATE confirmation_email_queue SET n_retries = 100")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_confirmation_emails().await;
+
+    // Assert
+    let report = app.get_import_report(import_id).await.text().await.unwrap();
+    assert!(
+        report.contains("2,ursula@example.com,accepted,The confirmation email could not be sent.")
+    );
+    let queued = sqlx::query!("SELECT subscription_token FROM confirmation_email_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+}
+
+#[sqlx::test]
+async fn files_without_the_required_columns_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Upload a file without headers
+    let response = app
+        .post_import("confirmed", "ursula@example.com,Ursula\n")
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_import_html().await;
+    assert!(html_page.contains(
+        "The first line of the file must name its columns, including `email` and `name`."
+    ));
+
+    // Assert
+    let imports = sqlx::query!("SELECT import_id FROM subscriber_imports")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(imports.is_empty());
+}
+
+#[sqlx::test]
+async fn the_report_lists_what_happened_to_each_row(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let csv = "\
+email,name
+ursula@example.com,Ursula
+not-an-email,Someone
+ursula@example.com,Ursula again
+";
+    app.post_import("confirmed", csv).await;
+    let import_id = last_import_id(&app).await;
+
+    // Act - Part 1 - The import is listed
+    let html_page = app.get_import_html().await;
+    assert!(html_page.contains(&format!("/admin/subscribers/import/{import_id}/report")));
+
+    // Act - Part 2 - Download its report
+    let response = app.get_import_report(import_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    let report = response.text().await.unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "line,email,outcome,message");
+    assert_eq!(lines[1], "2,ursula@example.com,accepted,");
+    assert!(lines[2].starts_with("3,not-an-email,invalid,"));
+    assert_eq!(
+        lines[3],
+        "4,ursula@example.com,duplicate,The address appears earlier in the file."
+    );
+}
+
+#[sqlx::test]
+async fn imports_are_written_in_batches(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let mut csv = "email,name\n".to_owned();
+    for i in 0..1200 {
+        csv.push_str(&format!("reader{i}@example.com,Reader {i}\n"));
+    }
+
+    // Act
+    app.post_import("confirmed", &csv).await;
+
+    // Assert
+    let import_id = last_import_id(&app).await;
+    let import = sqlx::query!(
+        r#"
+        SELECT n_accepted, n_duplicates, n_invalid, finished_at
+        FROM subscriber_imports
+        WHERE import_id = $1
+        "#,
+        import_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(import.n_accepted, 1200);
+    assert_eq!(import.n_duplicates, 0);
+    assert_eq!(import.n_invalid, 0);
+    assert!(import.finished_at.is_some());
+    let n_rows = sqlx::query!(
+        r#"SELECT COUNT(*) AS "n!" FROM subscriber_import_rows WHERE import_id = $1"#,
+        import_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .n;
+    assert_eq!(n_rows, 1200);
+}
+
+#[sqlx::test]
+async fn imports_are_audited(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    app.post_import("confirmed", "email,name\nursula@example.com,Ursula\n")
+        .await;
+
+    // Assert
+    let import_id = last_import_id(&app).await;
+    let event = sqlx::query!(
+        "SELECT target, details FROM audit_events WHERE action = 'subscribers_imported'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        event.target.as_deref(),
+        Some(import_id.to_string().as_str())
+    );
+    assert_eq!(
+        event.details.as_deref(),
+        Some("1 accepted, 0 duplicate(s), 0 invalid")
+    );
+}
@@ -0,0 +1,296 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    insert_subscribers, spawn_app, subscriber_id, TestUser,
+};
+use chrono::{Duration, Utc};
+use sqlx::{Pool, Postgres};
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_see_subscribers(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app.get_subscribers("").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn subscribers_are_paginated(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    insert_subscribers(&app, 60, Utc::now(), -Duration::minutes(1)).await;
+
+    // Act - Part 1 - The first page
+    let html_page = app.get_subscribers_html("").await;
+    assert!(html_page.contains("60 subscriber(s) found."));
+    assert!(html_page.contains("Page 1 of 2"));
+    assert!(html_page.contains("reader1@example.com"));
+    assert!(!html_page.contains("reader60@example.com"));
+
+    // Act - Part 2 - The second one
+    let html_page = app.get_subscribers_html("page=2").await;
+    assert!(html_page.contains("Page 2 of 2"));
+    assert!(html_page.contains("reader60@example.com"));
+    assert!(!html_page.contains("reader1@example.com<"));
+}
+
+#[sqlx::test]
+async fn subscribers_can_be_searched_and_filtered(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    insert_subscribers(&app, 20, Utc::now(), -Duration::minutes(1)).await;
+
+    // Act
+    let by_email = app.get_subscribers_html("q=reader12%40").await;
+    let by_name = app.get_subscribers_html("q=reader%2013").await;
+    let confirmed = app.get_subscribers_html("status=confirmed").await;
+    let nothing = app.get_subscribers_html("q=%25").await;
+
+    // Assert
+    assert!(by_email.contains("1 subscriber(s) found."));
+    assert!(by_email.contains("reader12@example.com"));
+    assert!(by_name.contains("reader13@example.com"));
+    assert!(confirmed.contains("10 subscriber(s) found."));
+    assert!(!confirmed.contains("pending_confirmation</td>"));
+    // `%` is matched literally
+    assert!(nothing.contains("0 subscriber(s) found."));
+}
+
+#[sqlx::test]
+async fn invalid_status_filters_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_subscribers("status=deleted").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[sqlx::test]
+async fn the_details_page_shows_tokens_and_deliveries(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.test_user.login(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    crate::helpers::publish_newsletter(&app).await;
+
+    // Act
+    let html_page = app.get_subscriber_html(subscriber_id).await;
+
+    // Assert
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("<td>valid</td>"));
+    assert!(html_page.contains("<td>Newsletter title</td>"));
+    assert!(html_page.contains("next attempt at"));
+}
+
+#[sqlx::test]
+async fn viewers_can_browse_but_not_change_subscribers(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    // Act
+    let html_page = app.get_subscriber_html(subscriber_id).await;
+    let response = app
+        .post_subscriber_action(subscriber_id, "delete", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(!html_page.contains("Delete permanently"));
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn editors_can_confirm_a_subscriber_by_hand(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriber_action(subscriber_id, "confirm", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+    let html_page = app.get_subscriber_html(subscriber_id).await;
+    assert!(html_page.contains("The subscriber has been confirmed."));
+    let n_welcome = sqlx::query!(r#"SELECT count(*) as "count!" FROM welcome_email_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_welcome, 1);
+    let action = sqlx::query!("SELECT action FROM audit_events ORDER BY audit_event_id DESC")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .action;
+    assert_eq!(action, "subscriber_confirmed");
+}
+
+#[sqlx::test]
+async fn editors_can_unsubscribe_a_subscriber(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    app.post_subscriber_action(subscriber_id, "unsubscribe", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "unsubscribed");
+    let n_tokens = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(
+        n_tokens, 0,
+        "Unsubscribing must drop the confirmation tokens."
+    );
+}
+
+#[sqlx::test]
+async fn edits_go_through_the_domain_validation(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": "Ursula", "email": "not-an-email"}),
+            "not-an-email is not a valid subscriber email.",
+        ),
+        (
+            serde_json::json!({"name": "", "email": "ursula_le_guin@gmail.com"}),
+            " is not a valid subscriber name.",
+        ),
+    ];
+
+    for (body, message) in test_cases {
+        // Act
+        app.post_subscriber_action(subscriber_id, "edit", &body)
+            .await;
+
+        // Assert
+        let html_page = app.get_subscriber_html(subscriber_id).await;
+        assert!(html_page.contains(message), "{}", html_page);
+    }
+    let name = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .name;
+    assert_eq!(name, "le guin");
+}
+
+#[sqlx::test]
+async fn editors_can_fix_a_name_and_an_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    app.post_subscriber_action(
+        subscriber_id,
+        "edit",
+        &serde_json::json!({"name": "Ursula K. Le Guin", "email": "ursula@example.com"}),
+    )
+    .await;
+
+    // Assert
+    let html_page = app.get_subscriber_html(subscriber_id).await;
+    assert!(html_page.contains("The subscriber has been updated."));
+    let row = sqlx::query!("SELECT name, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(row.name, "Ursula K. Le Guin");
+    assert_eq!(row.email, "ursula@example.com");
+    let details = sqlx::query!("SELECT details FROM audit_events ORDER BY audit_event_id DESC")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .details;
+    assert_eq!(details.as_deref(), Some("Changed: name, email"));
+}
+
+#[sqlx::test]
+async fn emails_already_used_by_another_subscriber_are_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    insert_subscribers(&app, 1, Utc::now(), -Duration::minutes(1)).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    app.post_subscriber_action(
+        subscriber_id,
+        "edit",
+        &serde_json::json!({"name": "le guin", "email": "reader1@example.com"}),
+    )
+    .await;
+
+    // Assert
+    let html_page = app.get_subscriber_html(subscriber_id).await;
+    assert!(html_page.contains("reader1@example.com is already used by another subscriber."));
+}
+
+#[sqlx::test]
+async fn editors_can_delete_a_subscriber(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriber_action(subscriber_id, "delete", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_subscribers_html("").await;
+    assert!(html_page.contains("The subscriber has been deleted."));
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+}
@@ -0,0 +1,112 @@
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+use zero2prod::authentication::purge_user_sessions;
+use zero2prod::subscription_cleanup_worker::purge_stale_subscriptions;
+
+const RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[sqlx::test]
+async fn stale_pending_subscribers_and_their_tokens_are_purged(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '9 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let outcome = purge_stale_subscriptions(&app.db_pool, RETENTION)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(outcome.n_tokens, 1);
+    assert_eq!(outcome.n_subscribers, 1);
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+}
+
+#[sqlx::test]
+async fn recently_expired_tokens_are_kept(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 day'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let outcome = purge_stale_subscriptions(&app.db_pool, RETENTION)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(outcome.n_tokens, 0);
+    assert_eq!(outcome.n_subscribers, 0);
+}
+
+#[sqlx::test]
+async fn confirmed_subscribers_are_never_purged(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '1 year'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 year'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let outcome = purge_stale_subscriptions(&app.db_pool, RETENTION)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(outcome.n_tokens, 1);
+    assert_eq!(outcome.n_subscribers, 0);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[sqlx::test]
+async fn sessions_idle_for_longer_than_the_session_ttl_are_purged(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    sqlx::query!("UPDATE user_sessions SET last_seen_at = now() - interval '25 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    purge_user_sessions(&app.db_pool, SESSION_TTL)
+        .await
+        .unwrap();
+
+    // Assert
+    let n_sessions = sqlx::query!(r#"SELECT count(*) as "count!" FROM user_sessions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_sessions, 1, "Only the recent session must be kept.");
+}
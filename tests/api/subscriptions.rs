@@ -4,7 +4,10 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use zero2prod::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use zero2prod::routes::{get_subscriber_by_email, insert_subscriber};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
 
 #[sqlx::test]
 async fn suscribe_returns_a_200_for_valid_form_data(pool: Pool<Postgres>) {
@@ -146,3 +149,193 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error(pool: Pool<Postgres>
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[sqlx::test]
+async fn subscribing_twice_while_pending_sends_a_new_confirmation_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response1 = app.post_subscriptions(body.into()).await;
+    app.clear_email_cooldowns().await;
+    let response2 = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+    // The second link confirms the subscription
+    reqwest::get(second_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[sqlx::test]
+async fn subscribing_answers_before_the_confirmation_email_is_sent(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let queued = sqlx::query!(
+        r#"
+        SELECT s.email
+        FROM confirmation_email_queue q
+        JOIN subscription_tokens t USING (subscription_token)
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.email, "ursula_le_guin@gmail.com");
+}
+
+#[sqlx::test]
+async fn subscribing_again_within_the_cooldown_does_not_send_another_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+
+    // Act - Part 1 - Another address, from the same IP
+    let same_ip = app
+        .post_subscriptions("name=someone&email=someone_else%40gmail.com".into())
+        .await;
+    // Act - Part 2 - Same address, from another IP
+    sqlx::query!("DELETE FROM confirmation_resend_throttle WHERE throttle_key LIKE 'ip:%'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let same_address = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(same_address.status().as_u16(), 200);
+    assert_eq!(same_ip.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn inserting_an_email_subscribed_in_the_meantime_does_not_fail(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let new_subscriber = NewSubscriber {
+        email: SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap(),
+        name: SubscriberName::parse("le guin".into()).unwrap(),
+    };
+    // Both requests found no subscriber before inserting
+    let mut first = app.db_pool.begin().await.unwrap();
+    let mut second = app.db_pool.begin().await.unwrap();
+    let subscriber_id = insert_subscriber(&mut first, &new_subscriber)
+        .await
+        .unwrap()
+        .unwrap();
+    first.commit().await.unwrap();
+
+    // Act
+    let outcome = insert_subscriber(&mut second, &new_subscriber).await;
+
+    // Assert
+    assert!(outcome.unwrap().is_none());
+    let existing = get_subscriber_by_email(&mut second, &new_subscriber.email)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(existing.id, subscriber_id);
+}
+
+#[sqlx::test]
+async fn subscribing_with_a_confirmed_email_returns_a_200_without_sending_an_email(
+    pool: Pool<Postgres>,
+) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[sqlx::test]
+async fn subscribing_after_unsubscribing_restarts_double_opt_in(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
@@ -4,7 +4,7 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use crate::helpers::{create_unconfirmed_subscriber, spawn_app};
 
 #[sqlx::test]
 async fn confirmations_without_token_are_rejected_with_a_400(pool: Pool<Postgres>) {
@@ -12,7 +12,7 @@ async fn confirmations_without_token_are_rejected_with_a_400(pool: Pool<Postgres
     let app = spawn_app(pool).await;
 
     // Act
-    let response = reqwest::get(&format!("{}/subscriptions/confirm", app.address))
+    let response = reqwest::get(format!("{}/subscriptions/confirm", app.address))
         .await
         .unwrap();
 
@@ -44,6 +44,13 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called(pool: Pool<Postg
     let response = reqwest::get(confirmation_link).await.unwrap();
     // Assert
     assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<title>Subscription confirmed</title>"));
+    assert!(html_page.contains("Thanks for confirming your subscription!"));
 }
 
 #[sqlx::test]
@@ -74,3 +81,200 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber(pool: Pool<Post
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[sqlx::test]
+async fn confirmation_tokens_expire_after_the_configured_ttl(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let saved = sqlx::query!(
+        r#"SELECT extract(epoch FROM expires_at - created_at)::bigint as "ttl_seconds!" FROM subscription_tokens"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved token.");
+    assert_eq!(saved.ttl_seconds, 24 * 60 * 60);
+}
+
+#[sqlx::test]
+async fn expired_confirmation_links_are_rejected_with_a_410(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("This confirmation link has expired."));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[sqlx::test]
+async fn unknown_tokens_are_rejected_with_an_explanatory_page(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=not-a-real-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<title>Invalid confirmation link</title>"));
+    assert!(html_page.contains("This confirmation link is not valid."));
+}
+
+#[sqlx::test]
+async fn an_old_confirmation_link_does_not_resubscribe_an_unsubscribed_subscriber(
+    pool: Pool<Postgres>,
+) {
+    // Arrange - a token left over from before unsubscribing
+    let app = spawn_app(pool).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[sqlx::test]
+async fn following_the_link_again_shows_the_subscription_is_already_confirmed(
+    pool: Pool<Postgres>,
+) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<title>Subscription already confirmed</title>"));
+    assert!(html_page.contains("Your subscription is already confirmed"));
+}
+
+#[sqlx::test]
+async fn an_expired_link_still_shows_the_subscription_is_already_confirmed(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<title>Subscription already confirmed</title>"));
+}
+
+#[sqlx::test]
+async fn confirmation_responses_are_json_when_the_client_asks_for_it(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let client = reqwest::Client::new();
+    let get_json = |url: reqwest::Url| client.get(url).header("Accept", "application/json").send();
+
+    // Act - Part 1 - Confirm
+    let response = get_json(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "confirmed");
+
+    // Act - Part 2 - Confirm again
+    let response = get_json(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "already_confirmed");
+
+    // Act - Part 3 - Expired token, on a subscriber still pending confirmation
+    sqlx::query!("UPDATE subscriptions SET status = 'pending_confirmation'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = get_json(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "expired_token");
+
+    // Act - Part 4 - Unknown token
+    let mut unknown_link = confirmation_links.html;
+    unknown_link.set_query(Some("subscription_token=not-a-real-token"));
+    let response = get_json(unknown_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "unknown_token");
+}
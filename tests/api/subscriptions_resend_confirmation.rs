@@ -0,0 +1,187 @@
+use sqlx::{Pool, Postgres};
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
+
+const EMAIL_BODY: &str = "email=ursula_le_guin%40gmail.com";
+
+#[sqlx::test]
+async fn resending_sends_a_new_working_confirmation_link_to_a_pending_subscriber(
+    pool: Pool<Postgres>,
+) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let first_links = create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_resend_confirmation(EMAIL_BODY.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let new_links = app.get_confirmation_links(email_request);
+    assert_ne!(new_links.html, first_links.html);
+    reqwest::get(new_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[sqlx::test]
+async fn resending_to_an_unknown_or_confirmed_address_returns_200_without_sending_an_email(
+    pool: Pool<Postgres>,
+) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    // Clear the per-IP cooldown between the two requests
+    let clear_throttle = || async {
+        sqlx::query!("DELETE FROM confirmation_resend_throttle")
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    };
+
+    // Act - Part 1 - Confirmed address
+    let response = app.post_resend_confirmation(EMAIL_BODY.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Unknown address
+    clear_throttle().await;
+    let response = app
+        .post_resend_confirmation("email=someone_else%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn resending_returns_a_400_for_an_invalid_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let test_cases = vec![
+        ("email=", "empty email"),
+        ("email=definitely-not-an-email", "invalid email"),
+        ("", "missing email"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_resend_confirmation(body.into()).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+}
+
+#[sqlx::test]
+async fn resending_twice_to_the_same_address_is_throttled(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - First resend goes through
+    let response = app.post_resend_confirmation(EMAIL_BODY.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Second resend, from another IP, with different casing
+    sqlx::query!("DELETE FROM confirmation_resend_throttle WHERE throttle_key LIKE 'ip:%'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app
+        .post_resend_confirmation("email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[sqlx::test]
+async fn resending_to_many_addresses_from_the_same_ip_is_throttled(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=first%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app
+        .post_resend_confirmation("email=second%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    // The rejected request did not use up the cooldown of the second address
+    let n_throttled = sqlx::query!(
+        r#"SELECT count(*) as "count!" FROM confirmation_resend_throttle WHERE throttle_key = 'address:second@gmail.com'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_throttled, 0);
+}
+
+#[sqlx::test]
+async fn an_expired_cooldown_allows_another_resend(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_resend_confirmation(EMAIL_BODY.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    sqlx::query!(
+        "UPDATE confirmation_resend_throttle SET last_sent_at = now() - interval '1 hour'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.post_resend_confirmation(EMAIL_BODY.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -0,0 +1,166 @@
+use crate::helpers::{create_confirmed_subscriber, publish_newsletter, spawn_app};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[sqlx::test]
+async fn newsletter_issues_carry_an_unsubscribe_link(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body = serde_html_form::from_bytes::<HashMap<String, String>>(&email_request.body).unwrap();
+    assert_eq!(
+        body["h:List-Unsubscribe-Post"],
+        "List-Unsubscribe=One-Click"
+    );
+    let unsubscribe_link = body["h:List-Unsubscribe"]
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    assert!(unsubscribe_link.starts_with("http://127.0.0.1/subscriptions/unsubscribe?"));
+    assert!(body["TextBody"].contains(unsubscribe_link));
+    assert!(body["HtmlBody"].contains("Unsubscribe</a>"));
+}
+
+#[sqlx::test]
+async fn following_the_unsubscribe_link_asks_for_confirmation(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+
+    // Act
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Unsubscribe</button>"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[sqlx::test]
+async fn one_click_unsubscribe_unsubscribes_the_subscriber(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+
+    // Act - what a mail client does for RFC 8058 one-click unsubscribe
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[sqlx::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[sqlx::test]
+async fn a_tampered_unsubscribe_link_is_rejected_with_a_400(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let subscriber_id = uuid::Uuid::new_v4();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?subscriber_id={}&tag={}",
+            app.address,
+            subscriber_id,
+            "00".repeat(32)
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
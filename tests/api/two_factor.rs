@@ -0,0 +1,289 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, totp_code};
+use sqlx::{Pool, Postgres};
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_manage_two_factor_authentication(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app.get_two_factor().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn enrollment_requires_a_valid_code(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    app.get_two_factor_html().await;
+
+    // Act - Part 1 - Submit a wrong code
+    let response = app.post_two_factor("000000").await;
+    assert_is_redirect_to(&response, "/admin/2fa");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>The code is invalid. Please try again.</i></p>"));
+    assert!(html_page.contains("Two-factor authentication is disabled."));
+}
+
+#[sqlx::test]
+async fn the_enrollment_page_shows_a_qr_code_and_a_stable_secret(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let first_page = app.get_two_factor_html().await;
+    let second_page = app.get_two_factor_html().await;
+
+    // Assert
+    assert!(first_page.contains("<svg"));
+    assert!(first_page.contains("otpauth://totp/zero2prod:"));
+    assert_eq!(first_page, second_page);
+}
+
+#[sqlx::test]
+async fn enrollment_shows_recovery_codes_that_are_stored_hashed(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let (_, recovery_codes) = app.enable_two_factor().await;
+
+    // Assert
+    assert_eq!(recovery_codes.len(), 10);
+    let stored_hashes: Vec<String> = sqlx::query_scalar!(
+        "SELECT code_hash FROM user_recovery_codes WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(stored_hashes.len(), 10);
+    for code in &recovery_codes {
+        assert!(!stored_hashes.contains(code));
+    }
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+    assert!(html_page.contains("You have 10 unused recovery codes left."));
+}
+
+#[sqlx::test]
+async fn login_requires_a_totp_code_once_two_factor_is_enabled(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let (secret, _) = app.enable_two_factor().await;
+    app.post_logout().await;
+
+    // Act - Part 1 - The password alone is not enough
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login/2fa");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Enter the code
+    let response = app.post_login_two_factor(&totp_code(&secret, 0)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[sqlx::test]
+async fn the_second_step_requires_a_verified_password(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app.post_login_two_factor("123456").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn invalid_codes_are_rejected_and_eventually_reset_the_login(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    app.enable_two_factor().await;
+    app.post_logout().await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+
+    // Act - Part 1 - A wrong code
+    let response = app.post_login_two_factor("not-a-code").await;
+    assert_is_redirect_to(&response, "/login/2fa");
+    let html_page = app.get_login_two_factor_html().await;
+    assert!(html_page.contains("<p><i>The code is invalid.</i></p>"));
+
+    // Act - Part 2 - Too many wrong codes
+    for _ in 0..3 {
+        app.post_login_two_factor("not-a-code").await;
+    }
+    let response = app.post_login_two_factor("not-a-code").await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many invalid codes. Please try again later.</i></p>"));
+    let response = app.post_login_two_factor("not-a-code").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn logging_in_again_does_not_reset_the_second_factor_lockout(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let (secret, _) = app.enable_two_factor().await;
+    app.post_logout().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    for _ in 0..2 {
+        app.post_login(&login_body).await;
+        for _ in 0..3 {
+            app.post_login_two_factor("not-a-code").await;
+        }
+    }
+
+    // Act - A fresh login with a valid code
+    app.post_login(&login_body).await;
+    let response = app.post_login_two_factor(&totp_code(&secret, 0)).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many invalid codes. Please try again later.</i></p>"));
+}
+
+#[sqlx::test]
+async fn a_recovery_code_can_only_be_used_once(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let (_, recovery_codes) = app.enable_two_factor().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+
+    // Act - Part 1 - Use a recovery code
+    app.post_logout().await;
+    app.post_login(&login_body).await;
+    let response = app
+        .post_login_two_factor(&recovery_codes[0].to_uppercase())
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("You have 9 unused recovery codes left."));
+
+    // Act - Part 2 - Use it again
+    app.post_logout().await;
+    app.post_login(&login_body).await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/2fa");
+}
+
+#[sqlx::test]
+async fn a_totp_code_can_not_be_replayed(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let (secret, _) = app.enable_two_factor().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let code = totp_code(&secret, 0);
+    app.post_logout().await;
+    app.post_login(&login_body).await;
+    let response = app.post_login_two_factor(&code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act
+    app.post_logout().await;
+    app.post_login(&login_body).await;
+    let response = app.post_login_two_factor(&code).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/2fa");
+}
+
+#[sqlx::test]
+async fn disabling_two_factor_requires_the_current_password(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    app.enable_two_factor().await;
+
+    // Act - Part 1 - Wrong password
+    let response = app.post_disable_two_factor("wrong-password").await;
+    assert_is_redirect_to(&response, "/admin/2fa");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+
+    // Act - Part 2 - Right password, once the delay after the failure is over
+    sqlx::query!("UPDATE login_failures SET blocked_until = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app.post_disable_two_factor(&app.test_user.password).await;
+    assert_is_redirect_to(&response, "/admin/2fa");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Two-factor authentication has been disabled.</i></p>"));
+
+    // Act - Part 3 - Logging in takes the password only
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[sqlx::test]
+async fn disabling_two_factor_is_throttled_like_logging_in(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    app.enable_two_factor().await;
+    app.post_disable_two_factor("wrong-password").await;
+
+    // Act
+    let response = app.post_disable_two_factor(&app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/2fa");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Too many failed attempts. Please try again later.</i></p>"));
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+    let saved = sqlx::query!(
+        "SELECT n_failures FROM login_failures WHERE throttle_key = $1",
+        format!("username:{}", app.test_user.username)
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.n_failures, 1);
+}
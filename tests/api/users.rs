@@ -0,0 +1,384 @@
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, csrf_token_of, spawn_app, TestUser};
+
+const INITIAL_PASSWORD: &str = "crooked lighthouse hums softly";
+
+async fn store_user_with_role(app: &crate::helpers::TestApp, role: &str) -> TestUser {
+    let user = TestUser::generate_with_role(role);
+    user.store(&app.db_pool).await;
+    user
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_manage_users(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app.get_users().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn viewers_can_neither_publish_nor_manage_users(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let viewer = store_user_with_role(&app, "viewer").await;
+    viewer.login(&app).await;
+
+    // Act
+    let publish_form = app.get_publish_newsletter().await;
+    let publish = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    let users = app.get_users().await;
+
+    // Assert
+    assert_eq!(publish_form.status().as_u16(), 403);
+    assert_eq!(publish.status().as_u16(), 403);
+    assert_eq!(users.status().as_u16(), 403);
+    let n_issues = sqlx::query!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[sqlx::test]
+async fn editors_can_publish_but_not_manage_users(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let editor = store_user_with_role(&app, "editor").await;
+    editor.login(&app).await;
+
+    // Act
+    let publish_form = app.get_publish_newsletter().await;
+    let users = app.get_users().await;
+    let invite = app
+        .post_invite_user(&serde_json::json!({
+            "username": "intruder",
+            "role": "owner",
+            "password": "password",
+            "password_check": "password",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(publish_form.status().as_u16(), 200);
+    assert_eq!(users.status().as_u16(), 403);
+    assert_eq!(invite.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn the_dashboard_only_links_to_what_the_role_allows(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let viewer = store_user_with_role(&app, "viewer").await;
+
+    // Act - Part 1 - Owner
+    app.test_user.login(&app).await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(r#"href="/admin/users""#));
+    assert!(html_page.contains(r#"href="/admin/newsletters""#));
+
+    // Act - Part 2 - Viewer
+    app.post_logout().await;
+    viewer.login(&app).await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("You are signed in as viewer."));
+    assert!(!html_page.contains(r#"href="/admin/users""#));
+    assert!(!html_page.contains(r#"href="/admin/newsletters""#));
+}
+
+#[sqlx::test]
+async fn owners_can_invite_users_who_can_then_log_in(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Invite
+    let response = app
+        .post_invite_user(&serde_json::json!({
+            "username": "new-editor",
+            "role": "editor",
+            "password": INITIAL_PASSWORD,
+            "password_check": INITIAL_PASSWORD,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>new-editor has been invited as editor.</i></p>"));
+    assert!(html_page.contains("<td>new-editor</td>"));
+
+    // Act - Part 3 - Log in as the new user
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "new-editor",
+            "password": INITIAL_PASSWORD,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("You are signed in as editor."));
+}
+
+#[sqlx::test]
+async fn inviting_a_taken_username_is_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_invite_user(&serde_json::json!({
+            "username": &app.test_user.username,
+            "role": "viewer",
+            "password": INITIAL_PASSWORD,
+            "password_check": INITIAL_PASSWORD,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains(&format!(
+        "<p><i>The username {} is already taken.</i></p>",
+        app.test_user.username
+    )));
+}
+
+#[sqlx::test]
+async fn invitations_with_an_invalid_role_or_mismatched_passwords_are_rejected(
+    pool: Pool<Postgres>,
+) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let test_cases = [
+        (
+            serde_json::json!({
+                "username": "someone",
+                "role": "superuser",
+                "password": INITIAL_PASSWORD,
+                "password_check": INITIAL_PASSWORD,
+            }),
+            "Please pick a valid role.",
+        ),
+        (
+            serde_json::json!({
+                "username": "someone",
+                "role": "viewer",
+                "password": INITIAL_PASSWORD,
+                "password_check": "another-password",
+            }),
+            "You entered two different passwords - the field values must match.",
+        ),
+        (
+            serde_json::json!({
+                "username": " ",
+                "role": "viewer",
+                "password": INITIAL_PASSWORD,
+                "password_check": INITIAL_PASSWORD,
+            }),
+            "The username can&#x27;t be empty.",
+        ),
+        (
+            serde_json::json!({
+                "username": "someone",
+                "role": "viewer",
+                "password": "password1234",
+                "password_check": "password1234",
+            }),
+            "password is too easy to guess",
+        ),
+        (
+            serde_json::json!({
+                "username": "<script>someone</script>",
+                "role": "viewer",
+                "password": INITIAL_PASSWORD,
+                "password_check": INITIAL_PASSWORD,
+            }),
+            "Usernames can only contain letters, digits",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        // Act
+        let response = app.post_invite_user(&body).await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/users");
+        let html_page = app.get_users_html().await;
+        assert!(
+            html_page.contains(error_message),
+            "Missing `{}` in the users page",
+            error_message
+        );
+    }
+    let n_users =
+        sqlx::query!(r#"SELECT count(*) as "count!" FROM users WHERE username = 'someone'"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_users, 0);
+}
+
+#[sqlx::test]
+async fn owners_can_change_the_role_of_another_user(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let viewer = store_user_with_role(&app, "viewer").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_user_action(
+            viewer.user_id,
+            "role",
+            &serde_json::json!({"role": "editor"}),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let saved = sqlx::query!("SELECT role FROM users WHERE user_id = $1", viewer.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.role, "editor");
+}
+
+#[sqlx::test]
+async fn deactivated_users_are_logged_out_and_cannot_log_in(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let editor = store_user_with_role(&app, "editor").await;
+    // The editor logs in from their own browser
+    let editor_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = editor_client
+        .post(format!("{}/login", app.address))
+        .form(&serde_json::json!({
+            "username": &editor.username,
+            "password": &editor.password,
+            "csrf_token": csrf_token_of(&editor_client, &app.address).await,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_user_action(
+            editor.user_id,
+            "status",
+            &serde_json::json!({"is_active": "false"}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // Assert - Part 1 - The open session stops working
+    let response = editor_client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert - Part 2 - Logging in again fails
+    let response = editor_client
+        .post(format!("{}/login", app.address))
+        .form(&serde_json::json!({
+            "username": &editor.username,
+            "password": &editor.password,
+            "csrf_token": csrf_token_of(&editor_client, &app.address).await,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn owners_can_delete_another_user(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let editor = store_user_with_role(&app, "editor").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_user_action(editor.user_id, "delete", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>The user has been deleted.</i></p>"));
+    let n_users = sqlx::query!(
+        r#"SELECT count(*) as "count!" FROM users WHERE user_id = $1"#,
+        editor.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_users, 0);
+}
+
+#[sqlx::test]
+async fn owners_cannot_change_their_own_account(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    let user_id = app.test_user.user_id;
+
+    // Act
+    let responses = [
+        app.post_user_action(user_id, "role", &serde_json::json!({"role": "viewer"}))
+            .await,
+        app.post_user_action(
+            user_id,
+            "status",
+            &serde_json::json!({"is_active": "false"}),
+        )
+        .await,
+        app.post_user_action(user_id, "delete", &serde_json::json!({}))
+            .await,
+    ];
+
+    // Assert
+    for response in &responses {
+        assert_is_redirect_to(response, "/admin/users");
+    }
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("You can&#x27;t change your own account from this page."));
+    let saved = sqlx::query!(
+        "SELECT role, is_active FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.role, "owner");
+    assert!(saved.is_active);
+}
@@ -0,0 +1,223 @@
+use sqlx::{Pool, Postgres};
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+};
+
+async fn count_queued_welcome_emails(pool: &Pool<Postgres>) -> i64 {
+    sqlx::query!(r#"SELECT count(*) as "count!" FROM welcome_email_queue"#)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[sqlx::test]
+async fn confirming_a_subscription_enqueues_the_welcome_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    // Act
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert - the email is queued, not sent during the request
+    assert_eq!(count_queued_welcome_emails(&app.db_pool).await, 1);
+}
+
+#[sqlx::test]
+async fn the_welcome_email_is_sent_by_the_worker(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_welcome_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body = String::from_utf8(email_request.body).unwrap();
+    assert!(body.contains("Welcome+to+our+newsletter"));
+    assert!(body.contains("Unsubscribe"));
+    assert_eq!(count_queued_welcome_emails(&app.db_pool).await, 0);
+}
+
+#[sqlx::test]
+async fn confirming_twice_enqueues_a_single_welcome_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("DELETE FROM welcome_email_queue")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(count_queued_welcome_emails(&app.db_pool).await, 0);
+}
+
+#[sqlx::test]
+async fn a_failing_email_provider_does_not_fail_the_confirmation(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    app.dispatch_all_pending_welcome_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let task = sqlx::query!("SELECT n_retries, last_error FROM welcome_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert!(task.last_error.is_some());
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_edit_the_welcome_email(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+
+    // Act
+    let response = app
+        .post_welcome_email(&serde_json::json!({
+            "enabled": "true",
+            "subject": "Hi!",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn the_edited_welcome_email_is_sent_to_new_subscribers(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Edit the template
+    let response = app
+        .post_welcome_email(&serde_json::json!({
+            "enabled": "true",
+            "subject": "Glad to have you",
+            "text_content": "Hello from the team",
+            "html_content": "<p>Hello from the team</p>",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/welcome_email");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_welcome_email_html().await;
+    assert!(html_page.contains("<p><i>The welcome email has been updated.</i></p>"));
+    assert!(html_page.contains(r#"value="Glad to have you""#));
+
+    // Act - Part 3 - A new subscriber confirms
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_welcome_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body = String::from_utf8(email_request.body).unwrap();
+    assert!(body.contains("Glad+to+have+you"));
+}
+
+#[sqlx::test]
+async fn no_welcome_email_is_queued_when_it_is_disabled(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+    // An unchecked checkbox is left out of the form
+    let response = app
+        .post_welcome_email(&serde_json::json!({
+            "subject": "Glad to have you",
+            "text_content": "Hello from the team",
+            "html_content": "<p>Hello from the team</p>",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/welcome_email");
+
+    // Act
+    create_confirmed_subscriber(&app).await;
+
+    // Assert
+    assert_eq!(count_queued_welcome_emails(&app.db_pool).await, 0);
+}
+
+#[sqlx::test]
+async fn an_incomplete_welcome_email_is_rejected(pool: Pool<Postgres>) {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_welcome_email(&serde_json::json!({
+            "enabled": "true",
+            "subject": " ",
+            "text_content": "Hello from the team",
+            "html_content": "<p>Hello from the team</p>",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/welcome_email");
+
+    // Assert
+    let html_page = app.get_welcome_email_html().await;
+    assert!(html_page.contains(
+        "<p><i>The subject and both contents of the welcome email are required.</i></p>"
+    ));
+    assert!(html_page.contains(r#"value="Welcome to our newsletter!""#));
+}
@@ -0,0 +1,62 @@
+use crate::helpers::spawn_app;
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[sqlx::test]
+async fn the_application_starts_its_background_workers(pool: Pool<Postgres>) {
+    // Arrange - a confirmation email waiting in the queue
+    let app = spawn_app(pool).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let workers = app.spawn_workers().await;
+
+    // Assert
+    let names: Vec<_> = workers.iter().map(|(name, _)| *name).collect();
+    assert_eq!(
+        names,
+        vec![
+            "Background worker",
+            "Welcome email worker",
+            "Confirmation email worker",
+            "Personal data email worker",
+            "Password reset email worker",
+            "Subscription cleanup",
+        ]
+    );
+    let mut n_queued = 1;
+    for _ in 0..50 {
+        n_queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM confirmation_email_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+        if n_queued == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(n_queued, 0);
+    for (name, worker) in workers {
+        assert!(!worker.is_finished(), "{} has stopped", name);
+        worker.abort();
+    }
+}